@@ -0,0 +1,431 @@
+// Editor features for scripts: diagnostics, values under the cursor, definitions,
+// completions and document symbols. Locations are byte offsets into the source, the
+// language server converts them to lines and columns.
+
+use crate::{
+    ast::*,
+    checker,
+    compiler::{self, Build, Module, BUILTINS},
+    diagnostics::{Diagnostic, COLOR_SPACES, EVENT_OUTPUTS, SPLINE_MODES},
+    easing::Easing,
+    imports::{self, Sources},
+    parser,
+};
+
+/// Parsed and compiled script
+pub struct Analysis {
+    source: String,
+    /// Parsed script with resolved imports, unless it has syntax errors
+    root: Option<Expr>,
+    sources: Sources,
+    module: Option<Module>,
+    /// Parse or compile error
+    pub errors: Vec<Diagnostic>,
+    /// Unused definitions
+    pub warnings: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hover {
+    /// Markdown text
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompletionKind {
+    Function,
+    Variable,
+    /// Symbol option, e.g. a spline mode
+    Constant,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+}
+
+/// Export of the script
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// Inferred type, if the script compiles
+    pub detail: Option<String>,
+    /// The whole definition
+    pub span: Span,
+    pub name_span: Span,
+}
+
+impl Analysis {
+    /// Analyses a script loaded from `path`. Imports are loaded like in `compile_file`.
+    pub fn new<F>(path: &str, source: &str, mut load: F) -> Self
+    where
+        F: FnMut(&str) -> Result<String, String>,
+    {
+        let mut analysis = Self {
+            source: source.into(),
+            root: None,
+            sources: Sources::new(),
+            module: None,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        };
+        let parsed = match parser::parse(source) {
+            Ok(root) => root,
+            Err(err) => {
+                analysis
+                    .errors
+                    .push(Diagnostic::from_parse_error(&err, source));
+                return analysis;
+            }
+        };
+
+        let mut root = parsed.clone();
+        let result = imports::resolve(&mut root, path, &mut load).and_then(|sources| {
+            analysis.sources = sources.clone();
+            compiler::build_with_imports(root.clone(), sources)
+        });
+        match result {
+            Ok(module) => {
+                analysis.warnings = module.warnings.clone();
+                analysis.module = Some(module);
+            }
+            Err(err) => analysis
+                .errors
+                .push(Diagnostic::from_build_error(&err, &parsed)),
+        }
+        analysis.root = Some(root);
+        analysis
+    }
+
+    /// Type of the expression at `offset` and its value at `time`
+    pub fn hover(&self, offset: usize, time: f64) -> Option<Hover> {
+        let root = self.root.as_ref()?;
+        let expr = innermost(root, offset)?;
+        let ty = checker::infer(root, &self.sources, expr);
+        let text = match compiler::evaluate(root.clone(), self.sources.clone(), expr.clone()) {
+            Ok(Build::NumberList(n)) => format!("{}\n\n`{}`", ty, numbers(&n)),
+            Ok(Build::EnvelopeFn(f)) => {
+                let dimension = ty.dimension().unwrap_or(1);
+                let value = f.get_value(time);
+                format!(
+                    "{}, {} s long\n\n`{}` at {} s",
+                    ty,
+                    number(f.get_duration()),
+                    numbers(&value.0[..dimension.min(value.0.len())]),
+                    number(time)
+                )
+            }
+            _ => return None,
+        };
+        Some(Hover {
+            text,
+            span: expr.span,
+        })
+    }
+
+    /// Location of the definition of the name at `offset`
+    pub fn definition(&self, offset: usize) -> Option<Span> {
+        let root = self.root.as_ref()?;
+        let mut path = Vec::new();
+        ancestors(root, offset, &mut path);
+        let name = match &path.last()?.kind {
+            ExprKind::Symbol(name) => name,
+            ExprKind::List(name, _) if !name.starts_with('.') => name,
+            _ => return None,
+        };
+        path.iter().rev().find_map(|scope| {
+            definitions(scope)
+                .into_iter()
+                .find(|(expr, _)| matches!(&expr.kind, ExprKind::Symbol(n) if n == name))
+                .map(|(expr, _)| expr.span)
+        })
+    }
+
+    /// Names starting with the word before `offset`: definitions visible at the offset,
+    /// builtin functions, easings and symbol options
+    pub fn completions(&self, offset: usize) -> Vec<Completion> {
+        let before = &self.source[..offset.min(self.source.len())];
+        let prefix = before
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+            .map_or(before, |i| &before[i + 1..]);
+
+        let mut completions = Vec::new();
+        if let Some(root) = &self.root {
+            let mut path = Vec::new();
+            ancestors(root, offset, &mut path);
+            if path.is_empty() {
+                path.push(root);
+            }
+            for scope in path.iter().rev() {
+                for (expr, kind) in definitions(scope) {
+                    if let ExprKind::Symbol(name) = &expr.kind {
+                        completions.push(Completion {
+                            label: name.clone(),
+                            kind,
+                        });
+                    }
+                }
+            }
+        }
+        let builtins = BUILTINS
+            .iter()
+            .map(|name| name.to_string())
+            .chain(Easing::names());
+        completions.extend(builtins.map(|label| Completion {
+            label,
+            kind: CompletionKind::Function,
+        }));
+        let options = SPLINE_MODES
+            .iter()
+            .chain(EVENT_OUTPUTS.iter())
+            .chain(COLOR_SPACES.iter());
+        completions.extend(options.map(|label| Completion {
+            label: label.to_string(),
+            kind: CompletionKind::Constant,
+        }));
+
+        let mut seen = std::collections::HashSet::new();
+        completions.retain(|c| c.label.starts_with(prefix) && seen.insert(c.label.clone()));
+        completions.sort_by(|a, b| a.label.cmp(&b.label));
+        completions
+    }
+
+    /// Exports of the script, in source order
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let statements = match self.root.as_ref().map(|root| &root.kind) {
+            Some(ExprKind::List(_, cons)) => cons,
+            _ => return Vec::new(),
+        };
+        statements
+            .iter()
+            .filter_map(|statement| match &statement.kind {
+                ExprKind::List(keyword, cons) if keyword == KW_EXPORT => match cons.first() {
+                    Some(Expr {
+                        kind: ExprKind::Symbol(name),
+                        span,
+                    }) => Some(Symbol {
+                        name: name.clone(),
+                        detail: self
+                            .module
+                            .as_ref()
+                            .and_then(|module| module.signatures.get(name))
+                            .map(|ty| ty.to_string()),
+                        span: statement.span,
+                        name_span: *span,
+                    }),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start <= offset && offset <= span.end
+}
+
+/// Innermost expression at `offset` that has a value
+fn innermost(expr: &Expr, offset: usize) -> Option<&Expr> {
+    if !contains(expr.span, offset) {
+        return None;
+    }
+    let child = match &expr.kind {
+        ExprKind::List(_, cons) => cons.iter().find_map(|e| innermost(e, offset)),
+        _ => None,
+    };
+    if child.is_some() {
+        return child;
+    }
+    match &expr.kind {
+        ExprKind::List(keyword, _) => match keyword.as_str() {
+            KW_ROOT | KW_DEFINE | KW_EXPORT | KW_FUNCTION | KW_PARAMS | KW_MARKER | KW_IMPORT
+            | KW_SCENE => None,
+            _ => Some(expr),
+        },
+        ExprKind::Comment(_) => None,
+        _ => Some(expr),
+    }
+}
+
+/// Expressions from the root to the innermost one at `offset`
+fn ancestors<'a>(expr: &'a Expr, offset: usize, path: &mut Vec<&'a Expr>) {
+    if !contains(expr.span, offset) {
+        return;
+    }
+    path.push(expr);
+    if let ExprKind::List(_, cons) = &expr.kind {
+        if let Some(child) = cons.iter().find(|e| contains(e.span, offset)) {
+            ancestors(child, offset, path);
+        }
+    }
+}
+
+/// Names defined by the statements of a block, or parameters of a function
+fn definitions(expr: &Expr) -> Vec<(&Expr, CompletionKind)> {
+    let (keyword, cons) = match &expr.kind {
+        ExprKind::List(keyword, cons) => (keyword.as_str(), cons),
+        _ => return Vec::new(),
+    };
+    match keyword {
+        KW_ROOT | KW_BLOCK => cons
+            .iter()
+            .flat_map(|statement| match &statement.kind {
+                ExprKind::List(keyword, cons) => match keyword.as_str() {
+                    KW_DEFINE | KW_EXPORT => cons
+                        .first()
+                        .map(|name| (name, CompletionKind::Variable))
+                        .into_iter()
+                        .collect(),
+                    KW_FUNCTION => cons
+                        .first()
+                        .map(|name| (name, CompletionKind::Function))
+                        .into_iter()
+                        .collect(),
+                    KW_MARKER => cons
+                        .iter()
+                        .step_by(2)
+                        .map(|name| (name, CompletionKind::Variable))
+                        .collect(),
+                    _ => Vec::new(),
+                },
+                _ => Vec::new(),
+            })
+            .collect(),
+        KW_FUNCTION => match cons.get(1).map(|params| &params.kind) {
+            Some(ExprKind::List(_, params)) => params
+                .iter()
+                .map(|param| (param, CompletionKind::Variable))
+                .collect(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Number with at most four decimals
+fn number(x: f64) -> String {
+    if !x.is_finite() {
+        return x.to_string();
+    }
+    let text = format!("{:.4}", x);
+    match text.trim_end_matches('0').trim_end_matches('.') {
+        "-0" => "0".into(),
+        text => text.into(),
+    }
+}
+
+fn numbers(values: &[f64]) -> String {
+    match values {
+        [x] => number(*x),
+        _ => {
+            let items: Vec<String> = values.iter().map(|x| number(*x)).collect();
+            format!("[{}]", items.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+fn analyse(source: &str) -> Analysis {
+    Analysis::new("", source, |path| Err(format!("not found: {}", path)))
+}
+
+#[test]
+fn hovering() {
+    let source = "size = [1, 2]\n\
+                  fade(to) = linear(0, to, 2)\n\
+                  out a = fade(4) * 2\n\
+                  out b = hold(size, 1)";
+    let analysis = analyse(source);
+    assert!(analysis.errors.is_empty(), "{:?}", analysis.errors);
+
+    let at = |text: &str| source.find(text).unwrap() + 1;
+    let hover = analysis.hover(at("size ="), 0.0).unwrap();
+    assert_eq!(hover.text, "a list of 2 numbers\n\n`[1, 2]`");
+    assert_eq!(hover.span, Span { start: 0, end: 4 });
+
+    let hover = analysis.hover(at("fade(4)"), 1.0).unwrap();
+    assert_eq!(hover.text, "an envelope, 2 s long\n\n`2` at 1 s");
+    let hover = analysis.hover(at("* 2"), 1.0).unwrap();
+    assert_eq!(hover.text, "an envelope, 2 s long\n\n`4` at 1 s");
+    let hover = analysis.hover(at("b ="), 0.5).unwrap();
+    assert_eq!(
+        hover.text,
+        "an envelope of 2 components, 1 s long\n\n`[1, 2]` at 0.5 s"
+    );
+
+    // Parameters have no value outside a call
+    assert_eq!(analysis.hover(at("to, 2"), 0.0), None);
+    assert_eq!(analysis.hover(source.len() + 10, 0.0), None);
+}
+
+#[test]
+fn definitions_and_symbols() {
+    let source = "size = 2\n\
+                  marker drop = 4\n\
+                  f(size) = hold(size, 1)\n\
+                  out a = at(drop, f(size))\n\
+                  out b = { size = 3\nhold(size, 1) }";
+    let analysis = analyse(source);
+    assert!(analysis.errors.is_empty(), "{:?}", analysis.errors);
+
+    let position = |text: &str| source.find(text).unwrap();
+    let definition = |text: &str| {
+        analysis
+            .definition(position(text))
+            .map(|span| &source[span.start..span.end + 4])
+    };
+    assert_eq!(definition("drop, f"), Some("drop = 4"));
+    assert_eq!(definition("f(size))"), Some("f(siz"));
+    assert_eq!(definition("size))"), Some("size = 2"));
+    assert_eq!(definition("size, 1)\nout"), Some("size) = "));
+    assert_eq!(definition("size, 1) }"), Some("size = 3"));
+    assert_eq!(definition("hold"), None);
+
+    let names: Vec<(String, Option<String>)> = analysis
+        .symbols()
+        .into_iter()
+        .map(|symbol| (symbol.name, symbol.detail))
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("a".into(), Some("an envelope".into())),
+            ("b".into(), Some("an envelope".into()))
+        ]
+    );
+}
+
+#[test]
+fn completing() {
+    let source = "length = 2\nlerp(x) = x\nout a = l";
+    let analysis = analyse(source);
+    let labels: Vec<(String, CompletionKind)> = analysis
+        .completions(source.len())
+        .into_iter()
+        .map(|c| (c.label, c.kind))
+        .collect();
+    assert_eq!(
+        labels,
+        vec![
+            ("length".into(), CompletionKind::Variable),
+            ("lerp".into(), CompletionKind::Function),
+            ("linear".into(), CompletionKind::Function),
+            ("linear_color".into(), CompletionKind::Function),
+            ("linear_rgb".into(), CompletionKind::Constant),
+            ("loop".into(), CompletionKind::Function),
+        ]
+    );
+    assert!(analysis.errors[0].message.contains("`l`"));
+
+    let analysis = analyse("out a = linear(0, 1, 1, ease_in_cu");
+    assert_eq!(analysis.errors.len(), 1);
+    let labels: Vec<String> = analysis
+        .completions(34)
+        .into_iter()
+        .map(|c| c.label)
+        .collect();
+    assert_eq!(labels, vec!["ease_in_cubic"]);
+}
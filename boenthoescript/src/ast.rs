@@ -1,36 +1,96 @@
 pub const KW_ROOT: &str = ".root";
 pub const KW_DEFINE: &str = ".define";
+pub const KW_FUNCTION: &str = ".function";
+pub const KW_PARAMS: &str = ".params";
 pub const KW_BLOCK: &str = ".block";
 pub const KW_ARRAY: &str = ".array";
 pub const KW_EXPORT: &str = ".export";
+pub const KW_MARKER: &str = ".marker";
+pub const KW_BEATS: &str = ".beats";
+pub const KW_BARS: &str = ".bars";
+pub const KW_IMPORT: &str = ".import";
+pub const KW_SCENE: &str = ".scene";
+
+pub const OP_ADD: &str = ".add";
+pub const OP_SUB: &str = ".sub";
+pub const OP_MUL: &str = ".mul";
+pub const OP_DIV: &str = ".div";
+pub const OP_NEG: &str = ".neg";
+
+/// Byte range of an expression in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Expressions created by the compiler itself have no location
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expr {
+pub enum ExprKind {
     Symbol(String),
     NumberList(Vec<f64>),
     List(String, Vec<Expr>),
     Comment(String),
 }
 
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+// Spans are ignored in comparisons, so that parsed trees can be compared to constructed ones
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl From<ExprKind> for Expr {
+    fn from(kind: ExprKind) -> Self {
+        Expr {
+            kind,
+            span: Span::default(),
+        }
+    }
+}
+
 impl From<&str> for Expr {
     fn from(key: &str) -> Self {
-        Expr::Symbol(String::from(key))
+        ExprKind::Symbol(String::from(key)).into()
     }
 }
 
 impl From<f64> for Expr {
     fn from(n: f64) -> Self {
-        Expr::NumberList(vec![n])
+        ExprKind::NumberList(vec![n]).into()
     }
 }
 
 impl Expr {
     pub fn list(keyword: &str, cons: Vec<Expr>) -> Self {
-        Self::List(String::from(keyword), cons)
+        ExprKind::List(String::from(keyword), cons).into()
     }
-}
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Export {
-    name: String,
+    /// Binary operation, spanning over both operands
+    pub fn binary(keyword: &str, a: Expr, b: Expr) -> Self {
+        let span = Span {
+            start: a.span.start,
+            end: b.span.end,
+        };
+        Self::list(keyword, vec![a, b]).with_span(span.start, span.end)
+    }
+
+    pub fn with_span(self, start: usize, end: usize) -> Self {
+        Self {
+            span: Span { start, end },
+            ..self
+        }
+    }
 }
@@ -0,0 +1,307 @@
+// Language server for scripts. Talks LSP over stdin and stdout and supports diagnostics,
+// hovering values, going to definitions, completing names and listing exports.
+//
+// The time used for hover values can be set with the `previewTime` initialization option,
+// or with the `boenthoescript.previewTime` setting.
+
+use boenthoescript::analysis::{Analysis, CompletionKind};
+use boenthoescript::Diagnostic;
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeConfiguration, DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+        Notification as _, PublishDiagnostics,
+    },
+    request::{Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _},
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, DiagnosticSeverity,
+    DocumentSymbol, DocumentSymbolParams, GotoDefinitionParams, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, Position,
+    PublishDiagnosticsParams, Range, ServerCapabilities, SymbolKind, TextDocumentSyncCapability,
+    TextDocumentSyncKind, Url,
+};
+use serde_json::Value;
+use std::{collections::HashMap, error::Error, fs};
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Sync + Send>>;
+
+/// Open documents and the preview time
+struct Server {
+    documents: HashMap<Url, String>,
+    time: f64,
+}
+
+fn main() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    let params = connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut server = Server {
+        documents: HashMap::new(),
+        time: preview_time(&params["initializationOptions"]).unwrap_or(0.0),
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let response = server.request(request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                for diagnostics in server.notification(notification)? {
+                    connection
+                        .sender
+                        .send(Message::Notification(Notification::new(
+                            PublishDiagnostics::METHOD.into(),
+                            diagnostics,
+                        )))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    // The writer thread stops when the connection is dropped
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+fn preview_time(settings: &Value) -> Option<f64> {
+    settings["previewTime"].as_f64()
+}
+
+impl Server {
+    /// Updates the open documents. Returns diagnostics of all of them, because a change
+    /// may break scripts importing the changed one.
+    fn notification(
+        &mut self,
+        notification: Notification,
+    ) -> Result<Vec<PublishDiagnosticsParams>> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                self.documents
+                    .insert(params.text_document.uri, params.text_document.text);
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(params.text_document.uri, change.text);
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                return Ok(vec![PublishDiagnosticsParams::new(
+                    params.text_document.uri,
+                    Vec::new(),
+                    None,
+                )]);
+            }
+            DidChangeConfiguration::METHOD => {
+                let params: lsp_types::DidChangeConfigurationParams =
+                    serde_json::from_value(notification.params)?;
+                if let Some(time) = preview_time(&params.settings["boenthoescript"]) {
+                    self.time = time;
+                }
+                return Ok(Vec::new());
+            }
+            _ => return Ok(Vec::new()),
+        }
+        Ok(self
+            .documents
+            .iter()
+            .map(|(uri, source)| {
+                let analysis = self.analyse(uri, source);
+                let errors = analysis
+                    .errors
+                    .iter()
+                    .map(|d| (d, DiagnosticSeverity::ERROR));
+                let warnings = analysis
+                    .warnings
+                    .iter()
+                    .map(|d| (d, DiagnosticSeverity::WARNING));
+                let diagnostics = errors
+                    .chain(warnings)
+                    .map(|(diagnostic, severity)| lsp_diagnostic(source, diagnostic, severity))
+                    .collect();
+                PublishDiagnosticsParams::new(uri.clone(), diagnostics, None)
+            })
+            .collect())
+    }
+
+    fn request(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        match self.handle(request) {
+            Ok(result) => Response::new_ok(id, result),
+            Err(err) => Response::new_err(
+                id,
+                lsp_server::ErrorCode::InvalidParams as i32,
+                err.to_string(),
+            ),
+        }
+    }
+
+    fn handle(&self, request: Request) -> Result<Value> {
+        match request.method.as_str() {
+            HoverRequest::METHOD => {
+                let params: HoverParams = serde_json::from_value(request.params)?;
+                let position = params.text_document_position_params;
+                let (source, analysis) = self.document(&position.text_document.uri)?;
+                let hover = analysis
+                    .hover(offset(source, position.position), self.time)
+                    .map(|hover| Hover {
+                        contents: HoverContents::Markup(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value: hover.text,
+                        }),
+                        range: Some(range(source, hover.span)),
+                    });
+                Ok(serde_json::to_value(hover)?)
+            }
+            GotoDefinition::METHOD => {
+                let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+                let position = params.text_document_position_params;
+                let uri = position.text_document.uri;
+                let (source, analysis) = self.document(&uri)?;
+                let location = analysis
+                    .definition(offset(source, position.position))
+                    .map(|span| Location::new(uri.clone(), range(source, span)));
+                Ok(serde_json::to_value(location)?)
+            }
+            Completion::METHOD => {
+                let params: CompletionParams = serde_json::from_value(request.params)?;
+                let position = params.text_document_position;
+                let (source, analysis) = self.document(&position.text_document.uri)?;
+                let items: Vec<CompletionItem> = analysis
+                    .completions(offset(source, position.position))
+                    .into_iter()
+                    .map(|completion| CompletionItem {
+                        label: completion.label,
+                        kind: Some(match completion.kind {
+                            CompletionKind::Function => CompletionItemKind::FUNCTION,
+                            CompletionKind::Variable => CompletionItemKind::VARIABLE,
+                            CompletionKind::Constant => CompletionItemKind::CONSTANT,
+                        }),
+                        ..CompletionItem::default()
+                    })
+                    .collect();
+                Ok(serde_json::to_value(items)?)
+            }
+            DocumentSymbolRequest::METHOD => {
+                let params: DocumentSymbolParams = serde_json::from_value(request.params)?;
+                let (source, analysis) = self.document(&params.text_document.uri)?;
+                #[allow(deprecated)]
+                let symbols: Vec<DocumentSymbol> = analysis
+                    .symbols()
+                    .into_iter()
+                    .map(|symbol| DocumentSymbol {
+                        name: symbol.name,
+                        detail: symbol.detail,
+                        kind: SymbolKind::VARIABLE,
+                        tags: None,
+                        deprecated: None,
+                        range: range(source, symbol.span),
+                        selection_range: range(source, symbol.name_span),
+                        children: None,
+                    })
+                    .collect();
+                Ok(serde_json::to_value(symbols)?)
+            }
+            method => Err(format!("unsupported request `{}`", method).into()),
+        }
+    }
+
+    fn document(&self, uri: &Url) -> Result<(&str, Analysis)> {
+        let source = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| format!("{} is not open", uri))?;
+        Ok((source, self.analyse(uri, source)))
+    }
+
+    /// Analyses a document. Imports are read from open documents or from disk.
+    fn analyse(&self, uri: &Url, source: &str) -> Analysis {
+        let path = uri
+            .to_file_path()
+            .map_or_else(|_| uri.path().to_string(), |p| p.display().to_string());
+        Analysis::new(&path, source, |import| {
+            let open = Url::from_file_path(import)
+                .ok()
+                .and_then(|uri| self.documents.get(&uri));
+            match open {
+                Some(source) => Ok(source.clone()),
+                None => fs::read_to_string(import).map_err(|err| format!("{}: {}", import, err)),
+            }
+        })
+    }
+}
+
+fn lsp_diagnostic(
+    source: &str,
+    diagnostic: &Diagnostic,
+    severity: DiagnosticSeverity,
+) -> lsp_types::Diagnostic {
+    let mut message = diagnostic.message.clone();
+    if let Some(suggestion) = &diagnostic.suggestion {
+        message.push_str(&format!(", did you mean `{}`?", suggestion));
+    }
+    if let Some(note) = &diagnostic.note {
+        message.push('\n');
+        message.push_str(note);
+    }
+    let range = diagnostic
+        .span
+        .map_or_else(Range::default, |span| range(source, span));
+    lsp_types::Diagnostic {
+        severity: Some(severity),
+        source: Some("boenthoescript".into()),
+        ..lsp_types::Diagnostic::new_simple(range, message)
+    }
+}
+
+/// Byte offset of a position, whose character is counted in UTF-16 code units
+fn offset(source: &str, position: Position) -> usize {
+    let line_start: usize = source
+        .split_inclusive('\n')
+        .take(position.line as usize)
+        .map(str::len)
+        .sum();
+    let line = source[line_start..].split('\n').next().unwrap_or("");
+    let mut units = 0;
+    for (index, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return line_start + index;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+fn position(source: &str, offset: usize) -> Position {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(
+        before.matches('\n').count() as u32,
+        before[line_start..].encode_utf16().count() as u32,
+    )
+}
+
+fn range(source: &str, span: boenthoescript::Span) -> Range {
+    Range::new(position(source, span.start), position(source, span.end))
+}
@@ -0,0 +1,786 @@
+// Compact binary format for compiled scripts, so that release builds can load them
+// without the parser and the source text. Integers are LEB128 varints, numbers are
+// little-endian f64s and vectors leave out their trailing zero components.
+
+use crate::{
+    color::ColorSpace,
+    easing::Easing,
+    envelope::{EnvelopeFn, EventOutput, SplineKey, MATH_OPS},
+    program::{Node, Program, Segment},
+    timeline::{Marker, Scene, Timeline},
+    types::Type,
+    vector::Vector,
+};
+use std::collections::HashMap;
+
+const MAGIC: &[u8; 4] = b"BOEB";
+const VERSION: u8 = 3;
+
+/// Compiled script in a form that can be stored in the binary format and loaded
+/// without the compiler
+#[derive(Debug, Clone, Default)]
+pub struct Package {
+    pub program: Program,
+    /// Named time markers, ordered by time
+    pub markers: Vec<Marker>,
+    pub timeline: Timeline,
+    /// Inferred types of the exports
+    pub signatures: HashMap<String, Type>,
+}
+
+#[cfg(feature = "compiler")]
+impl From<crate::compiler::Module> for Package {
+    fn from(module: crate::compiler::Module) -> Self {
+        Self {
+            program: module.program,
+            markers: module.markers,
+            timeline: module.timeline,
+            signatures: module.signatures,
+        }
+    }
+}
+
+impl Package {
+    /// Exports as envelopes, evaluated by a shared copy of the program
+    pub fn exports(&self) -> HashMap<String, EnvelopeFn> {
+        self.program.clone().envelopes()
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut w = Writer { bytes: Vec::new() };
+        w.bytes.extend_from_slice(MAGIC);
+        w.bytes.push(VERSION);
+
+        let program = &self.program;
+        w.uint(program.nodes.len() as u64);
+        for (node, duration) in program.nodes.iter().zip(program.durations.iter()) {
+            w.number(*duration);
+            w.node(node)?;
+        }
+        w.uint(program.keys.len() as u64);
+        for key in program.keys.iter() {
+            w.number(key.time);
+            w.vector(&key.value);
+            w.vector(&key.in_tangent);
+            w.vector(&key.out_tangent);
+        }
+        w.uint(program.segments.len() as u64);
+        for segment in program.segments.iter() {
+            w.number(segment.end);
+            w.uint(segment.node as u64);
+        }
+        w.uint(program.choices.len() as u64);
+        for choice in program.choices.iter() {
+            w.uint(*choice as u64);
+        }
+        w.uint(program.operands.len() as u64);
+        for operand in program.operands.iter() {
+            w.uint(*operand as u64);
+        }
+        w.uint(program.events.len() as u64);
+        for (time, weight) in program.events.iter() {
+            w.number(*time);
+            w.number(*weight);
+        }
+        w.uint(program.outputs.len() as u64);
+        for (name, node) in program.outputs.iter() {
+            w.string(name);
+            w.uint(*node as u64);
+        }
+
+        w.uint(self.markers.len() as u64);
+        for marker in self.markers.iter() {
+            w.string(&marker.name);
+            w.number(marker.time);
+        }
+        w.uint(self.timeline.scenes().len() as u64);
+        for scene in self.timeline.scenes().iter() {
+            w.string(&scene.name);
+            w.number(scene.start);
+            w.number(scene.end);
+        }
+        let mut signatures: Vec<(&String, &Type)> = self.signatures.iter().collect();
+        signatures.sort_by(|a, b| a.0.cmp(b.0));
+        w.uint(signatures.len() as u64);
+        for (name, ty) in signatures {
+            w.string(name);
+            match ty {
+                Type::Numbers(n) => {
+                    w.bytes.push(0);
+                    w.uint(*n as u64);
+                }
+                Type::Envelope(n) => {
+                    w.bytes.push(1);
+                    w.uint(*n as u64);
+                }
+                Type::Symbol(s) => {
+                    w.bytes.push(2);
+                    w.string(s);
+                }
+                Type::Unknown => w.bytes.push(3),
+            }
+        }
+        Ok(w.bytes)
+    }
+
+    /// Loads a script written with `to_bytes`. The program is validated, so that
+    /// evaluating a corrupted file cannot index out of bounds.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut r = Reader { bytes, position: 0 };
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err("not a compiled script".into());
+        }
+        let version = r.byte()?;
+        if version != VERSION {
+            return Err(format!("unsupported version {}", version));
+        }
+
+        let mut program = Program::default();
+        for _ in 0..r.count()? {
+            let duration = r.number()?;
+            let node = r.node()?;
+            program.nodes.push(node);
+            program.durations.push(duration);
+        }
+        for _ in 0..r.count()? {
+            program.keys.push(SplineKey {
+                time: r.number()?,
+                value: r.vector()?,
+                in_tangent: r.vector()?,
+                out_tangent: r.vector()?,
+            });
+        }
+        for _ in 0..r.count()? {
+            program.segments.push(Segment {
+                end: r.number()?,
+                node: r.index()?,
+            });
+        }
+        for _ in 0..r.count()? {
+            program.choices.push(r.index()?);
+        }
+        for _ in 0..r.count()? {
+            program.operands.push(r.index()?);
+        }
+        for _ in 0..r.count()? {
+            program.events.push((r.number()?, r.number()?));
+        }
+        for _ in 0..r.count()? {
+            program.outputs.push((r.string()?, r.index()?));
+        }
+        program.outputs.sort_by(|a, b| a.0.cmp(&b.0));
+        validate(&program)?;
+
+        let mut markers = Vec::new();
+        for _ in 0..r.count()? {
+            markers.push(Marker {
+                name: r.string()?,
+                time: r.number()?,
+            });
+        }
+        let mut scenes = Vec::new();
+        for _ in 0..r.count()? {
+            scenes.push(Scene {
+                name: r.string()?,
+                start: r.number()?,
+                end: r.number()?,
+            });
+        }
+        let mut signatures = HashMap::new();
+        for _ in 0..r.count()? {
+            let name = r.string()?;
+            let ty = match r.byte()? {
+                0 => Type::Numbers(r.uint()? as usize),
+                1 => Type::Envelope(r.uint()? as usize),
+                2 => Type::Symbol(r.string()?),
+                3 => Type::Unknown,
+                tag => return Err(format!("unknown type {}", tag)),
+            };
+            signatures.insert(name, ty);
+        }
+        if r.position != bytes.len() {
+            return Err("unexpected data after the script".into());
+        }
+
+        Ok(Self {
+            program,
+            markers,
+            timeline: Timeline::new(scenes),
+            signatures,
+        })
+    }
+}
+
+/// Checks that children are added before their parents and that table ranges are in bounds
+fn validate(program: &Program) -> Result<(), String> {
+    for (index, node) in program.nodes.iter().enumerate() {
+        let mut valid = true;
+        node.clone().map_children(|child| {
+            valid &= (child as usize) < index;
+            child
+        });
+        let in_range = |first: u32, count: u32, len: usize| {
+            (first as usize)
+                .checked_add(count as usize)
+                .is_some_and(|end| end <= len)
+        };
+        valid &= match node {
+            Node::Spline { first, count } => in_range(*first, *count, program.keys.len()),
+            Node::Concat { first, count } => {
+                in_range(*first, *count, program.segments.len())
+                    && program.segments[*first as usize..(first + count) as usize]
+                        .iter()
+                        .all(|segment| (segment.node as usize) < index)
+            }
+            Node::Select { first, count, .. } => {
+                in_range(*first, *count, program.choices.len())
+                    && program.choices[*first as usize..(first + count) as usize]
+                        .iter()
+                        .all(|choice| (*choice as usize) < index)
+            }
+            Node::Apply { first, count, .. } => {
+                in_range(*first, *count, program.operands.len())
+                    && program.operands[*first as usize..(first + count) as usize]
+                        .iter()
+                        .all(|operand| (*operand as usize) < index)
+            }
+            Node::Events { first, count, .. } => in_range(*first, *count, program.events.len()),
+            _ => true,
+        };
+        if !valid {
+            return Err(format!("invalid node {}", index));
+        }
+    }
+    match program
+        .outputs
+        .iter()
+        .find(|(_, node)| *node as usize >= program.nodes.len())
+    {
+        Some((name, _)) => Err(format!("invalid export `{}`", name)),
+        None => Ok(()),
+    }
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn uint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn number(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn vector(&mut self, vector: &Vector) {
+        let len = vector
+            .0
+            .iter()
+            .rposition(|x| x.to_bits() != 0)
+            .map_or(0, |i| i + 1);
+        self.bytes.push(len as u8);
+        for x in vector.0[..len].iter() {
+            self.number(*x);
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.uint(value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Hold { value } => {
+                self.bytes.push(0);
+                self.vector(value);
+            }
+            Node::Linear { duration, a, b } => {
+                self.bytes.push(1);
+                self.number(*duration);
+                self.vector(a);
+                self.vector(b);
+            }
+            Node::Ease {
+                duration,
+                a,
+                b,
+                easing,
+            } => {
+                self.bytes.push(2);
+                self.number(*duration);
+                self.vector(a);
+                self.vector(b);
+                let index = Easing::names()
+                    .iter()
+                    .position(|name| Easing::from_name(name) == Some(*easing))
+                    .unwrap_or(0);
+                self.uint(index as u64);
+            }
+            Node::ColorFade {
+                duration,
+                from,
+                to,
+                space,
+                ..
+            } => {
+                // End points in the colour space are converted again when loading
+                self.bytes.push(3);
+                self.number(*duration);
+                self.vector(from);
+                self.vector(to);
+                self.bytes.push(match space {
+                    ColorSpace::LinearRgb => 0,
+                    ColorSpace::Hsl => 1,
+                    ColorSpace::Oklab => 2,
+                });
+            }
+            Node::Spline { first, count } => {
+                self.bytes.push(4);
+                self.uint(*first as u64);
+                self.uint(*count as u64);
+            }
+            Node::Random {
+                seed,
+                min,
+                range,
+                interval,
+            } => {
+                self.bytes.push(5);
+                self.uint(*seed);
+                self.vector(min);
+                self.vector(range);
+                self.number(*interval);
+            }
+            Node::Noise {
+                seed,
+                frequency,
+                amplitude,
+                octaves,
+            } => {
+                self.bytes.push(6);
+                self.uint(*seed);
+                self.number(*frequency);
+                self.vector(amplitude);
+                self.uint(*octaves as u64);
+            }
+            Node::Concat { first, count } => {
+                self.bytes.push(7);
+                self.uint(*first as u64);
+                self.uint(*count as u64);
+            }
+            Node::Apply { first, count, func } => {
+                let op = func
+                    .op
+                    .ok_or("math functions without a builtin operation cannot be serialized")?;
+                self.bytes.push(8);
+                self.bytes
+                    .push(MATH_OPS.iter().position(|o| *o == op).unwrap_or(0) as u8);
+                self.uint(*first as u64);
+                self.uint(*count as u64);
+            }
+            Node::Delay { offset, node } => self.unary(9, &[*offset], *node),
+            Node::Repeat {
+                duration,
+                period,
+                node,
+            } => self.unary(10, &[*duration, *period], *node),
+            Node::Loop { period, node } => self.unary(11, &[*period], *node),
+            Node::Every { interval, node } => self.unary(12, &[*interval], *node),
+            Node::Speed { factor, node } => self.unary(13, &[*factor], *node),
+            Node::Reverse { duration, node } => self.unary(14, &[*duration], *node),
+            Node::PingPong { period, node } => self.unary(15, &[*period], *node),
+            Node::Clip {
+                start,
+                length,
+                node,
+            } => self.unary(16, &[*start, *length], *node),
+            Node::SampleAt { time, node } => self.unary(17, &[*time], *node),
+            Node::Crossfade { start, fade, a, b } => {
+                self.bytes.push(18);
+                self.number(*start);
+                self.number(*fade);
+                self.uint(*a as u64);
+                self.uint(*b as u64);
+            }
+            Node::Select {
+                index,
+                first,
+                count,
+            } => {
+                self.bytes.push(19);
+                self.uint(*index as u64);
+                self.uint(*first as u64);
+                self.uint(*count as u64);
+            }
+            Node::Decay { half_life } => {
+                self.bytes.push(20);
+                self.number(*half_life);
+            }
+            Node::Events {
+                first,
+                count,
+                period,
+                output,
+            } => {
+                self.bytes.push(21);
+                self.uint(*first as u64);
+                self.uint(*count as u64);
+                self.number(*period);
+                match output {
+                    EventOutput::Shape(node) => {
+                        self.bytes.push(0);
+                        self.uint(*node as u64);
+                    }
+                    EventOutput::Since => self.bytes.push(1),
+                    EventOutput::Index => self.bytes.push(2),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Node with numeric parameters and one child
+    fn unary(&mut self, tag: u8, params: &[f64], node: u32) {
+        self.bytes.push(tag);
+        for param in params.iter() {
+            self.number(*param);
+        }
+        self.uint(node as u64);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or("unexpected end of data")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn uint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("invalid integer".into())
+    }
+
+    fn index(&mut self) -> Result<u32, String> {
+        let value = self.uint()?;
+        if value > u32::MAX as u64 {
+            return Err("invalid index".into());
+        }
+        Ok(value as u32)
+    }
+
+    /// Length of a list. Every item takes at least a byte, which bounds the allocations.
+    fn count(&mut self) -> Result<usize, String> {
+        let count = self.uint()?;
+        if count > (self.bytes.len() - self.position) as u64 {
+            return Err("unexpected end of data".into());
+        }
+        Ok(count as usize)
+    }
+
+    fn number(&mut self) -> Result<f64, String> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    fn numbers<const N: usize>(&mut self) -> Result<[f64; N], String> {
+        let mut numbers = [0.0; N];
+        for x in numbers.iter_mut() {
+            *x = self.number()?;
+        }
+        Ok(numbers)
+    }
+
+    fn vector(&mut self) -> Result<Vector, String> {
+        let len = self.byte()? as usize;
+        let mut vector = Vector::default();
+        match vector.0.get_mut(..len) {
+            Some(components) => {
+                for x in components.iter_mut() {
+                    *x = self.number()?;
+                }
+                Ok(vector)
+            }
+            None => Err(format!("vector of {} components", len)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.count()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "invalid name".into())
+    }
+
+    fn node(&mut self) -> Result<Node, String> {
+        let node = match self.byte()? {
+            0 => Node::Hold {
+                value: self.vector()?,
+            },
+            1 => Node::Linear {
+                duration: self.number()?,
+                a: self.vector()?,
+                b: self.vector()?,
+            },
+            2 => Node::Ease {
+                duration: self.number()?,
+                a: self.vector()?,
+                b: self.vector()?,
+                easing: Easing::names()
+                    .get(self.uint()? as usize)
+                    .and_then(|name| Easing::from_name(name))
+                    .ok_or("unknown easing")?,
+            },
+            3 => {
+                let duration = self.number()?;
+                let from = self.vector()?;
+                let to = self.vector()?;
+                let space = match self.byte()? {
+                    0 => ColorSpace::LinearRgb,
+                    1 => ColorSpace::Hsl,
+                    2 => ColorSpace::Oklab,
+                    _ => return Err("unknown colour space".into()),
+                };
+                let (a, b) = space.fade_points(&from, &to);
+                Node::ColorFade {
+                    duration,
+                    from,
+                    to,
+                    a,
+                    b,
+                    space,
+                }
+            }
+            4 => Node::Spline {
+                first: self.index()?,
+                count: self.index()?,
+            },
+            5 => Node::Random {
+                seed: self.uint()?,
+                min: self.vector()?,
+                range: self.vector()?,
+                interval: self.number()?,
+            },
+            6 => Node::Noise {
+                seed: self.uint()?,
+                frequency: self.number()?,
+                amplitude: self.vector()?,
+                octaves: self.index()?,
+            },
+            7 => Node::Concat {
+                first: self.index()?,
+                count: self.index()?,
+            },
+            8 => {
+                let op = *MATH_OPS
+                    .get(self.byte()? as usize)
+                    .ok_or("unknown math operation")?;
+                let first = self.index()?;
+                let count = self.index()?;
+                if count as usize != op.arity() {
+                    return Err(format!("{:?} with {} operands", op, count));
+                }
+                Node::Apply {
+                    first,
+                    count,
+                    func: op.func(),
+                }
+            }
+            9 => {
+                let [offset] = self.numbers()?;
+                Node::Delay {
+                    offset,
+                    node: self.index()?,
+                }
+            }
+            10 => {
+                let [duration, period] = self.numbers()?;
+                Node::Repeat {
+                    duration,
+                    period,
+                    node: self.index()?,
+                }
+            }
+            11 => {
+                let [period] = self.numbers()?;
+                Node::Loop {
+                    period,
+                    node: self.index()?,
+                }
+            }
+            12 => {
+                let [interval] = self.numbers()?;
+                Node::Every {
+                    interval,
+                    node: self.index()?,
+                }
+            }
+            13 => {
+                let [factor] = self.numbers()?;
+                Node::Speed {
+                    factor,
+                    node: self.index()?,
+                }
+            }
+            14 => {
+                let [duration] = self.numbers()?;
+                Node::Reverse {
+                    duration,
+                    node: self.index()?,
+                }
+            }
+            15 => {
+                let [period] = self.numbers()?;
+                Node::PingPong {
+                    period,
+                    node: self.index()?,
+                }
+            }
+            16 => {
+                let [start, length] = self.numbers()?;
+                Node::Clip {
+                    start,
+                    length,
+                    node: self.index()?,
+                }
+            }
+            17 => {
+                let [time] = self.numbers()?;
+                Node::SampleAt {
+                    time,
+                    node: self.index()?,
+                }
+            }
+            18 => Node::Crossfade {
+                start: self.number()?,
+                fade: self.number()?,
+                a: self.index()?,
+                b: self.index()?,
+            },
+            19 => Node::Select {
+                index: self.index()?,
+                first: self.index()?,
+                count: self.index()?,
+            },
+            20 => Node::Decay {
+                half_life: self.number()?,
+            },
+            21 => Node::Events {
+                first: self.index()?,
+                count: self.index()?,
+                period: self.number()?,
+                output: match self.byte()? {
+                    0 => EventOutput::Shape(self.index()?),
+                    1 => EventOutput::Since,
+                    2 => EventOutput::Index,
+                    _ => return Err("unknown event output".into()),
+                },
+            },
+            tag => return Err(format!("unknown node type {}", tag)),
+        };
+        Ok(node)
+    }
+}
+
+#[cfg(feature = "compiler")]
+#[test]
+fn roundtrip() {
+    let source = "bpm = 120\n\
+         marker drop = 2\n\
+         scene \"intro\" from 0 to drop\n\
+         out a = concat(hold([1, 1, 1], 1), ease_in_cubic([0, 0, 0], [1, 1, 1], 1), linear_color(#ff8800, #2040c0, 1, hsl))\n\
+         out b = spline([[0, 0], [1, 2], [3, 4]]) * clamp(sin(linear(0, 10, 4)), -0.5, 0.5)\n\
+         out c = random(3, 0, 1, 0.25) + fbm(1, 2, 1) + delay(0.5, pingpong(clip(0.25, 0.75, linear(0, 1, 1))))\n\
+         out d = select(linear(-1, 3, 4), [hold(1, 1), speed(2, linear(0, 1, 1)), crossfade(hold(0, 1), hold(2, 1), 0.5)])\n\
+         out e = events([0.5, 1, 2b], linear(1, 0, 0.5)) + on_beat(140, [0, 1], decay(0.2)) + events([1, 3], since)";
+    let module = crate::compile(source).unwrap();
+    let bytes = Package::from(crate::compile(source).unwrap())
+        .to_bytes()
+        .unwrap();
+    let loaded = Package::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.to_bytes().unwrap(), bytes);
+    assert_eq!(loaded.markers, module.markers);
+    assert_eq!(loaded.timeline, module.timeline);
+    assert_eq!(loaded.signatures, module.signatures);
+
+    // Exports of the loaded program, and lowering them into another program
+    let exports = loaded.exports();
+    let relowered = Program::new(&exports);
+    for (index, name) in loaded.program.outputs().enumerate() {
+        let original = &module.exports[name];
+        assert_eq!(exports[name].get_duration(), original.get_duration());
+        for i in -5..100 {
+            let time = i as f64 * 0.061;
+            let value = original.get_value(time);
+            assert_eq!(loaded.program.get_value(index, time), value, "{}", name);
+            assert_eq!(relowered.get_value(index, time), value, "{}", name);
+            assert_eq!(
+                exports[name].get_velocity(time),
+                original.get_velocity(time)
+            );
+        }
+    }
+}
+
+#[cfg(feature = "compiler")]
+#[test]
+fn invalid_data() {
+    let bytes = Package::from(crate::compile("out a = sin(linear(0, 1, 1))").unwrap())
+        .to_bytes()
+        .unwrap();
+    for len in 0..bytes.len() {
+        assert!(Package::from_bytes(&bytes[..len]).is_err());
+    }
+    assert_eq!(
+        Package::from_bytes(b"PNG\x01\x00").unwrap_err(),
+        "not a compiled script"
+    );
+    let mut extra = bytes.clone();
+    extra.push(0);
+    assert_eq!(
+        Package::from_bytes(&extra).unwrap_err(),
+        "unexpected data after the script"
+    );
+
+    // Math node referring to a node after it
+    let mut program = Program::default();
+    program.push_apply(&[1], crate::envelope::MathOp::Sin.func(), 1.0);
+    program.push(Node::Hold { value: 1.0.into() }, 1.0);
+    let package = Package {
+        program,
+        ..Package::default()
+    };
+    assert_eq!(
+        Package::from_bytes(&package.to_bytes().unwrap()).unwrap_err(),
+        "invalid node 0"
+    );
+}
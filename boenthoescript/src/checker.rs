@@ -0,0 +1,1204 @@
+// Static checks, run before a script is evaluated. The type and the vector size of
+// every binding is inferred from the tree, so that mismatches such as a 2-component
+// `linear` concatenated with a 3-component one are reported instead of padded with zeros.
+
+use crate::{
+    ast::*,
+    compiler::*,
+    diagnostics::Diagnostic,
+    diagnostics::{COLOR_SPACES, EVENT_OUTPUTS, SPLINE_MODES},
+    easing::Easing,
+    imports::Sources,
+    types::Type,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+/// Result of the static checks
+#[derive(Debug, Default)]
+pub struct Check {
+    /// Types of the exports by name
+    pub signatures: HashMap<String, Type>,
+    /// Errors, ordered by their location
+    pub errors: Vec<BuildError>,
+    /// Unused definitions of the main script
+    pub warnings: Vec<BuildError>,
+}
+
+/// Checks a script, which imports have been resolved with `imports::resolve`
+pub fn check(root: &Expr, sources: &Sources) -> Check {
+    let mut checker = Checker {
+        sources,
+        errors: Vec::new(),
+        locals: Vec::new(),
+        depth: 0,
+        main: true,
+        file: None,
+        site: Span::default(),
+    };
+    let scope = Scope::new(None);
+    checker.infer(root, &scope);
+    checker.scenes(root, &scope);
+    let signatures = checker.exports(&scope);
+    checker.finish(&scope);
+
+    let mut warnings: Vec<BuildError> = checker
+        .locals
+        .iter()
+        .filter(|(_, binding)| !binding.used.get())
+        .map(|(name, binding)| BuildError::UnusedVariable(name.clone()).at(binding.span))
+        .collect();
+    sort(&mut warnings);
+
+    let mut errors = checker.errors;
+    sort(&mut errors);
+    errors.dedup_by(|a, b| a.span() == b.span() && a.to_string() == b.to_string());
+
+    Check {
+        signatures,
+        errors,
+        warnings,
+    }
+}
+
+/// Type of an expression in the top level scope of a script, like `compiler::evaluate`
+pub(crate) fn infer(root: &Expr, sources: &Sources, expr: &Expr) -> Type {
+    let mut checker = Checker {
+        sources,
+        errors: Vec::new(),
+        locals: Vec::new(),
+        depth: 0,
+        main: false,
+        file: None,
+        site: Span::default(),
+    };
+    let scope = Scope::new(None);
+    checker.infer(root, &scope);
+    checker.infer(expr, &scope)
+}
+
+fn sort(errors: &mut [BuildError]) {
+    errors.sort_by_key(|err| err.span().map_or(usize::MAX, |span| span.start));
+}
+
+struct Binding {
+    /// Missing for the parameters of a function that is checked without a call
+    expr: Option<Expr>,
+    /// Parameter names and their locations
+    params: Option<Vec<(String, Span)>>,
+    is_export: bool,
+    is_marker: bool,
+    /// Location of the name
+    span: Span,
+    used: Cell<bool>,
+    /// Called with more arguments, e.g. `f = linear(0, 1)` and `f(2)`
+    partial: Cell<bool>,
+    /// Used as an array, e.g. the choices of `select`, which checks the items
+    array: Cell<bool>,
+    checking: Cell<bool>,
+    ty: RefCell<Option<Type>>,
+}
+
+impl Binding {
+    fn new(expr: Option<Expr>, span: Span) -> Self {
+        Self {
+            expr,
+            params: None,
+            is_export: false,
+            is_marker: false,
+            span,
+            used: Cell::new(false),
+            partial: Cell::new(false),
+            array: Cell::new(false),
+            checking: Cell::new(false),
+            ty: RefCell::new(None),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Entry {
+    binding: Rc<Binding>,
+    /// Scope to check in, if not the one the entry is stored in
+    closure: Option<Scope>,
+}
+
+struct Frame {
+    vars: RefCell<HashMap<String, Entry>>,
+    parent: Option<Scope>,
+    /// Path of the imported script, `None` for the main script
+    file: Option<Rc<str>>,
+}
+
+/// Lexical scope, which mirrors `Env` of the compiler
+#[derive(Clone)]
+struct Scope(Rc<Frame>);
+
+impl Scope {
+    fn new(file: Option<Rc<str>>) -> Self {
+        Self(Rc::new(Frame {
+            vars: RefCell::new(HashMap::new()),
+            parent: None,
+            file,
+        }))
+    }
+
+    fn child(&self) -> Self {
+        Self(Rc::new(Frame {
+            vars: RefCell::new(HashMap::new()),
+            parent: Some(self.clone()),
+            file: self.0.file.clone(),
+        }))
+    }
+
+    fn get(&self, name: &str) -> Option<(Rc<Binding>, Scope)> {
+        let mut scope = Some(self.clone());
+        while let Some(s) = scope {
+            if let Some(entry) = s.0.vars.borrow().get(name) {
+                let closure = entry.closure.clone().unwrap_or_else(|| s.clone());
+                return Some((entry.binding.clone(), closure));
+            }
+            scope = s.0.parent.clone();
+        }
+        None
+    }
+
+    fn insert(&self, name: String, binding: Rc<Binding>, closure: Option<Scope>) {
+        self.0
+            .vars
+            .borrow_mut()
+            .insert(name, Entry { binding, closure });
+    }
+
+    fn entries(&self) -> Vec<(String, Entry)> {
+        let mut entries: Vec<(String, Entry)> = self
+            .0
+            .vars
+            .borrow()
+            .iter()
+            .map(|(name, entry)| (name.clone(), entry.clone()))
+            .collect();
+        entries.sort_by_key(|(_, entry)| entry.binding.span.start);
+        entries
+    }
+}
+
+/// Arguments of a call
+struct Args<'a> {
+    cons: &'a [Expr],
+    scope: &'a Scope,
+    /// Location of the call, for missing arguments
+    span: Span,
+}
+
+impl<'a> Args<'a> {
+    fn get(&self, index: usize) -> Option<&'a Expr> {
+        self.cons.get(index)
+    }
+}
+
+struct Checker<'a> {
+    sources: &'a Sources,
+    errors: Vec<BuildError>,
+    /// Definitions of the main script, reported if they are not used
+    locals: Vec<(String, Rc<Binding>)>,
+    depth: usize,
+    /// False while checking an imported script
+    main: bool,
+    /// Script of the expression being checked, `None` for the main script
+    file: Option<Rc<str>>,
+    /// Location of the expression being checked in that script
+    site: Span,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, error: BuildError, span: Span) {
+        self.errors.push(error.at(span));
+    }
+
+    /// Checks in the scope of another script, like `compiler::compile` does. Errors in an
+    /// imported script are reported as one import error at the expression being checked.
+    fn enter<F>(&mut self, file: Option<Rc<str>>, check: F) -> Type
+    where
+        F: FnOnce(&mut Self) -> Type,
+    {
+        if file == self.file {
+            return check(self);
+        }
+        let errors = std::mem::take(&mut self.errors);
+        let caller = std::mem::replace(&mut self.file, file);
+        let site = self.site;
+        let ty = check(self);
+        self.site = site;
+        let file = std::mem::replace(&mut self.file, caller);
+        let raised = std::mem::replace(&mut self.errors, errors);
+
+        let (passed, mut raised): (Vec<_>, Vec<_>) = raised
+            .into_iter()
+            .partition(|err| matches!(err, BuildError::InMain(_)));
+        self.errors.extend(passed);
+        match file {
+            None => self.errors.extend(
+                raised
+                    .into_iter()
+                    .map(|err| BuildError::InMain(Box::new(err))),
+            ),
+            Some(path) => {
+                sort(&mut raised);
+                if let Some(err) = raised.first() {
+                    let message = match self.sources.get(path.as_ref()) {
+                        Some(source) => {
+                            Diagnostic::from_build_error(err, &source.ast).render(&source.source)
+                        }
+                        None => err.to_string(),
+                    };
+                    let path = path.to_string();
+                    self.error(BuildError::Import { path, message }, site);
+                }
+            }
+        }
+        ty
+    }
+
+    fn infer(&mut self, expr: &Expr, scope: &Scope) -> Type {
+        self.enter(scope.0.file.clone(), |checker| {
+            let site = std::mem::replace(&mut checker.site, expr.span);
+            let ty = checker.infer_expr(expr, scope);
+            checker.site = site;
+            ty
+        })
+    }
+
+    fn infer_expr(&mut self, expr: &Expr, scope: &Scope) -> Type {
+        match &expr.kind {
+            ExprKind::Symbol(name) => match scope.get(name) {
+                Some((binding, closure)) => {
+                    binding.used.set(true);
+                    match &binding.params {
+                        Some(params) => {
+                            self.call(name, params, &binding, &closure, &[], scope, expr.span)
+                        }
+                        None => self.variable(&binding, &closure),
+                    }
+                }
+                None => Type::Symbol(name.clone()),
+            },
+            ExprKind::NumberList(n) => Type::Numbers(n.len()),
+            ExprKind::List(name, cons) => self.list(
+                name,
+                &Args {
+                    cons,
+                    scope,
+                    span: expr.span,
+                },
+            ),
+            ExprKind::Comment(_) => Type::Unknown,
+        }
+    }
+
+    /// Type of a variable, inferred once in the scope of its definition
+    fn variable(&mut self, binding: &Binding, scope: &Scope) -> Type {
+        if let Some(ty) = binding.ty.borrow().clone() {
+            return ty;
+        }
+        let expr = match &binding.expr {
+            Some(expr) => expr,
+            None => return Type::Unknown,
+        };
+        // Recursion is reported by the compiler
+        if binding.checking.get() || self.depth >= MAX_DEPTH {
+            return Type::Unknown;
+        }
+
+        binding.checking.set(true);
+        self.depth += 1;
+        let ty = self.infer(expr, scope);
+        self.depth -= 1;
+        binding.checking.set(false);
+        *binding.ty.borrow_mut() = Some(ty.clone());
+        ty
+    }
+
+    /// Checks the body of a function with the arguments of a call
+    #[allow(clippy::too_many_arguments)]
+    fn call(
+        &mut self,
+        name: &str,
+        params: &[(String, Span)],
+        function: &Binding,
+        scope: &Scope,
+        cons: &[Expr],
+        caller: &Scope,
+        span: Span,
+    ) -> Type {
+        if params.len() != cons.len() {
+            self.error(
+                BuildError::ArityMismatch {
+                    name: name.into(),
+                    expected: params.len(),
+                    actual: cons.len(),
+                },
+                span,
+            );
+            return Type::Unknown;
+        }
+        let body = match &function.expr {
+            Some(body) if self.depth < MAX_DEPTH => body,
+            _ => return Type::Unknown,
+        };
+
+        let local = scope.child();
+        for ((param, _), arg) in params.iter().zip(cons) {
+            let binding = Binding::new(Some(arg.clone()), arg.span);
+            local.insert(param.clone(), Rc::new(binding), Some(caller.clone()));
+        }
+        self.depth += 1;
+        let ty = self.infer(body, &local);
+        self.depth -= 1;
+        ty
+    }
+
+    fn list(&mut self, name: &str, args: &Args) -> Type {
+        match name {
+            KW_ROOT => self.block(args.cons, args.scope),
+            KW_BLOCK => {
+                let scope = args.scope.child();
+                let ty = self.block(args.cons, &scope);
+                self.finish(&scope);
+                ty
+            }
+            KW_DEFINE => self.define(args, false),
+            KW_EXPORT => self.define(args, true),
+            KW_FUNCTION => self.function(args),
+            KW_MARKER => self.marker(args),
+            KW_IMPORT => self.import(args),
+            KW_SCENE => Type::Unknown,
+            KW_BEATS => {
+                self.number(args, 0);
+                self.bpm(args);
+                Type::Numbers(1)
+            }
+            KW_BARS => {
+                self.number(args, 0);
+                self.bpm(args);
+                if let Some((binding, scope)) = args.scope.get(VAR_BEATS_PER_BAR) {
+                    binding.used.set(true);
+                    let ty = self.variable(&binding, &scope);
+                    self.expect_number(ty, args.span);
+                }
+                Type::Numbers(1)
+            }
+            KW_ARRAY => self.number_list(args),
+
+            OP_ADD | OP_SUB | OP_MUL | OP_DIV | STD_MIN | STD_MAX | STD_POW | STD_ADD | STD_MUL => {
+                self.math(args, 2)
+            }
+            OP_NEG | STD_SIN | STD_COS | STD_ABS | STD_SQRT | STD_FLOOR | STD_FRACT => {
+                self.math(args, 1)
+            }
+            STD_CLAMP | STD_MIX => self.math(args, 3),
+
+            STD_HOLD => {
+                let value = self.numbers(args, 0);
+                self.number(args, 1);
+                envelope(value)
+            }
+            STD_LINEAR => {
+                let from = self.numbers(args, 0);
+                let to = self.numbers(args, 1);
+                self.number(args, 2);
+                if args.get(3).is_some() {
+                    self.easing(args, 3);
+                }
+                envelope(self.same(args, &[(0, from), (1, to)]))
+            }
+            STD_CONCAT => {
+                let dims: Vec<(usize, Option<usize>)> = (0..args.cons.len())
+                    .map(|i| (i, self.envelope(args, i)))
+                    .collect();
+                envelope(self.same(args, &dims))
+            }
+            STD_REPEAT | STD_AT | STD_DELAY | STD_SPEED | STD_STRETCH | STD_SAMPLE_AT => {
+                self.number(args, 0);
+                envelope(self.envelope(args, 1))
+            }
+            STD_EVERY => {
+                self.number(args, 0);
+                let ty = envelope(self.envelope(args, 1));
+                self.bpm(args);
+                ty
+            }
+            STD_LOOP | STD_REVERSE | STD_PINGPONG => envelope(self.envelope(args, 0)),
+            STD_CLIP => {
+                self.number(args, 0);
+                self.number(args, 1);
+                envelope(self.envelope(args, 2))
+            }
+            STD_CROSSFADE => {
+                let a = self.envelope(args, 0);
+                let b = self.envelope(args, 1);
+                self.number(args, 2);
+                envelope(self.same(args, &[(0, a), (1, b)]))
+            }
+            STD_SELECT => {
+                self.envelope(args, 0);
+                match self.array(args.get(1), args) {
+                    Some(items) => {
+                        let items = Args {
+                            cons: &items,
+                            ..*args
+                        };
+                        let dims: Vec<(usize, Option<usize>)> = (0..items.cons.len())
+                            .map(|i| (i, self.envelope(&items, i)))
+                            .collect();
+                        envelope(self.same(&items, &dims))
+                    }
+                    None => Type::Unknown,
+                }
+            }
+            STD_SPLINE => self.spline(args),
+            STD_EVENTS => {
+                self.numbers(args, 0);
+                self.event_output(args, 1)
+            }
+            STD_ON_BEAT => {
+                self.number(args, 0);
+                self.numbers(args, 1);
+                self.event_output(args, 2)
+            }
+            STD_DECAY => {
+                self.number(args, 0);
+                Type::Envelope(1)
+            }
+            STD_HSL | STD_OKLAB => {
+                for index in 0..3 {
+                    self.number(args, index);
+                }
+                if args.get(3).is_some() {
+                    self.number(args, 3);
+                    Type::Numbers(4)
+                } else {
+                    Type::Numbers(3)
+                }
+            }
+            STD_LINEAR_COLOR => self.linear_color(args),
+            STD_RANDOM => {
+                self.number(args, 0);
+                let min = self.numbers(args, 1);
+                let max = self.numbers(args, 2);
+                self.number(args, 3);
+                envelope(self.same(args, &[(1, min), (2, max)]))
+            }
+            STD_NOISE | STD_FBM => {
+                self.number(args, 0);
+                self.number(args, 1);
+                let amplitude = self.numbers(args, 2);
+                if name == STD_FBM && args.get(3).is_some() {
+                    self.number(args, 3);
+                }
+                envelope(amplitude)
+            }
+
+            _ if Easing::from_name(name).is_some() => {
+                let from = self.numbers(args, 0);
+                let to = self.numbers(args, 1);
+                self.number(args, 2);
+                envelope(self.same(args, &[(0, from), (1, to)]))
+            }
+
+            _ => match args.scope.get(name) {
+                Some((binding, closure)) => {
+                    binding.used.set(true);
+                    match &binding.params {
+                        Some(params) => self.call(
+                            name, params, &binding, &closure, args.cons, args.scope, args.span,
+                        ),
+                        None => self.partial(name, &binding, &closure, args),
+                    }
+                }
+                None => {
+                    self.error(BuildError::FunctionNotFound(name.into()), args.span);
+                    Type::Unknown
+                }
+            },
+        }
+    }
+
+    /// Call of a definition with more arguments, which are appended to the defined call.
+    /// Like the arguments of a function, they are checked in the scope of the caller.
+    fn partial(&mut self, name: &str, binding: &Binding, scope: &Scope, args: &Args) -> Type {
+        match binding.expr.as_ref().map(|expr| &expr.kind) {
+            Some(ExprKind::List(sub_name, sub_cons)) => {
+                binding.partial.set(true);
+                if self.depth >= MAX_DEPTH {
+                    return Type::Unknown;
+                }
+                let local = scope.child();
+                // Locations of the call are not known in the scope of another script
+                let same_file = local.0.file == self.file;
+                let mut cons = sub_cons.clone();
+                for (i, arg) in args.cons.iter().enumerate() {
+                    let name = format!("#{}", i);
+                    let binding = Binding::new(Some(arg.clone()), arg.span);
+                    local.insert(name.clone(), Rc::new(binding), Some(args.scope.clone()));
+                    cons.push(Expr {
+                        kind: ExprKind::Symbol(name),
+                        span: if same_file { arg.span } else { Span::default() },
+                    });
+                }
+                let span = if same_file {
+                    args.span
+                } else {
+                    Span::default()
+                };
+                self.depth += 1;
+                let ty = self.enter(local.0.file.clone(), |checker| {
+                    checker.list(
+                        sub_name,
+                        &Args {
+                            cons: &cons,
+                            scope: &local,
+                            span,
+                        },
+                    )
+                });
+                self.depth -= 1;
+                ty
+            }
+            Some(_) => {
+                let expr = binding.expr.clone().unwrap_or_else(|| name.into());
+                self.error(BuildError::NotPartial(expr), args.span);
+                Type::Unknown
+            }
+            None => Type::Unknown,
+        }
+    }
+
+    fn block(&mut self, cons: &[Expr], scope: &Scope) -> Type {
+        let mut ty = Type::Unknown;
+        for expr in cons.iter() {
+            ty = self.infer(expr, scope);
+        }
+        ty
+    }
+
+    /// Checks the definitions of a scope that have not been used, so that their errors are reported too
+    fn finish(&mut self, scope: &Scope) {
+        for (_, entry) in scope.entries() {
+            let binding = &entry.binding;
+            let closure = entry.closure.clone().unwrap_or_else(|| scope.clone());
+            match &binding.params {
+                Some(params) => {
+                    // Parameters are unknown without a call
+                    let local = closure.child();
+                    for (param, span) in params.iter() {
+                        let param_binding = Rc::new(Binding::new(None, *span));
+                        if self.main && entry.closure.is_none() {
+                            self.locals.push((param.clone(), param_binding.clone()));
+                        }
+                        local.insert(param.clone(), param_binding, None);
+                    }
+                    if let Some(body) = &binding.expr {
+                        self.infer(body, &local);
+                    }
+                }
+                None if !binding.partial.get() && !binding.array.get() => {
+                    self.variable(binding, &closure);
+                }
+                None => (),
+            }
+        }
+    }
+
+    /// Checks the scenes of the main script, after all definitions have been bound
+    fn scenes(&mut self, root: &Expr, scope: &Scope) {
+        let statements = match &root.kind {
+            ExprKind::List(_, cons) => cons,
+            _ => return,
+        };
+        for statement in statements.iter() {
+            if let ExprKind::List(name, cons) = &statement.kind {
+                if name == KW_SCENE {
+                    let args = Args {
+                        cons,
+                        scope,
+                        span: statement.span,
+                    };
+                    self.number(&args, 1);
+                    self.number(&args, 2);
+                }
+            }
+        }
+    }
+
+    /// Checks the exports and markers of a top level scope, returning types of the exports
+    fn exports(&mut self, scope: &Scope) -> HashMap<String, Type> {
+        let mut signatures = HashMap::new();
+        for (name, entry) in scope.entries() {
+            let binding = &entry.binding;
+            let closure = entry.closure.clone().unwrap_or_else(|| scope.clone());
+            let span = binding.expr.as_ref().map_or(binding.span, |expr| expr.span);
+            if binding.is_marker {
+                let ty = self.variable(binding, &closure);
+                self.expect_number(ty, span);
+            } else if binding.is_export {
+                match self.variable(binding, &closure) {
+                    ty @ Type::Envelope(_) => {
+                        signatures.insert(name, ty);
+                    }
+                    Type::Unknown => (),
+                    actual => self.error(
+                        BuildError::TypeMismatch {
+                            expected: "Envelope function".into(),
+                            actual,
+                        },
+                        span,
+                    ),
+                }
+            }
+        }
+        signatures
+    }
+
+    fn define(&mut self, args: &Args, is_export: bool) -> Type {
+        let (name, span) = match self.name(args, 0) {
+            Some(name) => name,
+            None => return Type::Unknown,
+        };
+        let expr = match args.get(1) {
+            Some(expr) => expr.clone(),
+            None => {
+                self.error(BuildError::MissingArgument, args.span);
+                return Type::Unknown;
+            }
+        };
+        let binding = Rc::new(Binding {
+            is_export,
+            ..Binding::new(Some(expr), span)
+        });
+        if self.main && !is_export {
+            self.locals.push((name.clone(), binding.clone()));
+        }
+        args.scope.insert(name, binding, None);
+        Type::Unknown
+    }
+
+    fn function(&mut self, args: &Args) -> Type {
+        let (name, span) = match self.name(args, 0) {
+            Some(name) => name,
+            None => return Type::Unknown,
+        };
+        let params = match args.get(1).map(|expr| &expr.kind) {
+            Some(ExprKind::List(_, params)) => params
+                .iter()
+                .filter_map(|param| match &param.kind {
+                    ExprKind::Symbol(name) => Some((name.clone(), param.span)),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let binding = Rc::new(Binding {
+            params: Some(params),
+            ..Binding::new(args.get(2).cloned(), span)
+        });
+        if self.main {
+            self.locals.push((name.clone(), binding.clone()));
+        }
+        args.scope.insert(name, binding, None);
+        Type::Unknown
+    }
+
+    fn marker(&mut self, args: &Args) -> Type {
+        for (i, pair) in args.cons.chunks(2).enumerate() {
+            if let Some((name, span)) = self.name(args, i * 2) {
+                let binding = Binding {
+                    is_marker: true,
+                    ..Binding::new(pair.get(1).cloned(), span)
+                };
+                args.scope.insert(name, Rc::new(binding), None);
+            }
+        }
+        Type::Unknown
+    }
+
+    /// Checks an imported script and binds its definitions, like the compiler does
+    fn import(&mut self, args: &Args) -> Type {
+        let path = match self.name(args, 0) {
+            Some((path, _)) => path,
+            None => return Type::Unknown,
+        };
+        let alias = match args.get(1) {
+            Some(_) => self.name(args, 1).map(|(alias, _)| alias),
+            None => None,
+        };
+        let source = match self.sources.get(&path) {
+            Some(source) => source,
+            None => {
+                let message = "not resolved".into();
+                self.error(BuildError::Import { path, message }, args.span);
+                return Type::Unknown;
+            }
+        };
+
+        let main = std::mem::replace(&mut self.main, false);
+        let module = Scope::new(Some(path.as_str().into()));
+        self.enter(Some(path.as_str().into()), |checker| {
+            checker.infer(&source.ast, &module);
+            checker.exports(&module);
+            checker.finish(&module);
+            Type::Unknown
+        });
+        self.main = main;
+
+        for (name, entry) in module.entries() {
+            let name = match &alias {
+                Some(alias) => format!("{}.{}", alias, name),
+                None => name,
+            };
+            let closure = entry.closure.unwrap_or_else(|| module.clone());
+            args.scope.insert(name, entry.binding, Some(closure));
+        }
+        Type::Unknown
+    }
+
+    /// Beats require `bpm` to be defined
+    fn bpm(&mut self, args: &Args) {
+        match args.scope.get(VAR_BPM) {
+            Some((binding, scope)) => {
+                binding.used.set(true);
+                let ty = self.variable(&binding, &scope);
+                self.expect_number(ty, args.span);
+            }
+            None => self.error(BuildError::BpmNotDefined, args.span),
+        }
+    }
+
+    fn number_list(&mut self, args: &Args) -> Type {
+        let mut len = Some(0);
+        for i in 0..args.cons.len() {
+            len = match (len, self.numbers(args, i)) {
+                (Some(len), Some(n)) => Some(len + n),
+                _ => None,
+            };
+        }
+        len.map_or(Type::Unknown, Type::Numbers)
+    }
+
+    /// Component-wise math. Single numbers are broadcast, other sizes have to match.
+    fn math(&mut self, args: &Args, arity: usize) -> Type {
+        let mut operands = Vec::new();
+        for i in 0..arity {
+            let ty = match self.arg(args, i) {
+                Some((ty @ Type::Numbers(_), _)) | Some((ty @ Type::Envelope(_), _)) => ty,
+                Some((Type::Unknown, _)) | None => Type::Unknown,
+                Some((ty, expr)) => {
+                    self.mismatch("NumberList or EnvelopeFunction", ty, expr);
+                    Type::Unknown
+                }
+            };
+            operands.push((i, ty));
+        }
+
+        let dims: Vec<(usize, Option<usize>)> = operands
+            .iter()
+            .filter(|(_, ty)| *ty != Type::Numbers(1))
+            .map(|(i, ty)| (*i, ty.dimension()))
+            .collect();
+        let dimension = self.same(args, &dims);
+        if operands.iter().any(|(_, ty)| *ty == Type::Unknown) {
+            Type::Unknown
+        } else if operands
+            .iter()
+            .any(|(_, ty)| matches!(ty, Type::Envelope(_)))
+        {
+            Type::Envelope(dimension.unwrap_or(1))
+        } else {
+            Type::Numbers(dimension.unwrap_or(1))
+        }
+    }
+
+    fn spline(&mut self, args: &Args) -> Type {
+        if args.get(1).is_some() {
+            if let Some(mode) = self.symbol(args, 1) {
+                if !SPLINE_MODES.contains(&mode.as_str()) {
+                    let span = args.cons[1].span;
+                    self.error(BuildError::UnknownSplineMode(mode), span);
+                }
+            }
+        }
+        let rows = match self.array(args.get(0), args) {
+            Some(rows) => rows,
+            None => return Type::Unknown,
+        };
+
+        let mut values = Vec::new();
+        for row in rows.iter() {
+            if let Some(items) = self.array(Some(row), args) {
+                let row_args = Args {
+                    cons: &items,
+                    scope: args.scope,
+                    span: row.span,
+                };
+                self.number(&row_args, 0);
+                let value = self.numbers(&row_args, 1);
+                for i in 2..items.len() {
+                    self.numbers(&row_args, i);
+                }
+                if let Some(expr) = items.get(1) {
+                    values.push((expr.clone(), value));
+                }
+            }
+        }
+        let values_args = Args {
+            cons: &values.iter().map(|(e, _)| e.clone()).collect::<Vec<Expr>>(),
+            ..*args
+        };
+        let dims: Vec<(usize, Option<usize>)> = values
+            .iter()
+            .enumerate()
+            .map(|(i, (_, dim))| (i, *dim))
+            .collect();
+        envelope(self.same(&values_args, &dims))
+    }
+
+    /// Fade between colours of three or four components, in a colour space
+    fn linear_color(&mut self, args: &Args) -> Type {
+        let from = self.numbers(args, 0);
+        let to = self.numbers(args, 1);
+        self.number(args, 2);
+        if args.get(3).is_some() {
+            if let Some(space) = self.symbol(args, 3) {
+                if !COLOR_SPACES.contains(&space.as_str()) {
+                    let span = args.cons[3].span;
+                    self.error(BuildError::UnknownColorSpace(space), span);
+                }
+            }
+        }
+        let dim = self.same(args, &[(0, from), (1, to)]);
+        if let Some(actual) = dim.filter(|n| *n < 3) {
+            let span = args.get(0).map_or(args.span, |expr| expr.span);
+            let error = BuildError::DimensionMismatch {
+                expected: 3,
+                actual,
+            };
+            self.error(error, span);
+        }
+        envelope(dim)
+    }
+
+    /// Shape envelope of events, or `since` or `index`
+    fn event_output(&mut self, args: &Args, index: usize) -> Type {
+        if args.get(index).is_none() {
+            return Type::Envelope(1);
+        }
+        match self.arg(args, index) {
+            Some((Type::Envelope(n), _)) => Type::Envelope(n),
+            Some((Type::Symbol(name), expr)) => {
+                if !EVENT_OUTPUTS.contains(&name.as_str()) {
+                    self.error(BuildError::UnknownEventOutput(name), expr.span);
+                }
+                Type::Envelope(1)
+            }
+            Some((Type::Unknown, _)) | None => Type::Unknown,
+            Some((ty, expr)) => {
+                self.mismatch("EnvelopeFunction", ty, expr);
+                Type::Unknown
+            }
+        }
+    }
+
+    /// Items of an array literal or a variable bound to one
+    fn array(&mut self, expr: Option<&Expr>, args: &Args) -> Option<Vec<Expr>> {
+        let expr = match expr {
+            Some(expr) => expr,
+            None => {
+                self.error(BuildError::MissingArgument, args.span);
+                return None;
+            }
+        };
+        match &expr.kind {
+            ExprKind::List(name, items) if name == KW_ARRAY => Some(items.clone()),
+            ExprKind::Symbol(name) => match args.scope.get(name) {
+                Some((binding, scope)) if binding.params.is_none() => {
+                    binding.used.set(true);
+                    binding.array.set(true);
+                    if self.depth >= MAX_DEPTH {
+                        return None;
+                    }
+                    self.depth += 1;
+                    let items = self.array(
+                        binding.expr.as_ref(),
+                        &Args {
+                            scope: &scope,
+                            ..*args
+                        },
+                    );
+                    self.depth -= 1;
+                    items
+                }
+                Some(_) => {
+                    let ty = self.infer(expr, args.scope);
+                    self.mismatch("Array", ty, expr);
+                    None
+                }
+                None => {
+                    self.error(BuildError::VariableNotFound(name.clone()), expr.span);
+                    None
+                }
+            },
+            _ => {
+                let ty = self.infer(expr, args.scope);
+                if ty != Type::Unknown {
+                    self.mismatch("Array", ty, expr);
+                }
+                None
+            }
+        }
+    }
+
+    /// Name of a definition, which is not evaluated
+    fn name(&mut self, args: &Args, index: usize) -> Option<(String, Span)> {
+        match args.get(index) {
+            Some(Expr {
+                kind: ExprKind::Symbol(name),
+                span,
+            }) => Some((name.clone(), *span)),
+            Some(expr) => {
+                let error = BuildError::TypeMismatch {
+                    expected: "Symbol".into(),
+                    actual: Type::Unknown,
+                };
+                self.error(error, expr.span);
+                None
+            }
+            None => {
+                self.error(BuildError::MissingArgument, args.span);
+                None
+            }
+        }
+    }
+
+    fn arg<'b>(&mut self, args: &Args<'b>, index: usize) -> Option<(Type, &'b Expr)> {
+        match args.get(index) {
+            Some(expr) => Some((self.infer(expr, args.scope), expr)),
+            None => {
+                self.error(BuildError::MissingArgument, args.span);
+                None
+            }
+        }
+    }
+
+    fn number(&mut self, args: &Args, index: usize) {
+        if let Some((ty, expr)) = self.arg(args, index) {
+            self.expect_number(ty, expr.span);
+        }
+    }
+
+    fn expect_number(&mut self, ty: Type, span: Span) {
+        match ty {
+            Type::Numbers(1) | Type::Unknown => (),
+            Type::Symbol(name) => self.error(BuildError::VariableNotFound(name), span),
+            actual => self.error(
+                BuildError::TypeMismatch {
+                    expected: "Number".into(),
+                    actual,
+                },
+                span,
+            ),
+        }
+    }
+
+    /// Length of a number list argument, if known
+    fn numbers(&mut self, args: &Args, index: usize) -> Option<usize> {
+        match self.arg(args, index)? {
+            (Type::Numbers(n), _) => Some(n),
+            (Type::Unknown, _) => None,
+            (ty, expr) => {
+                self.mismatch("NumberList", ty, expr);
+                None
+            }
+        }
+    }
+
+    /// Number of components of an envelope argument, if known
+    fn envelope(&mut self, args: &Args, index: usize) -> Option<usize> {
+        match self.arg(args, index)? {
+            (Type::Envelope(n), _) => Some(n),
+            (Type::Unknown, _) => None,
+            (ty, expr) => {
+                self.mismatch("EnvelopeFunction", ty, expr);
+                None
+            }
+        }
+    }
+
+    fn symbol(&mut self, args: &Args, index: usize) -> Option<String> {
+        match self.arg(args, index)? {
+            (Type::Symbol(name), _) => Some(name),
+            (Type::Unknown, _) => None,
+            (actual, expr) => {
+                let error = BuildError::TypeMismatch {
+                    expected: "Symbol".into(),
+                    actual,
+                };
+                self.error(error, expr.span);
+                None
+            }
+        }
+    }
+
+    fn easing(&mut self, args: &Args, index: usize) {
+        if let Some(name) = self.symbol(args, index) {
+            if Easing::from_name(&name).is_none() {
+                let span = args.cons[index].span;
+                self.error(BuildError::UnknownEasing(name), span);
+            }
+        }
+    }
+
+    fn mismatch(&mut self, expected: &str, actual: Type, expr: &Expr) {
+        let error = match actual {
+            Type::Symbol(name) => BuildError::VariableNotFound(name),
+            actual => BuildError::TypeMismatch {
+                expected: expected.into(),
+                actual,
+            },
+        };
+        self.error(error, expr.span);
+    }
+
+    /// Checks that the known sizes of the arguments are equal, returning the size
+    fn same(&mut self, args: &Args, dims: &[(usize, Option<usize>)]) -> Option<usize> {
+        let expected = dims.iter().find_map(|(_, dim)| *dim)?;
+        for (index, dim) in dims.iter() {
+            match dim {
+                Some(actual) if *actual != expected => {
+                    let span = args.get(*index).map_or(args.span, |expr| expr.span);
+                    let error = BuildError::DimensionMismatch {
+                        expected,
+                        actual: *actual,
+                    };
+                    self.error(error, span);
+                }
+                _ => (),
+            }
+        }
+        Some(expected)
+    }
+}
+
+fn envelope(dimension: Option<usize>) -> Type {
+    dimension.map_or(Type::Unknown, Type::Envelope)
+}
+
+#[cfg(test)]
+fn check_source(source: &str) -> Check {
+    check(&crate::parser::parse(source).unwrap(), &Sources::new())
+}
+
+#[cfg(test)]
+fn messages(errors: &[BuildError]) -> Vec<String> {
+    errors.iter().map(|err| err.to_string()).collect()
+}
+
+#[test]
+fn inference() {
+    let check = check_source(
+        "color = [1, 0.5, 0]\n\
+         fade(from, to) = linear(from, to, 2)\n\
+         out a = hold(color, 1)\n\
+         out b = concat(fade(0, 1), hold(1, 1)) * 2\n\
+         out c = fade(color, [0, 0, 1]) * [1, 2, 3] + 1\n\
+         out d = spline([[0, [0, 0]], [1, [1, 2]]])\n\
+         out e = {\n\
+            offset = [1, 2]\n\
+            hold(offset, 1) + 1\n\
+         }\n\
+         out f = events([1, 2], hold(color, 1)) + color\n\
+         out g = on_beat(120, [1, 0], index) + events([1], since)\n\
+         out h = linear_color(#ff8800, hsl(200, 0.5, 0.5), 1, hsl) + color\n\
+         out i = linear_color(#ff880080, oklab(0.5, 0, 0.1, 1), 1)\n\
+         choices = [hold(1, 1), hold(2, 1)]\n\
+         out j = select(linear(0, 2, 2), choices)",
+    );
+    assert!(check.errors.is_empty(), "{:?}", messages(&check.errors));
+    assert!(check.warnings.is_empty(), "{:?}", messages(&check.warnings));
+    assert_eq!(check.signatures["a"], Type::Envelope(3));
+    assert_eq!(check.signatures["b"], Type::Envelope(1));
+    assert_eq!(check.signatures["c"], Type::Envelope(3));
+    assert_eq!(check.signatures["d"], Type::Envelope(2));
+    assert_eq!(check.signatures["e"], Type::Envelope(2));
+    assert_eq!(check.signatures["f"], Type::Envelope(3));
+    assert_eq!(check.signatures["g"], Type::Envelope(1));
+    assert_eq!(check.signatures["h"], Type::Envelope(3));
+    assert_eq!(check.signatures["i"], Type::Envelope(4));
+    assert_eq!(check.signatures["j"], Type::Envelope(1));
+}
+
+#[test]
+fn mismatches() {
+    let check = check_source(
+        "out a = concat(linear([0, 0], [1, 1], 1), linear([0, 0, 0], [1, 1, 1], 1))\n\
+         out b = linear([0, 0], [1, 1, 1], 1)\n\
+         out c = hold(1, [1, 2])\n\
+         out d = hold(x, 1) * linear(0, 1, 1)\n\
+         out e = 1\n\
+         unused = hold(1, 1) + [1, 2]",
+    );
+    assert_eq!(
+        messages(&check.errors),
+        vec![
+            "expected 2 component(s), found 3",
+            "expected 2 component(s), found 3",
+            "expected Number, found a list of 2 numbers",
+            "unknown variable `x`",
+            "expected Envelope function, found a number",
+            "expected 1 component(s), found 2",
+        ]
+    );
+    assert_eq!(check.errors[0].span(), Some(Span { start: 42, end: 73 }));
+    assert!(!check.signatures.contains_key("e"));
+}
+
+#[test]
+fn unused_variables() {
+    let check = check_source(
+        "bpm = 120\n\
+         a = 1\n\
+         f(x, y) = hold(x, 1b)\n\
+         out b = hold(a, 1)",
+    );
+    assert!(check.errors.is_empty(), "{:?}", messages(&check.errors));
+    assert_eq!(
+        messages(&check.warnings),
+        vec!["unused variable `f`", "unused variable `y`"]
+    );
+    assert_eq!(check.warnings[1].span(), Some(Span { start: 21, end: 22 }));
+
+    let check = check_source("out a = hold(1, 2b)\nout b = every(1, hold(1, 1))");
+    assert_eq!(
+        messages(&check.errors),
+        vec![
+            "beats and bars require `bpm` to be defined",
+            "beats and bars require `bpm` to be defined",
+        ]
+    );
+
+    let check = check_source(
+        "scene \"intro\" from 0 to length\n\
+         scene \"outro\" from x to [1, 2]\n\
+         length = 8",
+    );
+    assert!(check.warnings.is_empty(), "{:?}", messages(&check.warnings));
+    assert_eq!(
+        messages(&check.errors),
+        vec![
+            "unknown variable `x`",
+            "expected Number, found a list of 2 numbers"
+        ]
+    );
+}
@@ -0,0 +1,321 @@
+// Colours are sRGB encoded components in range 0..1, like hex colours, with an optional
+// alpha component. Fades convert their end points into another colour space, interpolate
+// there and convert back to sRGB.
+
+use crate::vector::Vector;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    LinearRgb,
+    Hsl,
+    Oklab,
+}
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear_rgb" => Some(Self::LinearRgb),
+            "hsl" => Some(Self::Hsl),
+            "oklab" => Some(Self::Oklab),
+            _ => None,
+        }
+    }
+
+    /// Coordinates of an sRGB colour in this space. Alpha is kept as is.
+    pub fn coordinates(&self, color: &Vector) -> Vector {
+        let [r, g, b, alpha] = color.0;
+        let [x, y, z] = match self {
+            Self::LinearRgb => [to_linear(r), to_linear(g), to_linear(b)],
+            Self::Hsl => rgb_to_hsl(r, g, b),
+            Self::Oklab => linear_to_oklab(to_linear(r), to_linear(g), to_linear(b)),
+        };
+        Vector([x, y, z, alpha])
+    }
+
+    /// Converts a colour in this space to sRGB, along with its derivative
+    fn srgb(&self, color: [Dual; 3]) -> [Dual; 3] {
+        let [x, y, z] = color;
+        match self {
+            Self::LinearRgb => [to_srgb(x), to_srgb(y), to_srgb(z)],
+            Self::Hsl => hsl_to_rgb(x, y, z),
+            Self::Oklab => {
+                let [r, g, b] = oklab_to_linear(x, y, z);
+                [to_srgb(r), to_srgb(g), to_srgb(b)]
+            }
+        }
+    }
+
+    /// End points of a fade in this space. Hues take the shorter way around the colour wheel.
+    pub fn fade_points(&self, from: &Vector, to: &Vector) -> (Vector, Vector) {
+        let mut a = self.coordinates(from);
+        let mut b = self.coordinates(to);
+        if *self == Self::Hsl {
+            // Greys have no hue, so they take the hue of the other end
+            if a.0[1] == 0.0 {
+                a.0[0] = b.0[0];
+            } else if b.0[1] == 0.0 {
+                b.0[0] = a.0[0];
+            }
+            if b.0[0] - a.0[0] > 0.5 {
+                b.0[0] -= 1.0;
+            } else if a.0[0] - b.0[0] > 0.5 {
+                b.0[0] += 1.0;
+            }
+        }
+        (a, b)
+    }
+
+    /// Colour and its velocity at `t` (0..1) of a fade between end points from `fade_points`.
+    /// `rate` is the derivative of `t` with respect to time.
+    pub fn mix(&self, a: &Vector, b: &Vector, t: f64, rate: f64) -> (Vector, Vector) {
+        let mix = |i: usize| Dual {
+            value: a.0[i] + (b.0[i] - a.0[i]) * t,
+            derivative: (b.0[i] - a.0[i]) * rate,
+        };
+        let rgb = self.srgb([mix(0), mix(1), mix(2)]);
+        let alpha = mix(3);
+        (
+            Vector([rgb[0].value, rgb[1].value, rgb[2].value, alpha.value]),
+            Vector([
+                rgb[0].derivative,
+                rgb[1].derivative,
+                rgb[2].derivative,
+                alpha.derivative,
+            ]),
+        )
+    }
+}
+
+/// Colour from hue in degrees, saturation and lightness
+#[cfg(any(feature = "compiler", test))]
+pub fn hsl(hue: f64, saturation: f64, lightness: f64) -> [f64; 3] {
+    let [r, g, b] = hsl_to_rgb(
+        Dual::constant(hue / 360.0),
+        Dual::constant(saturation),
+        Dual::constant(lightness),
+    );
+    [r.value, g.value, b.value]
+}
+
+/// Colour from Oklab lightness and a and b axes
+#[cfg(any(feature = "compiler", test))]
+pub fn oklab(lightness: f64, a: f64, b: f64) -> [f64; 3] {
+    let [r, g, b] = oklab_to_linear(
+        Dual::constant(lightness),
+        Dual::constant(a),
+        Dual::constant(b),
+    );
+    [to_srgb(r).value, to_srgb(g).value, to_srgb(b).value]
+}
+
+/// Value with its derivative, so that colour conversions give velocities too
+#[derive(Debug, Clone, Copy)]
+struct Dual {
+    value: f64,
+    derivative: f64,
+}
+
+impl Dual {
+    #[cfg(any(feature = "compiler", test))]
+    fn constant(value: f64) -> Self {
+        Self {
+            value,
+            derivative: 0.0,
+        }
+    }
+
+    fn scale(self, factor: f64) -> Self {
+        Self {
+            value: self.value * factor,
+            derivative: self.derivative * factor,
+        }
+    }
+
+    fn offset(self, amount: f64) -> Self {
+        Self {
+            value: self.value + amount,
+            ..self
+        }
+    }
+
+    fn powf(self, exponent: f64) -> Self {
+        Self {
+            value: self.value.powf(exponent),
+            derivative: exponent * self.value.powf(exponent - 1.0) * self.derivative,
+        }
+    }
+}
+
+impl std::ops::Add for Dual {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            value: self.value + rhs.value,
+            derivative: self.derivative + rhs.derivative,
+        }
+    }
+}
+
+impl std::ops::Sub for Dual {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            value: self.value - rhs.value,
+            derivative: self.derivative - rhs.derivative,
+        }
+    }
+}
+
+impl std::ops::Mul for Dual {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self {
+            value: self.value * rhs.value,
+            derivative: self.derivative * rhs.value + self.value * rhs.derivative,
+        }
+    }
+}
+
+/// Sum of components scaled by a row of a matrix
+fn dot(row: [f64; 3], x: Dual, y: Dual, z: Dual) -> Dual {
+    x.scale(row[0]) + y.scale(row[1]) + z.scale(row[2])
+}
+
+fn to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn to_srgb(c: Dual) -> Dual {
+    if c.value <= 0.0031308 {
+        c.scale(12.92)
+    } else {
+        c.powf(1.0 / 2.4).scale(1.055).offset(-0.055)
+    }
+}
+
+fn rgb_to_hsl(r: f64, g: f64, b: f64) -> [f64; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let chroma = max - min;
+    if chroma == 0.0 {
+        return [0.0, 0.0, lightness];
+    }
+    let saturation = chroma / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    [hue / 6.0, saturation, lightness]
+}
+
+/// Hue in range 0..1
+fn hsl_to_rgb(hue: Dual, saturation: Dual, lightness: Dual) -> [Dual; 3] {
+    let q = if lightness.value < 0.5 {
+        lightness * saturation.offset(1.0)
+    } else {
+        lightness + saturation - lightness * saturation
+    };
+    let p = lightness.scale(2.0) - q;
+    let channel = |offset: f64| {
+        let t = hue.offset(offset - (hue.value + offset).floor());
+        if t.value < 1.0 / 6.0 {
+            p + (q - p) * t.scale(6.0)
+        } else if t.value < 0.5 {
+            q
+        } else if t.value < 2.0 / 3.0 {
+            p + (q - p) * t.scale(-6.0).offset(4.0)
+        } else {
+            p
+        }
+    };
+    [channel(1.0 / 3.0), channel(0.0), channel(-1.0 / 3.0)]
+}
+
+fn linear_to_oklab(r: f64, g: f64, b: f64) -> [f64; 3] {
+    let l = (0.4122214708 * r + 0.5363015870 * g + 0.0514770982 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn oklab_to_linear(lightness: Dual, a: Dual, b: Dual) -> [Dual; 3] {
+    let cube = |x: Dual| x * x * x;
+    let l = cube(dot([1.0, 0.3963377774, 0.2158037573], lightness, a, b));
+    let m = cube(dot([1.0, -0.1055613458, -0.0638541728], lightness, a, b));
+    let s = cube(dot([1.0, -0.0894841775, -1.2914855480], lightness, a, b));
+    [
+        dot([4.0767416621, -3.3077115913, 0.2309699292], l, m, s),
+        dot([-1.2684380046, 2.6097574011, -0.3413193965], l, m, s),
+        dot([-0.0041960863, -0.7034186147, 1.7076147010], l, m, s),
+    ]
+}
+
+#[cfg(test)]
+fn assert_close(a: &[f64], b: &[f64]) {
+    for (x, y) in a.iter().zip(b.iter()) {
+        assert!((x - y).abs() < 1e-4, "{:?} != {:?}", a, b);
+    }
+}
+
+#[test]
+fn conversions() {
+    assert_close(&hsl(0.0, 1.0, 0.5), &[1.0, 0.0, 0.0]);
+    assert_close(&hsl(120.0, 1.0, 0.25), &[0.0, 0.5, 0.0]);
+    assert_close(&hsl(210.0, 0.5, 0.6), &[0.4, 0.6, 0.8]);
+    assert_close(&oklab(1.0, 0.0, 0.0), &[1.0, 1.0, 1.0]);
+    assert_close(&oklab(0.0, 0.0, 0.0), &[0.0, 0.0, 0.0]);
+
+    let color = Vector([0.9, 0.4, 0.1, 0.5]);
+    for space in [ColorSpace::LinearRgb, ColorSpace::Hsl, ColorSpace::Oklab].iter() {
+        let converted = space.coordinates(&color);
+        let (back, _) = space.mix(&converted, &converted, 0.0, 0.0);
+        assert_close(&back.0, &color.0);
+    }
+}
+
+#[test]
+fn fades() {
+    let red = Vector([1.0, 0.0, 0.0, 1.0]);
+    let blue = Vector([0.0, 0.0, 1.0, 0.0]);
+
+    let (a, b) = ColorSpace::Hsl.fade_points(&red, &blue);
+    let (middle, _) = ColorSpace::Hsl.mix(&a, &b, 0.5, 0.0);
+    assert_close(&middle.0, &[1.0, 0.0, 1.0, 0.5]);
+
+    // Oklab keeps the midpoint brighter than sRGB interpolation
+    let (a, b) = ColorSpace::Oklab.fade_points(&red, &blue);
+    let (middle, _) = ColorSpace::Oklab.mix(&a, &b, 0.5, 0.0);
+    assert!(middle.0[0] > 0.5 && middle.0[2] > 0.5);
+
+    for space in [ColorSpace::LinearRgb, ColorSpace::Hsl, ColorSpace::Oklab].iter() {
+        let (a, b) = space.fade_points(&red, &blue);
+        for i in 1..10 {
+            let t = i as f64 * 0.1 + 0.01;
+            let h = 1e-6;
+            let (_, velocity) = space.mix(&a, &b, t, 1.0);
+            let (before, _) = space.mix(&a, &b, t - h, 0.0);
+            let (after, _) = space.mix(&a, &b, t + h, 0.0);
+            for c in 0..4 {
+                let numeric = (after.0[c] - before.0[c]) / (2.0 * h);
+                assert!(
+                    (velocity.0[c] - numeric).abs() < 1e-4,
+                    "{:?} at {}",
+                    space,
+                    t
+                );
+            }
+        }
+    }
+}
@@ -1,16 +1,27 @@
-use crate::{ast::*, envelope, envelope::Envelope};
-use std::collections::HashMap;
+use crate::{
+    ast::*,
+    checker,
+    color::{self, ColorSpace},
+    diagnostics::Diagnostic,
+    easing::Easing,
+    envelope,
+    envelope::{EnvelopeFn, MathOp},
+    imports::Sources,
+    program::Program,
+    timeline::{Marker, Scene, Timeline},
+    types::Type,
+    vector::Vector,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
 
 type Number = f64;
-pub type EnvelopeFn = Box<dyn Envelope>;
-
-impl std::fmt::Debug for EnvelopeFn {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Envelope").finish()
-    }
-}
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum Build {
     Symbol(String),
     NumberList(Vec<Number>),
@@ -20,86 +31,626 @@ pub enum Build {
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum BuildError {
-    InvalidType { expected: String, actual: Build },
+    InvalidType {
+        expected: String,
+        actual: Build,
+    },
     FunctionNotFound(String),
     VariableNotFound(String),
     MissingArgument,
+    UnknownEasing(String),
+    UnknownSplineMode(String),
+    UnknownEventOutput(String),
+    UnknownColorSpace(String),
+    InvalidScene(String),
+    /// Time that is not a finite number, e.g. `0/0`
+    InvalidTime(Number),
+    /// Length or period that is not positive and finite, e.g. `every(0, …)` or `loop(loop(…))`
+    InvalidDuration(Number),
+    /// Playback speed that is not positive and finite
+    InvalidSpeed(Number),
     NotPartial(Expr),
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    RecursionLimit(String),
+    BpmNotDefined,
+    ImportCycle(Vec<String>),
+    /// Found by the static checks, before evaluation
+    TypeMismatch {
+        expected: String,
+        actual: Type,
+    },
+    DimensionMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    UnusedVariable(String),
+    /// Imported script could not be loaded or compiled. The message refers to the imported source.
+    Import {
+        path: String,
+        message: String,
+    },
+    /// Error located in the main script, which is passed through imported definitions
+    /// unchanged, e.g. in an argument of an imported function
+    InMain(Box<BuildError>),
+    /// Error with the source location of the failing expression
+    Located {
+        span: Span,
+        error: Box<BuildError>,
+    },
+}
+
+impl BuildError {
+    /// Attaches a source location, unless the error already has a more specific one
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            Self::Located { .. } | Self::InMain(_) => self,
+            _ if span.is_empty() => self,
+            error => Self::Located {
+                span,
+                error: Box::new(error),
+            },
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Located { span, .. } => Some(*span),
+            Self::InMain(error) => error.span(),
+            _ => None,
+        }
+    }
+
+    /// The error without location information
+    pub fn cause(&self) -> &BuildError {
+        match self {
+            Self::Located { error, .. } | Self::InMain(error) => error.cause(),
+            error => error,
+        }
+    }
+}
+
+impl Build {
+    fn describe(&self) -> String {
+        match self {
+            Self::Symbol(s) => format!("symbol `{}`", s),
+            Self::NumberList(n) if n.len() == 1 => "a number".into(),
+            Self::NumberList(n) => format!("a list of {} numbers", n.len()),
+            Self::EnvelopeFn(_) => "an envelope".into(),
+            Self::Partial(_) => "a definition".into(),
+            Self::Nil => "nothing".into(),
+        }
+    }
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidType { expected, actual } => {
+                write!(f, "expected {}, found {}", expected, actual.describe())
+            }
+            Self::FunctionNotFound(name) => write!(f, "unknown function `{}`", name),
+            Self::VariableNotFound(name) => write!(f, "unknown variable `{}`", name),
+            Self::MissingArgument => write!(f, "missing argument"),
+            Self::UnknownEasing(name) => write!(f, "unknown easing `{}`", name),
+            Self::UnknownSplineMode(mode) => write!(f, "unknown spline mode `{}`", mode),
+            Self::UnknownEventOutput(name) => write!(f, "unknown event output `{}`", name),
+            Self::UnknownColorSpace(name) => write!(f, "unknown colour space `{}`", name),
+            Self::InvalidScene(name) => write!(f, "scene `{}` ends before it starts", name),
+            Self::InvalidTime(time) => write!(f, "time must be a finite number, found {}", time),
+            Self::InvalidDuration(duration) => write!(
+                f,
+                "duration must be a positive finite number, found {}",
+                duration
+            ),
+            Self::InvalidSpeed(factor) => write!(
+                f,
+                "speed must be a positive finite number, found {}",
+                factor
+            ),
+            Self::NotPartial(_) => write!(f, "expression cannot be called with arguments"),
+            Self::ArityMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "`{}` takes {} argument(s) but {} were given",
+                name, expected, actual
+            ),
+            Self::RecursionLimit(name) => write!(f, "recursion limit reached in `{}`", name),
+            Self::BpmNotDefined => write!(f, "beats and bars require `bpm` to be defined"),
+            Self::TypeMismatch { expected, actual } => {
+                write!(f, "expected {}, found {}", expected, actual)
+            }
+            Self::DimensionMismatch { expected, actual } => {
+                write!(f, "expected {} component(s), found {}", expected, actual)
+            }
+            Self::UnusedVariable(name) => write!(f, "unused variable `{}`", name),
+            Self::ImportCycle(cycle) => write!(f, "import cycle: {}", cycle.join(" -> ")),
+            Self::Import { path, .. } => write!(f, "error in imported script `{}`", path),
+            Self::Located { error, .. } | Self::InMain(error) => error.fmt(f),
+        }
+    }
 }
 
 type BuildResult = Result<Build, BuildError>;
 
-#[derive(Debug, Clone)]
+/// Compiled script
+#[derive(Debug)]
+pub struct Module {
+    pub exports: HashMap<String, EnvelopeFn>,
+    /// Named time markers, ordered by time
+    pub markers: Vec<Marker>,
+    /// Scenes of the main script
+    pub timeline: Timeline,
+    /// Paths of all imported scripts, including indirect imports
+    pub imports: Vec<String>,
+    /// Exports lowered into a flat program for fast evaluation
+    pub program: Program,
+    /// Inferred types of the exports
+    pub signatures: HashMap<String, Type>,
+    /// Unused definitions
+    pub warnings: Vec<Diagnostic>,
+}
+
+#[derive(Clone)]
 struct Variable {
     expr: Expr,
     is_export: bool,
+    is_marker: bool,
+    /// Parameter names of a function definition
+    params: Option<Vec<String>>,
+    /// Scope to evaluate in, if not the one the variable is stored in: function
+    /// arguments are evaluated where the function was called and imports in their own script
+    closure: Option<Env>,
+}
+
+impl Variable {
+    fn new(expr: Expr, is_export: bool) -> Self {
+        Self {
+            expr,
+            is_export,
+            is_marker: false,
+            params: None,
+            closure: None,
+        }
+    }
+}
+
+/// Maximum depth of nested variable lookups and function calls
+pub(crate) const MAX_DEPTH: usize = 100;
+
+struct Scope {
+    vars: RefCell<HashMap<String, Variable>>,
+    parent: Option<Rc<Scope>>,
+}
+
+/// Lexical scope. Variables are evaluated in the scope they were defined in,
+/// so definitions in a block or function body do not leak to the callers.
+#[derive(Clone)]
+pub(crate) struct Env {
+    scope: Rc<Scope>,
+    depth: Rc<Cell<usize>>,
+    sources: Rc<Sources>,
+    /// Path of the imported script the scope belongs to, `None` for the main script
+    file: Option<Rc<str>>,
+    /// Script of the expression being evaluated
+    current_file: Rc<RefCell<Option<Rc<str>>>>,
+}
+
+impl Env {
+    pub(crate) fn new(sources: Sources) -> Self {
+        Self {
+            scope: Rc::new(Scope {
+                vars: RefCell::new(HashMap::new()),
+                parent: None,
+            }),
+            depth: Rc::new(Cell::new(0)),
+            sources: Rc::new(sources),
+            file: None,
+            current_file: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    fn child(&self) -> Self {
+        Self {
+            scope: Rc::new(Scope {
+                vars: RefCell::new(HashMap::new()),
+                parent: Some(self.scope.clone()),
+            }),
+            depth: self.depth.clone(),
+            sources: self.sources.clone(),
+            file: self.file.clone(),
+            current_file: self.current_file.clone(),
+        }
+    }
+
+    /// Same scope with different imported scripts available
+    pub(crate) fn with_sources(&self, sources: Sources) -> Self {
+        Self {
+            scope: self.scope.clone(),
+            depth: self.depth.clone(),
+            sources: Rc::new(sources),
+            file: self.file.clone(),
+            current_file: self.current_file.clone(),
+        }
+    }
+
+    /// Top level scope of an imported script
+    fn root(&self, path: &str) -> Self {
+        Self {
+            scope: Rc::new(Scope {
+                vars: RefCell::new(HashMap::new()),
+                parent: None,
+            }),
+            depth: self.depth.clone(),
+            sources: self.sources.clone(),
+            file: Some(path.into()),
+            current_file: self.current_file.clone(),
+        }
+    }
+
+    /// Finds a variable and the scope it should be evaluated in
+    fn get(&self, name: &str) -> Option<(Variable, Env)> {
+        let mut scope = Some(self.scope.clone());
+        while let Some(s) = scope {
+            if let Some(var) = s.vars.borrow().get(name) {
+                let env = match &var.closure {
+                    Some(closure) => closure.clone(),
+                    None => Self {
+                        scope: s.clone(),
+                        ..self.clone()
+                    },
+                };
+                return Some((var.clone(), env));
+            }
+            scope = s.parent.clone();
+        }
+        None
+    }
+
+    fn insert(&self, name: String, var: Variable) {
+        self.scope.vars.borrow_mut().insert(name, var);
+    }
+
+    fn variables(&self, filter: fn(&Variable) -> bool) -> Vec<(String, Variable)> {
+        self.scope
+            .vars
+            .borrow()
+            .iter()
+            .filter(|(_, var)| filter(var))
+            .map(|(name, var)| (name.clone(), var.clone()))
+            .collect()
+    }
+
+    /// Runs a nested evaluation, failing if the nesting gets too deep (e.g. `f(x) = f(x)`)
+    fn nested<T, F>(&self, name: &str, func: F) -> Result<T, BuildError>
+    where
+        F: FnOnce() -> Result<T, BuildError>,
+    {
+        if self.depth.get() >= MAX_DEPTH {
+            return Err(BuildError::RecursionLimit(name.into()));
+        }
+        self.depth.set(self.depth.get() + 1);
+        let result = func();
+        self.depth.set(self.depth.get() - 1);
+        result
+    }
+
+    /// Makes an error raised in the script of this scope readable in the calling script.
+    /// Errors in an imported script are rendered against its source, errors in the main
+    /// script keep their location.
+    fn attribute(&self, error: BuildError) -> BuildError {
+        match (&self.file, error) {
+            (_, error @ BuildError::InMain(_)) => error,
+            (None, error) => BuildError::InMain(Box::new(error)),
+            (Some(path), error) => {
+                let message = match self.sources.get(path.as_ref()) {
+                    Some(source) => {
+                        Diagnostic::from_build_error(&error, &source.ast).render(&source.source)
+                    }
+                    None => error.to_string(),
+                };
+                BuildError::Import {
+                    path: path.to_string(),
+                    message,
+                }
+            }
+        }
+    }
 }
-type Env = HashMap<String, Variable>;
 
-pub fn build(expr: Expr) -> Result<HashMap<String, EnvelopeFn>, BuildError> {
-    let mut env = Env::new();
-    compile(expr, &mut env)?;
+/// Builds a script without imports
+#[cfg(test)]
+pub fn build(expr: Expr) -> Result<Module, BuildError> {
+    build_with_imports(expr, Sources::new())
+}
+
+/// Builds a script, which imports have been resolved with `imports::resolve`
+pub fn build_with_imports(expr: Expr, sources: Sources) -> Result<Module, BuildError> {
+    let mut imports: Vec<String> = sources.keys().cloned().collect();
+    imports.sort();
+    let check = checker::check(&expr, &sources);
+    if let Some(err) = check.errors.into_iter().next() {
+        return Err(err);
+    }
+    let warnings = check
+        .warnings
+        .iter()
+        .map(|warning| Diagnostic::from_build_error(warning, &expr))
+        .collect();
+
+    let scenes: Vec<Expr> = match &expr.kind {
+        ExprKind::List(_, cons) => cons
+            .iter()
+            .filter(|e| matches!(&e.kind, ExprKind::List(name, _) if name == KW_SCENE))
+            .cloned()
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let env = Env::new(sources);
+    compile(expr, &env)?;
+
+    // Imported definitions are evaluated in the scope of their own script
+    let scope_of = |var: &Variable| var.closure.clone().unwrap_or_else(|| env.clone());
+
+    let mut markers = Vec::new();
+    for (name, marker) in env.variables(|var| var.is_marker) {
+        let time = arg_time(Some(&marker.expr), &scope_of(&marker))?;
+        markers.push(Marker { name, time });
+    }
+    markers.sort_by(|a, b| {
+        a.time
+            .partial_cmp(&b.time)
+            .unwrap()
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    let timeline = Timeline::new(
+        scenes
+            .iter()
+            .map(|expr| scene(expr, &env))
+            .collect::<Result<_, _>>()?,
+    );
 
     let mut exports = HashMap::new();
-    for (name, export) in env.iter().filter(|(_, var)| var.is_export) {
+    for (name, export) in env.variables(|var| var.is_export) {
         exports.insert(
             name.clone(),
-            match compile(export.expr.clone(), &mut env.clone())? {
+            match compile(export.expr.clone(), &scope_of(&export))? {
                 Build::EnvelopeFn(f) => f,
                 x => {
                     return Err(BuildError::InvalidType {
                         expected: "Envelope function".into(),
                         actual: x,
-                    })
+                    }
+                    .at(export.expr.span))
                 }
             },
         );
     }
-    Ok(exports)
+    let program = Program::new(&exports);
+    Ok(Module {
+        exports,
+        markers,
+        timeline,
+        imports,
+        program,
+        signatures: check.signatures,
+        warnings,
+    })
 }
 
-fn compile(expr: Expr, env: &mut Env) -> BuildResult {
-    match expr {
-        Expr::Symbol(s) => match env.get(&s) {
-            Some(var) => compile(var.expr.clone(), env),
+/// Value of an expression in the top level scope of a script, e.g. for hovering it in an editor
+pub(crate) fn evaluate(root: Expr, sources: Sources, expr: Expr) -> BuildResult {
+    let env = Env::new(sources);
+    compile(root, &env)?;
+    compile(expr, &env)
+}
+
+pub(crate) fn compile(expr: Expr, env: &Env) -> BuildResult {
+    if *env.current_file.borrow() == env.file {
+        return compile_expr(expr, env);
+    }
+    let caller = env.current_file.replace(env.file.clone());
+    let result = compile_expr(expr, env);
+    env.current_file.replace(caller);
+    result.map_err(|err| env.attribute(err))
+}
+
+fn compile_expr(expr: Expr, env: &Env) -> BuildResult {
+    let span = expr.span;
+    match expr.kind {
+        ExprKind::Symbol(s) => match env.get(&s) {
+            Some((var, scope)) => match var.params {
+                Some(_) => call(&s, var, scope, vec![], env),
+                None => scope.nested(&s, || compile(var.expr, &scope)),
+            },
             None => Ok(Build::Symbol(s.clone())),
         },
-        Expr::NumberList(n) => Ok(Build::NumberList(n.clone())),
-        Expr::List(name, cons) => list(&name, cons, env),
-        Expr::Comment(_) => Ok(Build::Nil),
+        ExprKind::NumberList(n) => Ok(Build::NumberList(n.clone())),
+        ExprKind::List(name, cons) => list(&name, cons, env),
+        ExprKind::Comment(_) => Ok(Build::Nil),
     }
+    .map_err(|err| err.at(span))
 }
 
-fn list(name: &str, cons: Vec<Expr>, env: &mut Env) -> BuildResult {
+/// Names of the standard library functions
+pub const BUILTINS: &[&str] = &[
+    STD_HOLD,
+    STD_LINEAR,
+    STD_CONCAT,
+    STD_REPEAT,
+    STD_LOOP,
+    STD_AT,
+    STD_EVERY,
+    STD_SPLINE,
+    STD_RANDOM,
+    STD_NOISE,
+    STD_FBM,
+    STD_SIN,
+    STD_COS,
+    STD_ABS,
+    STD_SQRT,
+    STD_FLOOR,
+    STD_FRACT,
+    STD_MIN,
+    STD_MAX,
+    STD_POW,
+    STD_CLAMP,
+    STD_MIX,
+    STD_DELAY,
+    STD_SPEED,
+    STD_STRETCH,
+    STD_REVERSE,
+    STD_PINGPONG,
+    STD_CLIP,
+    STD_SAMPLE_AT,
+    STD_ADD,
+    STD_MUL,
+    STD_CROSSFADE,
+    STD_SELECT,
+    STD_EVENTS,
+    STD_ON_BEAT,
+    STD_DECAY,
+    STD_HSL,
+    STD_OKLAB,
+    STD_LINEAR_COLOR,
+];
+
+fn list(name: &str, cons: Vec<Expr>, env: &Env) -> BuildResult {
     match name {
         KW_ROOT => block(cons, env),
-        KW_BLOCK => block(cons, &mut env.clone()),
+        KW_BLOCK => block(cons, &env.child()),
         KW_DEFINE => define(cons, env, false),
+        KW_FUNCTION => function(cons, env),
+        KW_MARKER => marker(cons, env),
+        KW_BEATS => beats(cons, env, 1.0),
+        KW_BARS => beats(cons, env, beats_per_bar(env)?),
         KW_EXPORT => define(cons, env, true),
         KW_ARRAY => number_list(cons, env),
+        KW_IMPORT => import(cons, env),
+        // Scenes are built after all definitions, see `scene`
+        KW_SCENE => Ok(Build::Nil),
+
+        OP_ADD => math(cons, env, MathOp::Add),
+        OP_SUB => math(cons, env, MathOp::Sub),
+        OP_MUL => math(cons, env, MathOp::Mul),
+        OP_DIV => math(cons, env, MathOp::Div),
+        OP_NEG => math(cons, env, MathOp::Neg),
 
         STD_HOLD => hold(cons, env),
         STD_LINEAR => linear(cons, env),
         STD_CONCAT => concat(cons, env),
         STD_REPEAT => repeat(cons, env),
         STD_LOOP => inf_loop(cons, env),
+        STD_AT => at(cons, env),
+        STD_EVERY => every(cons, env),
+        STD_DELAY => at(cons, env),
+        STD_SPEED => speed(cons, env),
+        STD_STRETCH => stretch(cons, env),
+        STD_REVERSE => reverse(cons, env),
+        STD_PINGPONG => pingpong(cons, env),
+        STD_CLIP => clip(cons, env),
+        STD_SAMPLE_AT => sample_at(cons, env),
+        STD_SPLINE => spline(cons, env),
+        STD_RANDOM => random(cons, env),
+        STD_NOISE => noise(cons, env, 1),
+        STD_FBM => fbm(cons, env),
+
+        STD_SIN => math(cons, env, MathOp::Sin),
+        STD_COS => math(cons, env, MathOp::Cos),
+        STD_ABS => math(cons, env, MathOp::Abs),
+        STD_SQRT => math(cons, env, MathOp::Sqrt),
+        STD_FLOOR => math(cons, env, MathOp::Floor),
+        STD_FRACT => math(cons, env, MathOp::Fract),
+        STD_MIN => math(cons, env, MathOp::Min),
+        STD_MAX => math(cons, env, MathOp::Max),
+        STD_POW => math(cons, env, MathOp::Pow),
+        STD_CLAMP => math(cons, env, MathOp::Clamp),
+        STD_MIX => math(cons, env, MathOp::Mix),
+        STD_ADD => math(cons, env, MathOp::Add),
+        STD_MUL => math(cons, env, MathOp::Mul),
+        STD_CROSSFADE => crossfade(cons, env),
+        STD_SELECT => select(cons, env),
+        STD_EVENTS => events(cons, env),
+        STD_ON_BEAT => on_beat(cons, env),
+        STD_DECAY => decay(cons, env),
+        STD_HSL => color_constructor(cons, env, color::hsl),
+        STD_OKLAB => color_constructor(cons, env, color::oklab),
+        STD_LINEAR_COLOR => linear_color(cons, env),
+
+        _ if Easing::from_name(name).is_some() => ease(cons, env, name),
 
         _ => match env.get(name) {
-            Some(var) => match var.expr.clone() {
-                Expr::List(sub_name, sub_cons) => {
-                    let mut merged_cons = sub_cons.clone();
-                    merged_cons.append(&mut cons.clone());
-                    compile(Expr::list(&sub_name, merged_cons), env)
+            Some((var, scope)) if var.params.is_some() => call(name, var, scope, cons, env),
+            Some((var, scope)) => match var.expr.kind.clone() {
+                // Like the arguments of a function, the appended ones are evaluated in
+                // the scope of the caller
+                ExprKind::List(sub_name, mut merged_cons) => {
+                    let local = scope.child();
+                    for (i, arg) in cons.into_iter().enumerate() {
+                        // Locations in another script are not known where the call is evaluated
+                        let span = match local.file == env.file {
+                            true => arg.span,
+                            false => Span::default(),
+                        };
+                        let name = format!("#{}", i);
+                        local.insert(
+                            name.clone(),
+                            Variable {
+                                closure: Some(env.clone()),
+                                ..Variable::new(arg, false)
+                            },
+                        );
+                        merged_cons.push(Expr {
+                            kind: ExprKind::Symbol(name),
+                            span,
+                        });
+                    }
+                    env.nested(name, || compile(Expr::list(&sub_name, merged_cons), &local))
                 }
-                x => Err(BuildError::NotPartial(x)),
+                _ => Err(BuildError::NotPartial(var.expr)),
             },
             None => Err(BuildError::FunctionNotFound(name.into())),
         },
     }
 }
 
-fn block(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
+/// Calls a user defined function. The body is evaluated in the scope of the
+/// definition, with the arguments bound to the scope of the caller.
+fn call(name: &str, function: Variable, scope: Env, cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let params = function.params.unwrap_or_default();
+    let body = function.expr;
+    if params.len() != cons.len() {
+        return Err(BuildError::ArityMismatch {
+            name: name.into(),
+            expected: params.len(),
+            actual: cons.len(),
+        });
+    }
+
+    let local = scope.child();
+    for (param, arg) in params.into_iter().zip(cons) {
+        local.insert(
+            param,
+            Variable {
+                closure: Some(env.clone()),
+                ..Variable::new(arg, false)
+            },
+        );
+    }
+    local.nested(name, || compile(body, &local))
+}
+
+fn block(cons: Vec<Expr>, env: &Env) -> BuildResult {
     let (init, last) = cons.split_at(cons.len() - 1);
     for expr in init.iter() {
         compile(expr.clone(), env)?;
@@ -110,20 +661,132 @@ fn block(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
     }
 }
 
-fn define(cons: Vec<Expr>, env: &mut Env, is_export: bool) -> BuildResult {
-    let name = arg_symbol(cons.get(0), env)?;
+fn define(cons: Vec<Expr>, env: &Env, is_export: bool) -> BuildResult {
+    let name = arg_name(cons.first())?;
     let expr = arg_expr(cons.get(1))?;
+    env.insert(name, Variable::new(expr.clone(), is_export));
+    Ok(Build::Partial(expr))
+}
+
+fn function(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let name = arg_name(cons.first())?;
+    let params = match cons.get(1) {
+        Some(Expr {
+            kind: ExprKind::List(_, params),
+            ..
+        }) => params
+            .iter()
+            .map(|p| arg_name(Some(p)))
+            .collect::<Result<Vec<String>, BuildError>>()?,
+        _ => return Err(BuildError::MissingArgument),
+    };
+    let expr = arg_expr(cons.get(2))?;
     env.insert(
-        name.clone(),
+        name,
         Variable {
-            expr: expr.clone(),
-            is_export,
+            params: Some(params),
+            ..Variable::new(expr.clone(), false)
         },
     );
     Ok(Build::Partial(expr))
 }
 
-fn number_list(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
+/// Compiles an imported script and binds its definitions, prefixed with `alias.` if given
+fn import(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let path = arg_name(cons.first())?;
+    let alias = match cons.get(1) {
+        Some(_) => Some(arg_name(cons.get(1))?),
+        None => None,
+    };
+    let source = match env.sources.get(&path) {
+        Some(source) => source,
+        None => {
+            return Err(BuildError::Import {
+                path,
+                message: "not resolved".into(),
+            })
+        }
+    };
+
+    // Errors are rendered against the imported source when leaving its scope
+    let module = env.root(&path);
+    compile(source.ast.clone(), &module)?;
+
+    for (name, var) in module.variables(|_| true) {
+        let name = match &alias {
+            Some(alias) => format!("{}.{}", alias, name),
+            None => name,
+        };
+        let closure = var.closure.clone().unwrap_or_else(|| module.clone());
+        env.insert(
+            name,
+            Variable {
+                closure: Some(closure),
+                ..var
+            },
+        );
+    }
+    Ok(Build::Nil)
+}
+
+fn marker(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    for pair in cons.chunks(2) {
+        let name = arg_name(pair.first())?;
+        let expr = arg_expr(pair.get(1))?;
+        env.insert(
+            name,
+            Variable {
+                is_marker: true,
+                ..Variable::new(expr, false)
+            },
+        );
+    }
+    Ok(Build::Nil)
+}
+
+/// Scene of the main script, which times can refer to definitions anywhere in the script
+fn scene(expr: &Expr, env: &Env) -> Result<Scene, BuildError> {
+    let cons = match &expr.kind {
+        ExprKind::List(_, cons) => cons,
+        _ => return Err(BuildError::MissingArgument),
+    };
+    let name = arg_name(cons.first())?;
+    let start = arg_time(cons.get(1), env)?;
+    let end = arg_time(cons.get(2), env)?;
+    if end < start {
+        return Err(BuildError::InvalidScene(name).at(expr.span));
+    }
+    Ok(Scene { name, start, end })
+}
+
+// Musical time: `bpm = 128` defines the tempo for beat (`2b`) and bar (`1bar`) literals.
+// A bar has four beats unless `beats_per_bar` is defined.
+
+pub(crate) const VAR_BPM: &str = "bpm";
+pub(crate) const VAR_BEATS_PER_BAR: &str = "beats_per_bar";
+
+fn beat_duration(env: &Env) -> Result<Number, BuildError> {
+    match env.get(VAR_BPM) {
+        Some(_) => Ok(60.0 / arg_number(Some(&VAR_BPM.into()), env)?),
+        None => Err(BuildError::BpmNotDefined),
+    }
+}
+
+fn beats_per_bar(env: &Env) -> Result<Number, BuildError> {
+    match env.get(VAR_BEATS_PER_BAR) {
+        Some(_) => arg_number(Some(&VAR_BEATS_PER_BAR.into()), env),
+        None => Ok(4.0),
+    }
+}
+
+fn beats(cons: Vec<Expr>, env: &Env, multiplier: Number) -> BuildResult {
+    let count = arg_number(cons.first(), env)?;
+    Ok(Build::NumberList(vec![
+        count * multiplier * beat_duration(env)?,
+    ]))
+}
+
+fn number_list(cons: Vec<Expr>, env: &Env) -> BuildResult {
     let mut arr = Vec::new();
     for expr in cons.iter() {
         match compile(expr.clone(), env)? {
@@ -132,58 +795,131 @@ fn number_list(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
                 return Err(BuildError::InvalidType {
                     expected: "NumberList".into(),
                     actual: x,
-                })
+                }
+                .at(expr.span))
             }
         }
     }
     Ok(Build::NumberList(arr))
 }
 
-fn arg_symbol(expr: Option<&Expr>, env: &mut Env) -> Result<String, BuildError> {
+fn arg_name(expr: Option<&Expr>) -> Result<String, BuildError> {
+    match expr {
+        Some(Expr {
+            kind: ExprKind::Symbol(name),
+            ..
+        }) => Ok(name.clone()),
+        Some(e) => Err(BuildError::InvalidType {
+            expected: "Symbol".into(),
+            actual: Build::Partial(e.clone()),
+        }
+        .at(e.span)),
+        None => Err(BuildError::MissingArgument),
+    }
+}
+
+fn arg_symbol(expr: Option<&Expr>, env: &Env) -> Result<String, BuildError> {
     match expr {
         Some(e) => match compile(e.clone(), env)? {
             Build::Symbol(str) => Ok(str),
-            x => {
-                return Err(BuildError::InvalidType {
-                    expected: "Symbol".into(),
-                    actual: x,
-                })
-            }
-        },
+            x => Err(BuildError::InvalidType {
+                expected: "Symbol".into(),
+                actual: x,
+            }),
+        }
+        .map_err(|err| err.at(e.span)),
         None => Err(BuildError::MissingArgument),
     }
 }
 
-fn arg_number(expr: Option<&Expr>, env: &mut Env) -> Result<Number, BuildError> {
+fn arg_number(expr: Option<&Expr>, env: &Env) -> Result<Number, BuildError> {
     match expr {
         Some(e) => match compile(e.clone(), env)? {
             Build::NumberList(n) if n.len() == 1 => Ok(n[0]),
             Build::Symbol(k) => Err(BuildError::VariableNotFound(k)),
-            x => {
-                return Err(BuildError::InvalidType {
-                    expected: "Number".into(),
-                    actual: x,
-                })
-            }
-        },
+            x => Err(BuildError::InvalidType {
+                expected: "Number".into(),
+                actual: x,
+            }),
+        }
+        .map_err(|err| err.at(e.span)),
         None => Err(BuildError::MissingArgument),
     }
 }
 
-fn arg_number_list(expr: Option<&Expr>, env: &mut Env) -> Result<Vec<Number>, BuildError> {
+/// Number that is used as a point in time, so it must be finite
+fn arg_time(expr: Option<&Expr>, env: &Env) -> Result<Number, BuildError> {
+    let time = arg_number(expr, env)?;
+    match expr {
+        Some(e) if !time.is_finite() => Err(BuildError::InvalidTime(time).at(e.span)),
+        _ => Ok(time),
+    }
+}
+
+/// Length or period of time remapping, which must be positive and finite
+fn check_duration(duration: Number, expr: &Expr) -> Result<Number, BuildError> {
+    if duration > 0.0 && duration.is_finite() {
+        Ok(duration)
+    } else {
+        Err(BuildError::InvalidDuration(duration).at(expr.span))
+    }
+}
+
+/// Envelope that is repeated or remapped by its duration
+fn arg_finite_envelope_fn(expr: Option<&Expr>, env: &Env) -> Result<EnvelopeFn, BuildError> {
+    let envelope_fn = arg_envelope_fn(expr, env)?;
+    if let Some(e) = expr {
+        check_duration(envelope_fn.get_duration(), e)?;
+    }
+    Ok(envelope_fn)
+}
+
+fn arg_number_list(expr: Option<&Expr>, env: &Env) -> Result<Vec<Number>, BuildError> {
     match expr {
         Some(e) => match compile(e.clone(), env)? {
             Build::NumberList(a) => Ok(a),
             Build::Symbol(k) => Err(BuildError::VariableNotFound(k)),
-            x => {
-                return Err(BuildError::InvalidType {
-                    expected: "NumberList".into(),
-                    actual: x,
-                })
+            x => Err(BuildError::InvalidType {
+                expected: "NumberList".into(),
+                actual: x,
+            }),
+        }
+        .map_err(|err| err.at(e.span)),
+        None => Err(BuildError::MissingArgument),
+    }
+}
+
+fn arg_easing(expr: Option<&Expr>, env: &Env) -> Result<Easing, BuildError> {
+    let name = arg_symbol(expr, env)?;
+    Easing::from_name(&name).ok_or_else(|| {
+        let span = expr.map(|e| e.span).unwrap_or_default();
+        BuildError::UnknownEasing(name).at(span)
+    })
+}
+
+fn arg_array(expr: Option<&Expr>, env: &Env) -> Result<Vec<Expr>, BuildError> {
+    let e = match expr {
+        Some(e) => e,
+        None => return Err(BuildError::MissingArgument),
+    };
+    match &e.kind {
+        ExprKind::List(name, items) if name == KW_ARRAY => Ok(items.clone()),
+        ExprKind::Symbol(s) => match env.get(s) {
+            Some((var, scope)) if var.params.is_none() => {
+                scope.nested(s, || arg_array(Some(&var.expr), &scope))
             }
+            Some(_) => Err(BuildError::InvalidType {
+                expected: "Array".into(),
+                actual: compile(e.clone(), env)?,
+            }),
+            None => Err(BuildError::VariableNotFound(s.clone())),
         },
-        None => Err(BuildError::MissingArgument),
+        _ => Err(BuildError::InvalidType {
+            expected: "Array".into(),
+            actual: compile(e.clone(), env)?,
+        }),
     }
+    .map_err(|err| err.at(e.span))
 }
 
 fn arg_expr(expr: Option<&Expr>) -> Result<Expr, BuildError> {
@@ -193,27 +929,47 @@ fn arg_expr(expr: Option<&Expr>) -> Result<Expr, BuildError> {
     }
 }
 
-fn arg_envelope_fn(expr: Option<&Expr>, env: &mut Env) -> Result<EnvelopeFn, BuildError> {
+enum Operand {
+    Constant(Vec<Number>),
+    Envelope(EnvelopeFn),
+}
+
+fn arg_operand(expr: Option<&Expr>, env: &Env) -> Result<Operand, BuildError> {
+    match expr {
+        Some(e) => match compile(e.clone(), env)? {
+            Build::NumberList(a) => Ok(Operand::Constant(a)),
+            Build::EnvelopeFn(f) => Ok(Operand::Envelope(f)),
+            Build::Symbol(k) => Err(BuildError::VariableNotFound(k)),
+            x => Err(BuildError::InvalidType {
+                expected: "NumberList or EnvelopeFunction".into(),
+                actual: x,
+            }),
+        }
+        .map_err(|err| err.at(e.span)),
+        None => Err(BuildError::MissingArgument),
+    }
+}
+
+fn arg_envelope_fn(expr: Option<&Expr>, env: &Env) -> Result<EnvelopeFn, BuildError> {
     match expr {
         Some(e) => match compile(e.clone(), env)? {
             Build::EnvelopeFn(f) => Ok(f),
             Build::Symbol(k) => Err(BuildError::VariableNotFound(k)),
-            x => {
-                return Err(BuildError::InvalidType {
-                    expected: "EnvelopeFunction".into(),
-                    actual: x,
-                })
-            }
-        },
+            x => Err(BuildError::InvalidType {
+                expected: "EnvelopeFunction".into(),
+                actual: x,
+            }),
+        }
+        .map_err(|err| err.at(e.span)),
         None => Err(BuildError::MissingArgument),
     }
 }
 
 // Standard library
 
-const STD_HOLD: &str = "hold";
-fn hold(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
-    let value = arg_number_list(cons.get(0), env)?;
+pub(crate) const STD_HOLD: &str = "hold";
+fn hold(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let value = arg_number_list(cons.first(), env)?;
     let duration = arg_number(cons.get(1), env)?;
 
     Ok(Build::EnvelopeFn(Box::new(envelope::Hold::new(
@@ -222,21 +978,45 @@ fn hold(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
     ))))
 }
 
-const STD_LINEAR: &str = "linear";
-fn linear(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
-    let from = arg_number_list(cons.get(0), env)?;
+pub(crate) const STD_LINEAR: &str = "linear";
+fn linear(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let from = arg_number_list(cons.first(), env)?;
+    let to = arg_number_list(cons.get(1), env)?;
+    let duration = arg_number(cons.get(2), env)?;
+
+    match cons.get(3) {
+        Some(_) => Ok(Build::EnvelopeFn(Box::new(envelope::Ease::new(
+            duration,
+            from.into(),
+            to.into(),
+            arg_easing(cons.get(3), env)?,
+        )))),
+        None => Ok(Build::EnvelopeFn(Box::new(envelope::Linear::new(
+            duration,
+            from.into(),
+            to.into(),
+        )))),
+    }
+}
+
+// Easing functions, e.g. `ease_in_out_cubic(from, to, duration)` or `smoothstep(from, to, duration)`.
+// The same names can be passed as the fourth argument of `linear`.
+fn ease(cons: Vec<Expr>, env: &Env, name: &str) -> BuildResult {
+    let from = arg_number_list(cons.first(), env)?;
     let to = arg_number_list(cons.get(1), env)?;
     let duration = arg_number(cons.get(2), env)?;
+    let easing = Easing::from_name(name).ok_or_else(|| BuildError::UnknownEasing(name.into()))?;
 
-    Ok(Build::EnvelopeFn(Box::new(envelope::Linear::new(
+    Ok(Build::EnvelopeFn(Box::new(envelope::Ease::new(
         duration,
         from.into(),
         to.into(),
+        easing,
     ))))
 }
 
-const STD_CONCAT: &str = "concat";
-fn concat(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
+pub(crate) const STD_CONCAT: &str = "concat";
+fn concat(cons: Vec<Expr>, env: &Env) -> BuildResult {
     let mut fns = Vec::new();
     for expr in cons.iter() {
         match compile(expr.clone(), env)? {
@@ -245,7 +1025,8 @@ fn concat(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
                 return Err(BuildError::InvalidType {
                     expected: "EnvelopeFunction".into(),
                     actual,
-                })
+                }
+                .at(expr.span))
             }
         }
     }
@@ -253,24 +1034,421 @@ fn concat(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
     Ok(Build::EnvelopeFn(Box::new(envelope::Concat::new(fns))))
 }
 
-const STD_REPEAT: &str = "repeat";
-fn repeat(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
-    let repeats = arg_number(cons.get(0), env)? as u32;
-    let envelope_fn = arg_envelope_fn(cons.get(1), env)?;
+pub(crate) const STD_REPEAT: &str = "repeat";
+fn repeat(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let repeats = arg_number(cons.first(), env)? as u32;
+    let envelope_fn = arg_finite_envelope_fn(cons.get(1), env)?;
     Ok(Build::EnvelopeFn(Box::new(envelope::Repeat::new(
         repeats,
         envelope_fn,
     ))))
 }
 
-const STD_LOOP: &str = "loop";
-fn inf_loop(cons: Vec<Expr>, env: &mut Env) -> BuildResult {
-    let envelope_fn = arg_envelope_fn(cons.get(0), env)?;
+pub(crate) const STD_LOOP: &str = "loop";
+fn inf_loop(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let envelope_fn = arg_finite_envelope_fn(cons.first(), env)?;
     Ok(Build::EnvelopeFn(Box::new(envelope::Loop::new(
         envelope_fn,
     ))))
 }
 
+// Starts an envelope at given time, e.g. `at(drop, linear(0, 1, 2))` where `drop` is a marker.
+// Before the start time the envelope holds its initial value.
+pub(crate) const STD_AT: &str = "at";
+fn at(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let time = arg_time(cons.first(), env)?;
+    let envelope_fn = arg_envelope_fn(cons.get(1), env)?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::Delay::new(
+        time,
+        envelope_fn,
+    ))))
+}
+
+// Restarts the envelope on every `beats`th beat
+pub(crate) const STD_EVERY: &str = "every";
+fn every(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let beats = arg_number(cons.first(), env)?;
+    let interval = check_duration(beats * beat_duration(env)?, &cons[0])?;
+    let envelope_fn = arg_envelope_fn(cons.get(1), env)?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::Every::new(
+        interval,
+        envelope_fn,
+    ))))
+}
+
+// Plays `a` and fades into `b` during the last `duration` seconds of `a`
+pub(crate) const STD_CROSSFADE: &str = "crossfade";
+fn crossfade(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let a = arg_envelope_fn(cons.first(), env)?;
+    let b = arg_envelope_fn(cons.get(1), env)?;
+    let duration = arg_number(cons.get(2), env)?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::Crossfade::new(
+        duration, a, b,
+    ))))
+}
+
+// Switches between envelopes, e.g. `select(scene, [camera_a, camera_b])`.
+// The index is rounded down and clamped to the list.
+pub(crate) const STD_SELECT: &str = "select";
+fn select(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let index = arg_envelope_fn(cons.first(), env)?;
+    let mut fns = Vec::new();
+    for item in arg_array(cons.get(1), env)?.iter() {
+        fns.push(arg_envelope_fn(Some(item), env)?);
+    }
+    Ok(Build::EnvelopeFn(Box::new(envelope::Select::new(
+        index, fns,
+    ))))
+}
+
+// Time remapping. `delay(t, env)` is the same as `at(t, env)`.
+pub(crate) const STD_DELAY: &str = "delay";
+
+pub(crate) const STD_SPEED: &str = "speed";
+fn speed(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let factor = arg_number(cons.first(), env)?;
+    if !(factor > 0.0 && factor.is_finite()) {
+        return Err(BuildError::InvalidSpeed(factor).at(cons[0].span));
+    }
+    let envelope_fn = arg_envelope_fn(cons.get(1), env)?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::Speed::new(
+        factor,
+        envelope_fn,
+    ))))
+}
+
+pub(crate) const STD_STRETCH: &str = "stretch";
+fn stretch(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let duration = arg_number(cons.first(), env)?;
+    check_duration(duration, &cons[0])?;
+    let envelope_fn = arg_finite_envelope_fn(cons.get(1), env)?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::Speed::stretch(
+        duration,
+        envelope_fn,
+    ))))
+}
+
+pub(crate) const STD_REVERSE: &str = "reverse";
+fn reverse(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let envelope_fn = arg_finite_envelope_fn(cons.first(), env)?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::Reverse::new(
+        envelope_fn,
+    ))))
+}
+
+pub(crate) const STD_PINGPONG: &str = "pingpong";
+fn pingpong(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let envelope_fn = arg_finite_envelope_fn(cons.first(), env)?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::PingPong::new(
+        envelope_fn,
+    ))))
+}
+
+pub(crate) const STD_CLIP: &str = "clip";
+fn clip(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let start = arg_time(cons.first(), env)?;
+    let end = arg_time(cons.get(1), env)?;
+    let envelope_fn = arg_envelope_fn(cons.get(2), env)?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::Clip::new(
+        start,
+        end,
+        envelope_fn,
+    ))))
+}
+
+pub(crate) const STD_SAMPLE_AT: &str = "sample_at";
+fn sample_at(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let time = arg_time(cons.first(), env)?;
+    let envelope_fn = arg_envelope_fn(cons.get(1), env)?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::SampleAt::new(
+        time,
+        envelope_fn,
+    ))))
+}
+
+// Keyframes are given as `[time, value, ...]` rows, where the rest of the row depends on the mode:
+// - catmull_rom (default): `[time, value]`
+// - bezier: `[time, value, tangent]` or `[time, value, in_tangent, out_tangent]`
+// - tcb: `[time, value, tension, continuity, bias]`, parameters default to zero
+pub(crate) const STD_SPLINE: &str = "spline";
+fn spline(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let rows = arg_array(cons.first(), env)?;
+    let mode = match cons.get(1) {
+        Some(_) => arg_symbol(cons.get(1), env)?,
+        None => "catmull_rom".into(),
+    };
+
+    let mut keyframes = Vec::new();
+    for row in rows.iter() {
+        let items = arg_array(Some(row), env)?;
+        let time = arg_time(items.first(), env)?;
+        let value: Vector = arg_number_list(items.get(1), env)?.into();
+        let mut params = Vec::new();
+        for item in items.iter().skip(2) {
+            params.push(arg_number_list(Some(item), env)?);
+        }
+        keyframes.push((time, value, params));
+    }
+
+    let spline = match mode.as_str() {
+        "catmull_rom" => envelope::Spline::catmull_rom(
+            keyframes
+                .into_iter()
+                .map(|(time, value, _)| (time, value))
+                .collect(),
+        ),
+        "bezier" => envelope::Spline::new(
+            keyframes
+                .into_iter()
+                .map(|(time, value, params)| {
+                    let in_tangent: Vector = params.first().cloned().unwrap_or_default().into();
+                    let out_tangent = match params.get(1) {
+                        Some(tangent) => tangent.clone().into(),
+                        None => in_tangent.clone(),
+                    };
+                    envelope::SplineKey {
+                        time,
+                        value,
+                        in_tangent,
+                        out_tangent,
+                    }
+                })
+                .collect(),
+        ),
+        "tcb" => envelope::Spline::tcb(
+            keyframes
+                .into_iter()
+                .map(|(time, value, params)| {
+                    let param = |i: usize| params.get(i).and_then(|p| p.first()).cloned();
+                    (
+                        time,
+                        value,
+                        [
+                            param(0).unwrap_or(0.0),
+                            param(1).unwrap_or(0.0),
+                            param(2).unwrap_or(0.0),
+                        ],
+                    )
+                })
+                .collect(),
+        ),
+        _ => return Err(BuildError::UnknownSplineMode(mode)),
+    };
+
+    Ok(Build::EnvelopeFn(Box::new(spline)))
+}
+
+// Discrete events, e.g. `events([0.5, 1, 1.75], decay(0.1))` for flashes on kick drum hits.
+// The last argument is a shape envelope started at each event, `since` for the time since the
+// latest event or `index` for the number of the latest event. It defaults to `decay(0.1)`.
+pub(crate) const STD_EVENTS: &str = "events";
+fn events(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let times = arg_number_list(cons.first(), env)?;
+    if let Some(time) = times.iter().find(|time| !time.is_finite()) {
+        return Err(BuildError::InvalidTime(*time).at(cons[0].span));
+    }
+    let output = arg_event_output(cons.get(1), env)?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::Events::new(
+        times, output,
+    ))))
+}
+
+// Events repeating forever, one pattern step per beat of `bpm`. Zeros are rests and other
+// numbers scale the shape, e.g. `on_beat(128, [1, 0, 0.5, 0])`.
+pub(crate) const STD_ON_BEAT: &str = "on_beat";
+fn on_beat(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let bpm = arg_number(cons.first(), env)?;
+    let step = check_duration(60.0 / bpm, &cons[0])?;
+    let pattern = arg_number_list(cons.get(1), env)?;
+    let output = arg_event_output(cons.get(2), env)?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::Events::pattern(
+        step, &pattern, output,
+    ))))
+}
+
+// Exponential decay from 1 to 0, halving every `half_life` seconds
+pub(crate) const STD_DECAY: &str = "decay";
+fn decay(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let half_life = arg_number(cons.first(), env)?;
+    check_duration(half_life, &cons[0])?;
+    Ok(Build::EnvelopeFn(Box::new(envelope::Decay::new(half_life))))
+}
+
+const DEFAULT_HALF_LIFE: Number = 0.1;
+
+fn arg_event_output(
+    expr: Option<&Expr>,
+    env: &Env,
+) -> Result<envelope::EventOutput<EnvelopeFn>, BuildError> {
+    let e = match expr {
+        Some(e) => e,
+        None => {
+            let shape = envelope::Decay::new(DEFAULT_HALF_LIFE);
+            return Ok(envelope::EventOutput::Shape(Box::new(shape)));
+        }
+    };
+    match compile(e.clone(), env)? {
+        Build::EnvelopeFn(f) => Ok(envelope::EventOutput::Shape(f)),
+        Build::Symbol(name) => match name.as_str() {
+            "since" => Ok(envelope::EventOutput::Since),
+            "index" => Ok(envelope::EventOutput::Index),
+            _ => Err(BuildError::UnknownEventOutput(name)),
+        },
+        x => Err(BuildError::InvalidType {
+            expected: "EnvelopeFunction".into(),
+            actual: x,
+        }),
+    }
+    .map_err(|err| err.at(e.span))
+}
+
+// Colours are lists of sRGB components in range 0..1 with an optional alpha, like `#ff8800`
+// or `#ff880080`. `hsl(hue, saturation, lightness)` takes the hue in degrees and
+// `oklab(lightness, a, b)` uses the perceptual Oklab space. Both take alpha as a fourth argument.
+pub(crate) const STD_HSL: &str = "hsl";
+pub(crate) const STD_OKLAB: &str = "oklab";
+fn color_constructor<F>(cons: Vec<Expr>, env: &Env, convert: F) -> BuildResult
+where
+    F: Fn(Number, Number, Number) -> [Number; 3],
+{
+    let x = arg_number(cons.first(), env)?;
+    let y = arg_number(cons.get(1), env)?;
+    let z = arg_number(cons.get(2), env)?;
+    let mut components = convert(x, y, z).to_vec();
+    if cons.get(3).is_some() {
+        components.push(arg_number(cons.get(3), env)?);
+    }
+    Ok(Build::NumberList(components))
+}
+
+// Fade between colours, interpolated in `oklab` (default), `hsl` or `linear_rgb`,
+// e.g. `linear_color(#ff8800, #2040c0, 2b, hsl)`. A plain `linear` fades in sRGB, which
+// gives muddy midpoints.
+pub(crate) const STD_LINEAR_COLOR: &str = "linear_color";
+fn linear_color(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let from = arg_number_list(cons.first(), env)?;
+    let to = arg_number_list(cons.get(1), env)?;
+    let duration = arg_number(cons.get(2), env)?;
+    let space = match cons.get(3) {
+        Some(e) => {
+            let name = arg_symbol(Some(e), env)?;
+            ColorSpace::from_name(&name)
+                .ok_or_else(|| BuildError::UnknownColorSpace(name).at(e.span))?
+        }
+        None => ColorSpace::Oklab,
+    };
+
+    Ok(Build::EnvelopeFn(Box::new(envelope::ColorFade::new(
+        duration,
+        from.into(),
+        to.into(),
+        space,
+    ))))
+}
+
+// Random functions are deterministic: the same seed always gives the same output
+
+pub(crate) const STD_RANDOM: &str = "random";
+fn random(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let seed = arg_number(cons.first(), env)? as u64;
+    let min = arg_number_list(cons.get(1), env)?;
+    let max = arg_number_list(cons.get(2), env)?;
+    let interval = arg_number(cons.get(3), env)?;
+    check_duration(interval, &cons[3])?;
+
+    Ok(Build::EnvelopeFn(Box::new(envelope::Random::new(
+        seed,
+        min.into(),
+        max.into(),
+        interval,
+    ))))
+}
+
+pub(crate) const STD_NOISE: &str = "noise";
+fn noise(cons: Vec<Expr>, env: &Env, octaves: u32) -> BuildResult {
+    let seed = arg_number(cons.first(), env)? as u64;
+    let frequency = arg_number(cons.get(1), env)?;
+    let amplitude = arg_number_list(cons.get(2), env)?;
+
+    Ok(Build::EnvelopeFn(Box::new(envelope::Noise::new(
+        seed,
+        frequency,
+        amplitude.into(),
+        octaves,
+    ))))
+}
+
+pub(crate) const STD_FBM: &str = "fbm";
+fn fbm(cons: Vec<Expr>, env: &Env) -> BuildResult {
+    let octaves = match cons.get(3) {
+        Some(_) => arg_number(cons.get(3), env)? as u32,
+        None => 4,
+    };
+    noise(cons, env, octaves)
+}
+
+// Math functions operate component-wise on number lists and envelopes. Single
+// numbers are broadcast to every component, so `[1, 2] * 2` equals `[2, 4]`.
+// If every operand is a number list the result is folded into a number list,
+// otherwise the operands are combined per sample into a new envelope.
+
+pub(crate) const STD_SIN: &str = "sin";
+pub(crate) const STD_COS: &str = "cos";
+pub(crate) const STD_ABS: &str = "abs";
+pub(crate) const STD_SQRT: &str = "sqrt";
+pub(crate) const STD_FLOOR: &str = "floor";
+pub(crate) const STD_FRACT: &str = "fract";
+pub(crate) const STD_MIN: &str = "min";
+pub(crate) const STD_MAX: &str = "max";
+pub(crate) const STD_POW: &str = "pow";
+pub(crate) const STD_CLAMP: &str = "clamp";
+pub(crate) const STD_MIX: &str = "mix";
+pub(crate) const STD_ADD: &str = "add";
+pub(crate) const STD_MUL: &str = "mul";
+
+fn math(cons: Vec<Expr>, env: &Env, op: MathOp) -> BuildResult {
+    let arity = op.arity();
+    let func = op.func();
+    let mut operands = Vec::new();
+    for i in 0..arity {
+        operands.push(arg_operand(cons.get(i), env)?);
+    }
+
+    if operands.iter().all(|a| matches!(a, Operand::Constant(_))) {
+        let lists: Vec<Vec<Number>> = operands
+            .into_iter()
+            .map(|a| match a {
+                Operand::Constant(list) => list,
+                Operand::Envelope(_) => unreachable!(),
+            })
+            .collect();
+        let len = lists.iter().map(|a| a.len()).max().unwrap_or(0);
+        let mut args = Vec::with_capacity(arity);
+        let mut result = Vec::with_capacity(len);
+        for i in 0..len {
+            args.clear();
+            args.extend(lists.iter().map(|a| match a.len() {
+                1 => a[0],
+                _ => *a.get(i).unwrap_or(&0.0),
+            }));
+            result.push((func.value)(&args));
+        }
+        Ok(Build::NumberList(result))
+    } else {
+        let fns = operands
+            .into_iter()
+            .map(|a| match a {
+                Operand::Constant(list) if list.len() == 1 => {
+                    Box::new(envelope::Hold::new(0.0, Vector::splat(list[0]))) as EnvelopeFn
+                }
+                Operand::Constant(list) => Box::new(envelope::Hold::new(0.0, list.into())),
+                Operand::Envelope(f) => f,
+            })
+            .collect();
+        Ok(Build::EnvelopeFn(Box::new(envelope::Apply::new(fns, func))))
+    }
+}
+
 #[test]
 fn build1() {
     use crate::ast::Expr;
@@ -280,12 +1458,12 @@ fn build1() {
         vec![
             Expr::list(KW_DEFINE, vec!["length".into(), 4.0.into()]),
             Expr::list(KW_DEFINE, vec!["value".into(), 1.0.into()]),
-            Expr::list("hold", vec!["length".into(), "value".into()]),
+            Expr::list("hold", vec!["value".into(), "length".into()]),
         ],
     );
 
-    let mut env = HashMap::new();
-    let result = compile(ast, &mut env);
+    let env = Env::new(Sources::new());
+    let result = compile(ast, &env);
 
     match result {
         Ok(c) => match c {
@@ -298,3 +1476,585 @@ fn build1() {
         _ => panic!("Failure of life"),
     }
 }
+
+#[cfg(test)]
+fn build_source(source: &str) -> HashMap<String, EnvelopeFn> {
+    build(crate::parser::parse(source).unwrap())
+        .unwrap()
+        .exports
+}
+
+#[test]
+fn arithmetic() {
+    let exports = build_source(
+        "base = [1, 2]\n\
+         out a = hold(base * 2 + 1, 1)\n\
+         out b = hold(0.5, 1) + linear(0, 1, 2) * 2\n\
+         out c = -linear(0, 1, 2) / 2",
+    );
+
+    assert_eq!(exports["a"].get_value(0.0).to_f2(), (3.0, 5.0));
+    assert_eq!(exports["b"].get_duration(), 2.0);
+    assert_eq!(exports["b"].get_value(1.0).to_f(), 1.5);
+    assert_eq!(exports["c"].get_value(1.0).to_f(), -0.25);
+}
+
+#[test]
+fn math_functions() {
+    let exports = build_source(
+        "out a = hold(clamp([-1, 0.5, 2], 0, 1), 1)\n\
+         out b = hold(mix(10, 20, 0.25) + pow(2, 3) + abs(-1), 1)\n\
+         out c = sin(linear(0, 3, 1))\n\
+         out d = max(linear(0, 2, 2), 1)",
+    );
+
+    assert_eq!(exports["a"].get_value(0.0).to_f3(), (0.0, 0.5, 1.0));
+    assert_eq!(exports["b"].get_value(0.0).to_f(), 21.5);
+    assert_eq!(exports["c"].get_value(0.5).to_f(), 1.5f64.sin());
+    assert_eq!(exports["d"].get_value(0.5).to_f(), 1.0);
+    assert_eq!(exports["d"].get_value(1.5).to_f(), 1.5);
+}
+
+#[test]
+fn math_derivatives() {
+    let exports = build_source(
+        "a = linear(0, 2, 2)\n\
+         out b = sin(a) * 3 - a / linear(1, 3, 2)\n\
+         out c = pow(a + 1, a) + sqrt(a) + mix(a, 2, a) + clamp(a * 2, 1, 3)\n\
+         out d = min(a, 1) - max(cos(a), 0.5) + abs(-a) + fract(a * 3)",
+    );
+
+    for name in ["b", "c", "d"].iter() {
+        for time in [0.13, 0.47, 0.8, 1.1, 1.7].iter() {
+            let h = 1e-6;
+            let value = |t: f64| exports[*name].get_value(t).to_f();
+            let numeric = (value(time + h) - value(time - h)) / (2.0 * h);
+            let analytic = exports[*name].get_velocity(*time).to_f();
+            assert!(
+                (numeric - analytic).abs() < 1e-4,
+                "{} at {}: {} != {}",
+                name,
+                time,
+                analytic,
+                numeric
+            );
+        }
+    }
+}
+
+#[test]
+fn mixing() {
+    let exports = build_source(
+        "a = linear(0, 2, 2)\n\
+         b = hold(10, 2)\n\
+         out mixed = mix(a, b, linear(0, 1, 2))\n\
+         out sum = add(a, mul(b, 2))\n\
+         out faded = crossfade(a, b, 1)\n\
+         out selected = select(concat(hold(0, 1), hold(1, 1)), [a, b])",
+    );
+
+    assert_eq!(exports["mixed"].get_value(1.0).to_f(), 5.5);
+    assert_eq!(exports["sum"].get_value(1.0).to_f(), 21.0);
+    assert_eq!(exports["faded"].get_duration(), 3.0);
+    assert_eq!(exports["faded"].get_value(1.5).to_f(), 5.75);
+    assert_eq!(exports["selected"].get_value(0.5).to_f(), 0.5);
+    assert_eq!(exports["selected"].get_value(1.5).to_f(), 10.0);
+}
+
+#[test]
+fn easings() {
+    let exports = build_source(
+        "out a = ease_in_quad(0, 4, 2)\n\
+         out b = linear(0, 4, 2, ease_out_quad)\n\
+         out c = concat(smoothstep([0, 1], [1, 0], 1), hold([1, 0], 1))",
+    );
+
+    assert_eq!(exports["a"].get_value(1.0).to_f(), 1.0);
+    assert_eq!(exports["b"].get_value(1.0).to_f(), 3.0);
+    assert_eq!(exports["c"].get_duration(), 2.0);
+    assert_eq!(exports["c"].get_value(0.5).to_f2(), (0.5, 0.5));
+
+    let exports = build_source("out a = ease_in_out_cubic(1, 3, 0)");
+    assert_eq!(exports["a"].get_value(0.0).to_f(), 3.0);
+    assert_eq!(exports["a"].get_velocity(0.0).to_f(), 0.0);
+
+    let source = "out a = linear(0, 1, 1, ease_in_wobble)";
+    match build(crate::parser::parse(source).unwrap())
+        .as_ref()
+        .map_err(BuildError::cause)
+    {
+        Err(BuildError::UnknownEasing(name)) => assert_eq!(name, "ease_in_wobble"),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
+
+#[test]
+fn splines() {
+    let exports = build_source(
+        "keys = [[0, [0, 0]], [1, [1, 2]], [2, [2, 4]]]\n\
+         out a = spline(keys)\n\
+         out b = spline([[0, 0, 0], [2, 1, 0]], bezier)\n\
+         out c = spline([[0, 0], [1, 1, 1], [2, 0]], tcb)",
+    );
+
+    assert_eq!(exports["a"].get_duration(), 2.0);
+    assert_eq!(exports["a"].get_value(0.5).to_f2(), (0.5, 1.0));
+    assert_eq!(exports["a"].get_value(2.0).to_f2(), (2.0, 4.0));
+    assert_eq!(exports["b"].get_value(1.0).to_f(), 0.5);
+    assert_eq!(exports["c"].get_value(1.0).to_f(), 1.0);
+
+    let source = "out a = spline([[0, 0], [1, 1]], cubic)";
+    match build(crate::parser::parse(source).unwrap())
+        .as_ref()
+        .map_err(BuildError::cause)
+    {
+        Err(BuildError::UnknownSplineMode(mode)) => assert_eq!(mode, "cubic"),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+
+    let source = "out a = spline([[0/0, 1], [1, 2], [2, 3]])";
+    match build(crate::parser::parse(source).unwrap()) {
+        Err(err) => {
+            assert!(matches!(err.cause(), BuildError::InvalidTime(t) if t.is_nan()));
+            let span = err.span().unwrap();
+            assert_eq!(&source[span.start..span.end], "0/0");
+        }
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
+
+#[test]
+fn triggers() {
+    let exports = build_source(
+        "bpm = 120\n\
+         kicks = [0, 1b, 2b, 3.5b]\n\
+         out flash = events(kicks, linear(1, 0, 0.25))\n\
+         out elapsed = events(kicks, since)\n\
+         out count = events(kicks, index)\n\
+         out strobe = on_beat(bpm * 2, [1, 0.5])\n\
+         out bump = on_beat(bpm, [0, 1], hold([0, 2], 1))",
+    );
+
+    assert_eq!(exports["flash"].get_value(0.125).to_f(), 0.5);
+    assert_eq!(exports["flash"].get_value(0.375).to_f(), 0.0);
+    assert_eq!(exports["elapsed"].get_value(1.25).to_f(), 0.25);
+    assert_eq!(exports["count"].get_value(1.25).to_f(), 2.0);
+    assert_eq!(exports["count"].get_value(10.0).to_f(), 3.0);
+    assert_eq!(exports["strobe"].get_value(0.25).to_f(), 0.5);
+    assert!((exports["strobe"].get_value(0.35).to_f() - 0.25).abs() < 1e-9);
+    assert_eq!(exports["bump"].get_value(0.25).to_f2(), (0.0, 0.0));
+    assert_eq!(exports["bump"].get_value(1.75).to_f2(), (0.0, 2.0));
+
+    let source = "out a = events([1], sinse)";
+    match build(crate::parser::parse(source).unwrap())
+        .as_ref()
+        .map_err(BuildError::cause)
+    {
+        Err(BuildError::UnknownEventOutput(name)) => assert_eq!(name, "sinse"),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+
+    let source = "out a = events([1, 0/0, 2])";
+    match build(crate::parser::parse(source).unwrap()) {
+        Err(err) => {
+            assert!(matches!(err.cause(), BuildError::InvalidTime(t) if t.is_nan()));
+            let span = err.span().unwrap();
+            assert_eq!(&source[span.start..span.end], "[1, 0/0, 2]");
+        }
+        x => panic!("Unexpected result: {:?}", x),
+    }
+
+    for source in ["out a = decay(0)", "out a = events([1, 2], decay(-1))"].iter() {
+        match build(crate::parser::parse(source).unwrap())
+            .as_ref()
+            .map_err(BuildError::cause)
+        {
+            Err(BuildError::InvalidDuration(_)) => (),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+}
+
+#[test]
+fn colors() {
+    let exports = build_source(
+        "orange = #ff8000\n\
+         out a = hold(orange, 1)\n\
+         out b = hold(hsl(240, 1, 0.5, 0.5), 1)\n\
+         out c = linear_color(#ff0000, #00ff00, 2, hsl)\n\
+         out d = linear_color(#000000ff, oklab(1, 0, 0, 0), 1)",
+    );
+
+    let close = |a: Vector, b: [f64; 4]| (0..4).all(|i| (a.0[i] - b[i]).abs() < 1e-6);
+    assert!(close(
+        exports["a"].get_value(0.0),
+        [1.0, 128.0 / 255.0, 0.0, 0.0]
+    ));
+    assert!(close(exports["b"].get_value(0.0), [0.0, 0.0, 1.0, 0.5]));
+    assert!(close(exports["c"].get_value(1.0), [1.0, 1.0, 0.0, 0.0]));
+    assert!(close(exports["d"].get_value(1.0), [1.0, 1.0, 1.0, 0.0]));
+    assert!((exports["d"].get_value(0.5).0[3] - 0.5).abs() < 1e-9);
+
+    let source = "out a = linear_color(#000000, #ffffff, 1, lab)";
+    match build(crate::parser::parse(source).unwrap())
+        .as_ref()
+        .map_err(BuildError::cause)
+    {
+        Err(BuildError::UnknownColorSpace(name)) => assert_eq!(name, "lab"),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
+
+#[test]
+fn randoms() {
+    let source = "out shake = noise(1, 4, [0.1, 0.1]) + [0, 2]\n\
+                  out flicker = random(2, 0.5, 1, 0.1)\n\
+                  out drift = fbm(3, 0.5, 2, 6)";
+    let a = build_source(source);
+    let b = build_source(source);
+
+    for i in 0..20 {
+        let t = i as f64 * 0.37;
+        for key in ["shake", "flicker", "drift"].iter() {
+            assert_eq!(a[*key].get_value(t), b[*key].get_value(t));
+        }
+        let (x, y) = a["shake"].get_value(t).to_f2();
+        assert!(x.abs() <= 0.1 && (y - 2.0).abs() <= 0.1);
+        assert!((0.5..1.0).contains(&a["flicker"].get_value(t).to_f()));
+    }
+
+    for source in ["out a = random(1, 0, 1, 0)", "out a = random(1, 0, 1, -1)"].iter() {
+        match build(crate::parser::parse(source).unwrap()) {
+            Err(err) => {
+                assert!(matches!(err.cause(), BuildError::InvalidDuration(_)));
+                let span = err.span().unwrap();
+                assert_eq!(span.end, source.len() - 1);
+            }
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+}
+
+#[test]
+fn functions() {
+    let exports = build_source(
+        "fade(a, b, d) = concat(linear(a, b, d), hold(b, hold_time))\n\
+         hold_time = 1\n\
+         twice(x) = x * 2\n\
+         out a = fade(0, 1, 2)\n\
+         out b = {\n\
+            b = 10\n\
+            d = 100\n\
+            fade(d, b, twice(twice(1)))\n\
+         }\n\
+         out c = {\n\
+            hold_time = 5\n\
+            fade(1, 0, 1)\n\
+         }",
+    );
+
+    assert_eq!(exports["a"].get_duration(), 3.0);
+    assert_eq!(exports["a"].get_value(1.0).to_f(), 0.5);
+    assert_eq!(exports["b"].get_duration(), 5.0);
+    assert_eq!(exports["b"].get_value(2.0).to_f(), 55.0);
+    assert_eq!(exports["c"].get_duration(), 2.0);
+}
+
+#[test]
+fn function_errors() {
+    let build_error = |source: &str| match build(crate::parser::parse(source).unwrap()) {
+        Err(err) => err,
+        Ok(_) => panic!("Expected an error"),
+    };
+
+    match build_error("f(a, b) = hold(a, b)\nout x = f(1)").cause() {
+        BuildError::ArityMismatch {
+            name,
+            expected,
+            actual,
+        } => assert_eq!((name.as_str(), *expected, *actual), ("f", 2, 1)),
+        x => panic!("Unexpected error: {:?}", x),
+    }
+
+    match build_error("f(a) = f(a)\nout x = f(1)").cause() {
+        BuildError::RecursionLimit(name) => assert_eq!(name, "f"),
+        x => panic!("Unexpected error: {:?}", x),
+    }
+
+    match build_error("a = b + 1\nb = a\nout x = hold(a, 1)").cause() {
+        BuildError::RecursionLimit(_) => (),
+        x => panic!("Unexpected error: {:?}", x),
+    }
+}
+
+#[test]
+fn markers() {
+    let module = build(
+        crate::parser::parse(
+            "markers { intro: 0, drop: 32.5, outro: drop + 60 }\n\
+             marker break = 16\n\
+             out fade = at(drop, linear(0, 1, 2))",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+
+    let markers: Vec<(&str, f64)> = module
+        .markers
+        .iter()
+        .map(|m| (m.name.as_str(), m.time))
+        .collect();
+    assert_eq!(
+        markers,
+        vec![
+            ("intro", 0.0),
+            ("break", 16.0),
+            ("drop", 32.5),
+            ("outro", 92.5)
+        ]
+    );
+
+    let fade = &module.exports["fade"];
+    assert_eq!(fade.get_duration(), 34.5);
+    assert_eq!(fade.get_value(10.0).to_f(), 0.0);
+    assert_eq!(fade.get_value(33.5).to_f(), 0.5);
+    assert_eq!(fade.get_value(40.0).to_f(), 1.0);
+
+    let source = "marker a = 1\nmarker b = 0/0";
+    match build(crate::parser::parse(source).unwrap()) {
+        Err(err) => {
+            assert!(matches!(err.cause(), BuildError::InvalidTime(t) if t.is_nan()));
+            let span = err.span().unwrap();
+            assert_eq!(&source[span.start..span.end], "0/0");
+        }
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
+
+#[test]
+fn scenes() {
+    let module = build(
+        crate::parser::parse(
+            "bpm = 120\n\
+             scene \"tunnel\" from drop to drop + 8bar\n\
+             scene \"intro\" from 0 to drop\n\
+             marker drop = 12",
+        )
+        .unwrap(),
+    )
+    .unwrap();
+
+    let scenes: Vec<(&str, f64, f64)> = module
+        .timeline
+        .scenes()
+        .iter()
+        .map(|s| (s.name.as_str(), s.start, s.end))
+        .collect();
+    assert_eq!(scenes, vec![("intro", 0.0, 12.0), ("tunnel", 12.0, 28.0)]);
+    assert_eq!(
+        module.timeline.at("tunnel", 16.0).map(|t| t.progress),
+        Some(0.25)
+    );
+
+    let source = "scene \"outro\" from 60 to 50";
+    match build(crate::parser::parse(source).unwrap())
+        .as_ref()
+        .map_err(BuildError::cause)
+    {
+        Err(BuildError::InvalidScene(name)) => assert_eq!(name, "outro"),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+
+    let source = "scene \"outro\" from 0/0 to 50";
+    match build(crate::parser::parse(source).unwrap())
+        .as_ref()
+        .map_err(BuildError::cause)
+    {
+        Err(BuildError::InvalidTime(time)) => assert!(time.is_nan()),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
+
+#[test]
+fn time_remapping() {
+    let exports = build_source(
+        "move = linear(0, 1, 2)\n\
+         out a = speed(2, move)\n\
+         out b = stretch(4, move)\n\
+         out c = concat(move, reverse(move))\n\
+         out d = pingpong(move)\n\
+         out e = delay(1, clip(0.5, 1.5, move))\n\
+         out f = sample_at(1, move)",
+    );
+
+    assert_eq!(exports["a"].get_duration(), 1.0);
+    assert_eq!(exports["a"].get_value(0.5).to_f(), 0.5);
+    assert_eq!(exports["b"].get_duration(), 4.0);
+    assert_eq!(exports["b"].get_value(1.0).to_f(), 0.25);
+    assert_eq!(exports["c"].get_duration(), 4.0);
+    assert_eq!(exports["c"].get_value(3.0).to_f(), 0.5);
+    assert_eq!(exports["d"].get_value(5.0).to_f(), 0.5);
+    assert_eq!(exports["e"].get_duration(), 2.0);
+    assert_eq!(exports["e"].get_value(0.0).to_f(), 0.25);
+    assert_eq!(exports["e"].get_value(1.5).to_f(), 0.5);
+    assert_eq!(exports["f"].get_value(0.0).to_f(), 0.5);
+
+    for (source, span) in [
+        ("bpm = 120\nout a = every(0, linear(0, 1, 1))", "0"),
+        (
+            "out a = reverse(loop(linear(0, 1, 1)))",
+            "loop(linear(0, 1, 1))",
+        ),
+        ("out a = loop(hold(1, 0))", "hold(1, 0)"),
+        ("out a = stretch(-1, linear(0, 1, 1))", "-1"),
+        (
+            "out a = pingpong(at(1, loop(linear(0, 1, 1))))",
+            "at(1, loop(linear(0, 1, 1)))",
+        ),
+    ]
+    .iter()
+    {
+        match build(crate::parser::parse(source).unwrap()) {
+            Err(err) => {
+                assert!(
+                    matches!(err.cause(), BuildError::InvalidDuration(_)),
+                    "{}",
+                    source
+                );
+                let s = err.span().unwrap();
+                assert_eq!(&source[s.start..s.end], *span);
+            }
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+    let source = "out a = speed(0, linear(0, 1, 1))";
+    match build(crate::parser::parse(source).unwrap())
+        .as_ref()
+        .map_err(BuildError::cause)
+    {
+        Err(BuildError::InvalidSpeed(factor)) => assert_eq!(*factor, 0.0),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
+
+#[test]
+fn imports() {
+    let files: HashMap<&str, &str> = vec![
+        (
+            "timing.boe",
+            "bpm = 120\nfade = 2b\nmarker drop = 4\nout pulse = every(1, linear(1, 0, 0.5))",
+        ),
+        (
+            "lib/palette.boe",
+            "import \"../timing.boe\"\nred = [1, 0, 0]\ntint(c) = mix(c, red, 0.5)",
+        ),
+        (
+            "pulse.boe",
+            "// Pulses that repeat for the whole demo, e.g. to flash the screen\n\
+             f(x) = loop(hold(1, x))\n\
+             g(x) = loop(x)\n\
+             length = 2\n\
+             fade = linear(0, 1, length)",
+        ),
+    ]
+    .into_iter()
+    .collect();
+    let build_main = |source: &str| {
+        let mut ast = crate::parser::parse(source).unwrap();
+        let mut load = |path: &str| {
+            files
+                .get(path)
+                .map(|source| source.to_string())
+                .ok_or_else(|| "not found".to_string())
+        };
+        let sources = crate::imports::resolve(&mut ast, "main.boe", &mut load)?;
+        build_with_imports(ast, sources)
+    };
+
+    let module = build_main(
+        "import \"timing.boe\"\n\
+         import \"lib/palette.boe\" as pal\n\
+         red = [0, 1, 0]\n\
+         out a = linear(0, 1, fade)\n\
+         out b = hold(pal.tint([0, 0, 1]), 1)\n\
+         out c = hold(red, pal.fade)",
+    )
+    .unwrap();
+    assert_eq!(module.imports, vec!["lib/palette.boe", "timing.boe"]);
+    assert_eq!(module.exports["a"].get_duration(), 1.0);
+    assert_eq!(module.exports["b"].get_value(0.0).to_f3(), (0.5, 0.0, 0.5));
+    assert_eq!(module.exports["c"].get_value(0.0).to_f3(), (0.0, 1.0, 0.0));
+    assert_eq!(module.exports["pulse"].get_value(0.25).to_f(), 0.5);
+    assert!(module.exports.contains_key("pal.pulse"));
+    assert_eq!(module.markers[0].name, "drop");
+
+    match build_main("import \"lib/palette.boe\" as pal\nout a = hold(red, 1)")
+        .as_ref()
+        .map_err(BuildError::cause)
+    {
+        Err(BuildError::VariableNotFound(name)) => assert_eq!(name, "red"),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+
+    // Errors in imported definitions are rendered against the imported script
+    let source = "import \"pulse.boe\"\nout a = f(0)";
+    let err = build_main(source).unwrap_err();
+    let span = err.span().unwrap();
+    assert_eq!(&source[span.start..span.end], "f(0)");
+    match err.cause() {
+        BuildError::Import { path, message } => {
+            assert_eq!(path, "pulse.boe");
+            assert!(message.starts_with("duration must be"), "{}", message);
+            assert!(message.contains(" at 2:13\n"), "{}", message);
+        }
+        x => panic!("Unexpected error: {:?}", x),
+    }
+
+    // ...and errors in arguments from the main script against the main script
+    let source = "import \"pulse.boe\"\nout a = g(loop(hold(1, 0)))";
+    let err = build_main(source).unwrap_err();
+    assert!(matches!(err.cause(), BuildError::InvalidDuration(_)));
+    let span = err.span().unwrap();
+    assert_eq!(&source[span.start..span.end], "hold(1, 0)");
+
+    // Appended arguments are evaluated where the definition is called
+    let module =
+        build_main("import \"pulse.boe\"\nlength = 4\nout a = fade(ease_in_quad)").unwrap();
+    assert_eq!(module.exports["a"].get_duration(), 2.0);
+    assert_eq!(module.exports["a"].get_value(1.0).to_f(), 0.25);
+    let err = build_main("import \"pulse.boe\"\nout a = fade(ease_in_quadd)").unwrap_err();
+    assert!(matches!(err.cause(), BuildError::Import { .. }));
+    assert_eq!(err.span(), Some(Span { start: 27, end: 46 }));
+}
+
+#[test]
+fn musical_time() {
+    let exports = build_source(
+        "bpm = 120\n\
+         out a = hold(1, 2b)\n\
+         out b = linear(0, 1, 1bar)\n\
+         out c = hold(1, 500ms + 1s)\n\
+         out d = every(2, linear(0, 1, 1b))\n\
+         out e = {\n\
+            bpm = 60\n\
+            beats_per_bar = 3\n\
+            hold(0, 1bar)\n\
+         }",
+    );
+
+    assert_eq!(exports["a"].get_duration(), 1.0);
+    assert_eq!(exports["b"].get_duration(), 2.0);
+    assert_eq!(exports["c"].get_duration(), 1.5);
+    assert_eq!(exports["d"].get_value(0.25).to_f(), 0.5);
+    assert_eq!(exports["d"].get_value(0.75).to_f(), 1.0);
+    assert_eq!(exports["d"].get_value(1.25).to_f(), 0.5);
+    assert_eq!(exports["e"].get_duration(), 3.0);
+
+    match build(crate::parser::parse("out a = hold(1, 2b)").unwrap())
+        .as_ref()
+        .map_err(BuildError::cause)
+    {
+        Err(BuildError::BpmNotDefined) => (),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+}
@@ -0,0 +1,317 @@
+// Human readable error messages with source locations and suggestions
+
+use crate::{
+    ast::*,
+    compiler::{BuildError, BUILTINS},
+    easing::Easing,
+};
+use peg::{error::ParseError, str::LineCol};
+
+pub(crate) const SPLINE_MODES: [&str; 3] = ["catmull_rom", "bezier", "tcb"];
+pub(crate) const EVENT_OUTPUTS: [&str; 2] = ["since", "index"];
+pub(crate) const COLOR_SPACES: [&str; 3] = ["linear_rgb", "hsl", "oklab"];
+
+/// Parse or compile error of a script
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    /// Byte range of the failing expression, if known
+    pub span: Option<Span>,
+    /// Closest known name for a misspelled one
+    pub suggestion: Option<String>,
+    /// Further details, e.g. the error in an imported script
+    pub note: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn from_parse_error(error: &ParseError<LineCol>, source: &str) -> Self {
+        let start = error.location.offset;
+        let (found, end) = match source[start..].chars().next() {
+            Some(c) => (format!("`{}`", c.escape_default()), start + c.len_utf8()),
+            None => ("end of file".into(), start),
+        };
+        Self {
+            message: format!("expected {}, found {}", error.expected, found),
+            span: Some(Span { start, end }),
+            suggestion: None,
+            note: None,
+        }
+    }
+
+    /// `root` is the parsed script, names defined in it are used for suggestions
+    pub fn from_build_error(error: &BuildError, root: &Expr) -> Self {
+        let mut span = error.span();
+        let suggestion = match error.cause() {
+            BuildError::FunctionNotFound(name) => {
+                // Point at the function name instead of the whole call
+                span = span.map(|s| Span {
+                    start: s.start,
+                    end: s.end.min(s.start + name.len()),
+                });
+                let mut candidates = definitions(root);
+                candidates.extend(BUILTINS.iter().map(|s| s.to_string()));
+                candidates.extend(Easing::names());
+                suggest(name, &candidates)
+            }
+            BuildError::VariableNotFound(name) => suggest(name, &definitions(root)),
+            BuildError::UnknownEasing(name) => suggest(name, &Easing::names()),
+            BuildError::UnknownSplineMode(mode) => {
+                let modes: Vec<String> = SPLINE_MODES.iter().map(|s| s.to_string()).collect();
+                suggest(mode, &modes)
+            }
+            BuildError::UnknownEventOutput(name) => {
+                let outputs: Vec<String> = EVENT_OUTPUTS.iter().map(|s| s.to_string()).collect();
+                suggest(name, &outputs)
+            }
+            BuildError::UnknownColorSpace(name) => {
+                let spaces: Vec<String> = COLOR_SPACES.iter().map(|s| s.to_string()).collect();
+                suggest(name, &spaces)
+            }
+            _ => None,
+        };
+        let note = match error.cause() {
+            BuildError::Import { message, .. } => Some(message.clone()),
+            _ => None,
+        };
+        Self {
+            message: error.to_string(),
+            span,
+            suggestion,
+            note,
+        }
+    }
+
+    /// One-based line and column of the start of the error
+    pub fn position(&self, source: &str) -> Option<(usize, usize)> {
+        self.span.map(|span| line_col(source, span.start))
+    }
+
+    /// Formats the error with the offending line, e.g.
+    ///
+    /// ```text
+    /// unknown function `lineer` at 2:9, did you mean `linear`?
+    ///   2 | out a = lineer(0, 1, 2)
+    ///     |         ^^^^^^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let mut text = self.message.clone();
+        if let Some((line, column)) = self.position(source) {
+            text.push_str(&format!(" at {}:{}", line, column));
+        }
+        if let Some(suggestion) = &self.suggestion {
+            text.push_str(&format!(", did you mean `{}`?", suggestion));
+        }
+
+        if let (Some(span), Some((line, column))) = (self.span, self.position(source)) {
+            let start = clamp(source, span.start);
+            let line_text = source.lines().nth(line - 1).unwrap_or("");
+            let line_end = source[start..]
+                .find('\n')
+                .map_or(source.len(), |i| start + i);
+            let width = source[start..clamp(source, span.end.min(line_end)).max(start)]
+                .chars()
+                .count()
+                .max(1);
+            let number = line.to_string();
+            let gutter = " ".repeat(number.len());
+            text.push_str(&format!(
+                "\n  {} | {}\n  {} | {}{}",
+                number,
+                line_text,
+                gutter,
+                " ".repeat(column - 1),
+                "^".repeat(width)
+            ));
+        }
+        if let Some(note) = &self.note {
+            text.push('\n');
+            text.push_str(note);
+        }
+        text
+    }
+}
+
+/// One-based line and column (in characters) of a byte offset
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..clamp(source, offset)];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Offset limited to the source and moved back to the start of the character it is in
+fn clamp(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// Names of the variables and functions defined anywhere in the script
+fn definitions(expr: &Expr) -> Vec<String> {
+    let mut names = Vec::new();
+    if let ExprKind::List(keyword, cons) = &expr.kind {
+        match keyword.as_str() {
+            KW_DEFINE | KW_EXPORT | KW_FUNCTION => {
+                if let Some(Expr {
+                    kind: ExprKind::Symbol(name),
+                    ..
+                }) = cons.first()
+                {
+                    names.push(name.clone());
+                }
+            }
+            KW_MARKER => {
+                for pair in cons.chunks(2) {
+                    if let Some(Expr {
+                        kind: ExprKind::Symbol(name),
+                        ..
+                    }) = pair.first()
+                    {
+                        names.push(name.clone());
+                    }
+                }
+            }
+            KW_PARAMS => {
+                for param in cons.iter() {
+                    if let ExprKind::Symbol(name) = &param.kind {
+                        names.push(name.clone());
+                    }
+                }
+            }
+            _ => (),
+        }
+        for child in cons.iter() {
+            names.append(&mut definitions(child));
+        }
+    }
+    names
+}
+
+/// Closest candidate within a few typos
+fn suggest(name: &str, candidates: &[String]) -> Option<String> {
+    let max_distance = name.chars().count() / 3;
+    candidates
+        .iter()
+        .filter(|candidate| candidate.as_str() != name)
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(d, _)| *d <= max_distance)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, candidate)| candidate.clone())
+}
+
+/// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(current)
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+fn check(source: &str) -> Diagnostic {
+    let ast = crate::parser::parse(source)
+        .map_err(|err| Diagnostic::from_parse_error(&err, source))
+        .unwrap();
+    Diagnostic::from_build_error(&crate::compiler::build(ast.clone()).unwrap_err(), &ast)
+}
+
+#[test]
+fn distances() {
+    assert_eq!(distance("linear", "linear"), 0);
+    assert_eq!(distance("lineer", "linear"), 1);
+    assert_eq!(distance("hld", "hold"), 1);
+    assert_eq!(distance("", "abc"), 3);
+    assert_eq!(line_col("a\nbc\nd", 4), (2, 3));
+    assert_eq!(line_col("a\nbc\nd", 100), (3, 2));
+    assert_eq!(line_col("ä", 1), (1, 1));
+}
+
+#[test]
+fn out_of_bounds() {
+    // E.g. a location in another script
+    let diagnostic = Diagnostic {
+        message: "error".into(),
+        span: Some(Span { start: 3, end: 40 }),
+        suggestion: None,
+        note: None,
+    };
+    assert_eq!(diagnostic.render("aä"), "error at 1:3\n  1 | aä\n    |   ^");
+    assert_eq!(diagnostic.render("ää"), "error at 1:2\n  1 | ää\n    |  ^");
+}
+
+#[test]
+fn unknown_function() {
+    let source = "length = 2\nout a = lineer(0, 1, length)";
+    let diagnostic = check(source);
+    assert_eq!(diagnostic.suggestion, Some("linear".into()));
+    assert_eq!(diagnostic.position(source), Some((2, 9)));
+    assert_eq!(
+        diagnostic.render(source),
+        "unknown function `lineer` at 2:9, did you mean `linear`?\n  2 | out a = lineer(0, 1, length)\n    |         ^^^^^^"
+    );
+}
+
+#[test]
+fn unknown_names() {
+    let source = "length = 2\nout a = linear(0, 1, lenght)";
+    let diagnostic = check(source);
+    assert_eq!(diagnostic.message, "unknown variable `lenght`");
+    assert_eq!(diagnostic.suggestion, Some("length".into()));
+    assert_eq!(diagnostic.position(source), Some((2, 22)));
+
+    let source = "out a = linear(0, 1, 2, ease_in_quadd)";
+    let diagnostic = check(source);
+    assert_eq!(diagnostic.suggestion, Some("ease_in_quad".into()));
+    assert_eq!(diagnostic.position(source), Some((1, 25)));
+
+    let diagnostic = check("out a = hold(1, x)");
+    assert_eq!(diagnostic.suggestion, None);
+}
+
+#[test]
+fn type_errors() {
+    let source = "out a = concat(\n  hold(1, 1),\n  2\n)";
+    let diagnostic = check(source);
+    assert_eq!(
+        diagnostic.message,
+        "expected EnvelopeFunction, found a number"
+    );
+    assert_eq!(diagnostic.position(source), Some((3, 3)));
+
+    let source = "f(x) = hold(x, 1)\nout a = f(1, 2)";
+    let diagnostic = check(source);
+    assert_eq!(
+        diagnostic.message,
+        "`f` takes 1 argument(s) but 2 were given"
+    );
+    assert_eq!(diagnostic.position(source), Some((2, 9)));
+}
+
+#[test]
+fn parse_errors() {
+    let source = "out a = hold(1, 2\nout b = 1";
+    let error = crate::parser::parse(source).unwrap_err();
+    let diagnostic = Diagnostic::from_parse_error(&error, source);
+    assert_eq!(diagnostic.position(source), Some((2, 1)));
+    assert!(diagnostic.message.starts_with("expected \")\""));
+
+    let source = "out a = [1, 2";
+    let error = crate::parser::parse(source).unwrap_err();
+    let diagnostic = Diagnostic::from_parse_error(&error, source);
+    assert_eq!(diagnostic.position(source), Some((1, 14)));
+    assert!(diagnostic.message.ends_with("found end of file"));
+}
@@ -0,0 +1,291 @@
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    Quad,
+    Cubic,
+    Quart,
+    Expo,
+    Sine,
+    Back,
+    Elastic,
+    Bounce,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    Smoothstep,
+    In(Curve),
+    Out(Curve),
+    InOut(Curve),
+}
+
+const CURVE_NAMES: [&str; 8] = [
+    "quad", "cubic", "quart", "expo", "sine", "back", "elastic", "bounce",
+];
+
+impl Curve {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "quad" => Some(Self::Quad),
+            "cubic" => Some(Self::Cubic),
+            "quart" => Some(Self::Quart),
+            "expo" => Some(Self::Expo),
+            "sine" => Some(Self::Sine),
+            "back" => Some(Self::Back),
+            "elastic" => Some(Self::Elastic),
+            "bounce" => Some(Self::Bounce),
+            _ => None,
+        }
+    }
+
+    /// Ease-in variant of the curve. Out and in-out variants are mirrored from this.
+    fn ease_in(&self, t: f64) -> f64 {
+        match self {
+            Self::Quad => t * t,
+            Self::Cubic => t * t * t,
+            Self::Quart => t * t * t * t,
+            Self::Expo => {
+                if t <= 0.0 {
+                    0.0
+                } else {
+                    (2.0f64).powf(10.0 * t - 10.0)
+                }
+            }
+            Self::Sine => 1.0 - (t * PI / 2.0).cos(),
+            Self::Back => {
+                const C1: f64 = 1.70158;
+                const C3: f64 = C1 + 1.0;
+                C3 * t * t * t - C1 * t * t
+            }
+            Self::Elastic => {
+                if t <= 0.0 || t >= 1.0 {
+                    t
+                } else {
+                    const C4: f64 = 2.0 * PI / 3.0;
+                    -(2.0f64).powf(10.0 * t - 10.0) * ((t * 10.0 - 10.75) * C4).sin()
+                }
+            }
+            Self::Bounce => 1.0 - bounce_out(1.0 - t),
+        }
+    }
+
+    /// Derivative of `ease_in`
+    fn ease_in_derivative(&self, t: f64) -> f64 {
+        match self {
+            Self::Quad => 2.0 * t,
+            Self::Cubic => 3.0 * t * t,
+            Self::Quart => 4.0 * t * t * t,
+            Self::Expo => {
+                if t <= 0.0 {
+                    0.0
+                } else {
+                    10.0 * (2.0f64).ln() * (2.0f64).powf(10.0 * t - 10.0)
+                }
+            }
+            Self::Sine => (t * PI / 2.0).sin() * PI / 2.0,
+            Self::Back => {
+                const C1: f64 = 1.70158;
+                const C3: f64 = C1 + 1.0;
+                3.0 * C3 * t * t - 2.0 * C1 * t
+            }
+            Self::Elastic => {
+                const C4: f64 = 2.0 * PI / 3.0;
+                let a = (2.0f64).powf(10.0 * t - 10.0);
+                let angle = (t * 10.0 - 10.75) * C4;
+                -a * (10.0 * (2.0f64).ln() * angle.sin() + 10.0 * C4 * angle.cos())
+            }
+            Self::Bounce => bounce_out_derivative(1.0 - t),
+        }
+    }
+}
+
+fn bounce_out(t: f64) -> f64 {
+    const N1: f64 = 7.5625;
+    const D1: f64 = 2.75;
+    if t < 1.0 / D1 {
+        N1 * t * t
+    } else if t < 2.0 / D1 {
+        let t = t - 1.5 / D1;
+        N1 * t * t + 0.75
+    } else if t < 2.5 / D1 {
+        let t = t - 2.25 / D1;
+        N1 * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D1;
+        N1 * t * t + 0.984375
+    }
+}
+
+fn bounce_out_derivative(t: f64) -> f64 {
+    const N1: f64 = 7.5625;
+    const D1: f64 = 2.75;
+    let offset = if t < 1.0 / D1 {
+        0.0
+    } else if t < 2.0 / D1 {
+        1.5 / D1
+    } else if t < 2.5 / D1 {
+        2.25 / D1
+    } else {
+        2.625 / D1
+    };
+    2.0 * N1 * (t - offset)
+}
+
+impl Easing {
+    /// Resolves names like `ease_in_quad`, `ease_out_bounce`, `ease_in_out_cubic` and `smoothstep`
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name == "smoothstep" {
+            return Some(Self::Smoothstep);
+        }
+        let name = name.strip_prefix("ease_")?;
+        if let Some(curve) = name.strip_prefix("in_out_") {
+            Curve::from_name(curve).map(Self::InOut)
+        } else if let Some(curve) = name.strip_prefix("in_") {
+            Curve::from_name(curve).map(Self::In)
+        } else if let Some(curve) = name.strip_prefix("out_") {
+            Curve::from_name(curve).map(Self::Out)
+        } else {
+            None
+        }
+    }
+
+    /// All accepted easing names
+    pub fn names() -> Vec<String> {
+        let mut names = vec![String::from("smoothstep")];
+        for direction in ["in", "out", "in_out"].iter() {
+            for curve in CURVE_NAMES.iter() {
+                names.push(format!("ease_{}_{}", direction, curve));
+            }
+        }
+        names
+    }
+
+    /// Maps normalized time `t` (0..1) into eased progress
+    pub fn apply(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Smoothstep => t * t * (3.0 - 2.0 * t),
+            Self::In(curve) => curve.ease_in(t),
+            Self::Out(curve) => 1.0 - curve.ease_in(1.0 - t),
+            Self::InOut(curve) => {
+                if t < 0.5 {
+                    curve.ease_in(2.0 * t) / 2.0
+                } else {
+                    1.0 - curve.ease_in(2.0 - 2.0 * t) / 2.0
+                }
+            }
+        }
+    }
+
+    /// Derivative of `apply` with respect to `t`. Zero outside 0..1, where the progress is clamped.
+    pub fn derivative(&self, t: f64) -> f64 {
+        if !(0.0..=1.0).contains(&t) {
+            return 0.0;
+        }
+        match self {
+            Self::Smoothstep => 6.0 * t * (1.0 - t),
+            Self::In(curve) => curve.ease_in_derivative(t),
+            Self::Out(curve) => curve.ease_in_derivative(1.0 - t),
+            Self::InOut(curve) => {
+                if t < 0.5 {
+                    curve.ease_in_derivative(2.0 * t)
+                } else {
+                    curve.ease_in_derivative(2.0 - 2.0 * t)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+const CURVES: [Curve; 8] = [
+    Curve::Quad,
+    Curve::Cubic,
+    Curve::Quart,
+    Curve::Expo,
+    Curve::Sine,
+    Curve::Back,
+    Curve::Elastic,
+    Curve::Bounce,
+];
+
+#[test]
+fn names() {
+    assert_eq!(Easing::from_name("smoothstep"), Some(Easing::Smoothstep));
+    assert_eq!(
+        Easing::from_name("ease_in_out_cubic"),
+        Some(Easing::InOut(Curve::Cubic))
+    );
+    assert_eq!(
+        Easing::from_name("ease_out_bounce"),
+        Some(Easing::Out(Curve::Bounce))
+    );
+    assert_eq!(
+        Easing::from_name("ease_in_expo"),
+        Some(Easing::In(Curve::Expo))
+    );
+    assert_eq!(Easing::from_name("ease_in_wobble"), None);
+    assert_eq!(Easing::from_name("linear"), None);
+    for name in Easing::names() {
+        assert!(Easing::from_name(&name).is_some(), "{}", name);
+    }
+}
+
+#[test]
+fn end_points() {
+    for curve in CURVES.iter() {
+        for easing in [
+            Easing::In(*curve),
+            Easing::Out(*curve),
+            Easing::InOut(*curve),
+        ]
+        .iter()
+        {
+            assert!(easing.apply(0.0).abs() < 1e-9, "{:?} at 0", easing);
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-9, "{:?} at 1", easing);
+        }
+        assert!((Easing::InOut(*curve).apply(0.5) - 0.5).abs() < 1e-9);
+    }
+    assert_eq!(Easing::Smoothstep.apply(0.5), 0.5);
+    assert_eq!(Easing::Smoothstep.apply(2.0), 1.0);
+}
+
+#[test]
+fn shapes() {
+    assert_eq!(Easing::In(Curve::Quad).apply(0.5), 0.25);
+    assert_eq!(Easing::Out(Curve::Quad).apply(0.5), 0.75);
+    assert_eq!(Easing::InOut(Curve::Cubic).apply(0.25), 0.0625);
+    assert!(Easing::In(Curve::Back).apply(0.2) < 0.0);
+    assert!(Easing::Out(Curve::Back).apply(0.8) > 1.0);
+}
+
+#[test]
+fn derivatives() {
+    let mut easings = vec![Easing::Smoothstep];
+    for curve in CURVES.iter() {
+        easings.extend_from_slice(&[
+            Easing::In(*curve),
+            Easing::Out(*curve),
+            Easing::InOut(*curve),
+        ]);
+    }
+    for easing in easings.iter() {
+        for i in 1..100 {
+            let t = i as f64 * 0.01 + 0.001;
+            let h = 1e-6;
+            let numeric = (easing.apply(t + h) - easing.apply(t - h)) / (2.0 * h);
+            let analytic = easing.derivative(t);
+            assert!(
+                (numeric - analytic).abs() < 1e-4 * analytic.abs().max(1.0),
+                "{:?} at {}: {} != {}",
+                easing,
+                t,
+                analytic,
+                numeric
+            );
+        }
+        assert_eq!(easing.derivative(-0.5), 0.0);
+        assert_eq!(easing.derivative(1.5), 0.0);
+    }
+}
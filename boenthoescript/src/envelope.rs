@@ -1,23 +1,36 @@
-use crate::vector::Vector;
+use crate::{color::ColorSpace, program::Program, vector::Vector};
+#[cfg(any(feature = "compiler", test))]
+use crate::{
+    easing::Easing,
+    program::{self, Node},
+    random,
+};
 
 type Duration = f64;
 
 pub trait Envelope {
     fn get_duration(&self) -> Duration;
     fn get_value(&self, time: Duration) -> Vector;
+    /// Derivative of the value with respect to time, in units per second
+    fn get_velocity(&self, time: Duration) -> Vector;
+    /// Adds the envelope to a flat program, returning index of the root node
+    fn lower(&self, program: &mut Program) -> u32;
 }
 
+#[cfg(any(feature = "compiler", test))]
 pub struct Hold {
     pub duration: Duration,
     pub value: Vector,
 }
 
+#[cfg(any(feature = "compiler", test))]
 impl Hold {
     pub fn new(duration: Duration, value: Vector) -> Self {
         Self { duration, value }
     }
 }
 
+#[cfg(any(feature = "compiler", test))]
 impl Envelope for Hold {
     fn get_duration(&self) -> Duration {
         self.duration
@@ -26,119 +39,1434 @@ impl Envelope for Hold {
     fn get_value(&self, _time: Duration) -> Vector {
         self.value.clone()
     }
+
+    fn get_velocity(&self, _time: Duration) -> Vector {
+        0.0.into()
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        program.push(
+            Node::Hold {
+                value: self.value.clone(),
+            },
+            self.duration,
+        )
+    }
 }
 
+#[cfg(any(feature = "compiler", test))]
 pub struct Linear {
     pub duration: f64,
     pub a: Vector,
     pub b: Vector,
 }
 
-impl Linear {
-    pub fn new(duration: Duration, from: Vector, to: Vector) -> Self {
-        Self {
-            duration,
-            a: from.clone(),
-            b: &to - &from,
-        }
+#[cfg(any(feature = "compiler", test))]
+impl Linear {
+    pub fn new(duration: Duration, from: Vector, to: Vector) -> Self {
+        Self {
+            duration,
+            a: from.clone(),
+            b: &to - &from,
+        }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Linear {
+    fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        let t = (time / self.duration).min(1.0);
+        &self.a + &self.b.scalar(t)
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        if time < self.duration {
+            self.b.scalar(1.0 / self.duration)
+        } else {
+            0.0.into()
+        }
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        program.push(
+            Node::Linear {
+                duration: self.duration,
+                a: self.a.clone(),
+                b: self.b.clone(),
+            },
+            self.duration,
+        )
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+pub struct Ease {
+    pub duration: Duration,
+    pub a: Vector,
+    pub b: Vector,
+    pub easing: Easing,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Ease {
+    pub fn new(duration: Duration, from: Vector, to: Vector, easing: Easing) -> Self {
+        Self {
+            duration,
+            a: from.clone(),
+            b: &to - &from,
+            easing,
+        }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Ease {
+    fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        if self.duration <= 0.0 {
+            return &self.a + &self.b;
+        }
+        let t = self.easing.apply(time / self.duration);
+        &self.a + &self.b.scalar(t)
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        if self.duration <= 0.0 {
+            return 0.0.into();
+        }
+        let t = self.easing.derivative(time / self.duration);
+        self.b.scalar(t / self.duration)
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        program.push(
+            Node::Ease {
+                duration: self.duration,
+                a: self.a.clone(),
+                b: self.b.clone(),
+                easing: self.easing,
+            },
+            self.duration,
+        )
+    }
+}
+
+/// Colour fade interpolated in another colour space
+#[cfg(any(feature = "compiler", test))]
+pub struct ColorFade {
+    pub duration: Duration,
+    /// End colours in sRGB
+    pub from: Vector,
+    pub to: Vector,
+    /// End points in the colour space
+    pub a: Vector,
+    pub b: Vector,
+    pub space: ColorSpace,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl ColorFade {
+    pub fn new(duration: Duration, from: Vector, to: Vector, space: ColorSpace) -> Self {
+        let (a, b) = space.fade_points(&from, &to);
+        Self {
+            duration,
+            from,
+            to,
+            a,
+            b,
+            space,
+        }
+    }
+
+    fn fade(&self, time: Duration) -> (Vector, Vector) {
+        color_fade(
+            self.space,
+            self.duration,
+            (&self.from, &self.to),
+            (&self.a, &self.b),
+            time,
+        )
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for ColorFade {
+    fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        self.fade(time).0
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        self.fade(time).1
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        program.push(
+            Node::ColorFade {
+                duration: self.duration,
+                from: self.from.clone(),
+                to: self.to.clone(),
+                a: self.a.clone(),
+                b: self.b.clone(),
+                space: self.space,
+            },
+            self.duration,
+        )
+    }
+}
+
+/// Colour and its velocity, holding the end colours outside the fade. The end colours
+/// are returned as given, as converting them back from the colour space is not exact.
+pub fn color_fade(
+    space: ColorSpace,
+    duration: Duration,
+    (from, to): (&Vector, &Vector),
+    (a, b): (&Vector, &Vector),
+    time: Duration,
+) -> (Vector, Vector) {
+    if time < 0.0 {
+        (from.clone(), from.scalar(0.0))
+    } else if time < duration {
+        let (color, velocity) = space.mix(a, b, time / duration, 1.0 / duration);
+        (if time > 0.0 { color } else { from.clone() }, velocity)
+    } else {
+        (to.clone(), to.scalar(0.0))
+    }
+}
+
+#[derive(Clone)]
+pub struct SplineKey {
+    pub time: Duration,
+    pub value: Vector,
+    pub in_tangent: Vector,
+    pub out_tangent: Vector,
+}
+
+/// Cubic Hermite spline through keyframes. Tangents are velocities (units per second).
+#[cfg(any(feature = "compiler", test))]
+pub struct Spline {
+    pub keys: Vec<SplineKey>,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Spline {
+    pub fn new(mut keys: Vec<SplineKey>) -> Self {
+        keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        Self { keys }
+    }
+
+    /// Catmull-Rom spline, with tangents scaled to non-uniform keyframe spacing
+    pub fn catmull_rom(points: Vec<(Duration, Vector)>) -> Self {
+        Self::tcb(
+            points
+                .into_iter()
+                .map(|(time, value)| (time, value, [0.0, 0.0, 0.0]))
+                .collect(),
+        )
+    }
+
+    /// Kochanek-Bartels spline with tension, continuity and bias for every keyframe
+    pub fn tcb(mut points: Vec<(Duration, Vector, [f64; 3])>) -> Self {
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let slope = |a: usize, b: usize| -> Vector {
+            let (ta, va, _) = &points[a];
+            let (tb, vb, _) = &points[b];
+            if tb > ta {
+                (vb - va).scalar(1.0 / (tb - ta))
+            } else {
+                0.0.into()
+            }
+        };
+
+        let last = points.len().saturating_sub(1);
+        let keys = (0..points.len())
+            .map(|i| {
+                let (time, value, [tension, continuity, bias]) = points[i].clone();
+                let incoming = slope(i.saturating_sub(1), i);
+                let outgoing = slope(i, (i + 1).min(last));
+                let (incoming, outgoing) = match i {
+                    0 => (outgoing.clone(), outgoing),
+                    i if i == last => (incoming.clone(), incoming),
+                    _ => (incoming, outgoing),
+                };
+                let tangent = |a: f64, b: f64| -> Vector {
+                    &incoming.scalar((1.0 - tension) * a / 2.0)
+                        + &outgoing.scalar((1.0 - tension) * b / 2.0)
+                };
+                SplineKey {
+                    time,
+                    value,
+                    in_tangent: tangent(
+                        (1.0 + bias) * (1.0 - continuity),
+                        (1.0 - bias) * (1.0 + continuity),
+                    ),
+                    out_tangent: tangent(
+                        (1.0 + bias) * (1.0 + continuity),
+                        (1.0 - bias) * (1.0 - continuity),
+                    ),
+                }
+            })
+            .collect();
+
+        Self { keys }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Spline {
+    fn get_duration(&self) -> Duration {
+        self.keys.last().map_or(0.0, |k| k.time)
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        program::spline(&self.keys, time)
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        program::spline_velocity(&self.keys, time)
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        program.push_spline(&self.keys, self.get_duration())
+    }
+}
+
+/// Stepped random values, a new value every `interval` seconds
+#[cfg(any(feature = "compiler", test))]
+pub struct Random {
+    pub seed: u64,
+    pub min: Vector,
+    pub range: Vector,
+    pub interval: Duration,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Random {
+    pub fn new(seed: u64, min: Vector, max: Vector, interval: Duration) -> Self {
+        Self {
+            seed,
+            range: &max - &min,
+            min,
+            interval,
+        }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Random {
+    fn get_duration(&self) -> Duration {
+        f64::INFINITY
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        let index = (time / self.interval).floor() as i64;
+        let mut value = self.min.clone();
+        for (i, v) in value.0.iter_mut().enumerate() {
+            *v += self.range.0[i] * random::unit(self.seed, i as u64, index);
+        }
+        value
+    }
+
+    fn get_velocity(&self, _time: Duration) -> Vector {
+        0.0.into()
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        program.push(
+            Node::Random {
+                seed: self.seed,
+                min: self.min.clone(),
+                range: self.range.clone(),
+                interval: self.interval,
+            },
+            self.get_duration(),
+        )
+    }
+}
+
+/// Smooth gradient noise, summed over octaves for fractal noise
+#[cfg(any(feature = "compiler", test))]
+pub struct Noise {
+    pub seed: u64,
+    pub frequency: f64,
+    pub amplitude: Vector,
+    pub octaves: u32,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Noise {
+    pub fn new(seed: u64, frequency: f64, amplitude: Vector, octaves: u32) -> Self {
+        Self {
+            seed,
+            frequency,
+            amplitude,
+            octaves,
+        }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Noise {
+    fn get_duration(&self) -> Duration {
+        f64::INFINITY
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        let mut value = self.amplitude.clone();
+        for (i, v) in value.0.iter_mut().enumerate() {
+            *v *= random::fbm(self.seed, i as u64, time * self.frequency, self.octaves);
+        }
+        value
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        let mut velocity = self.amplitude.scalar(self.frequency);
+        for (i, v) in velocity.0.iter_mut().enumerate() {
+            *v *= random::fbm_derivative(self.seed, i as u64, time * self.frequency, self.octaves);
+        }
+        velocity
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        program.push(
+            Node::Noise {
+                seed: self.seed,
+                frequency: self.frequency,
+                amplitude: self.amplitude.clone(),
+                octaves: self.octaves,
+            },
+            self.get_duration(),
+        )
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+pub struct Concat {
+    pub duration: Duration,
+    pub cons: Vec<Box<dyn Envelope>>,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Concat {
+    pub fn new(cons: Vec<Box<dyn Envelope>>) -> Self {
+        let duration = cons.iter().map(|a| a.get_duration()).sum();
+        Self { duration, cons }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Concat {
+    fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        let mut pos = 0.0;
+        let mut last_duration = 0.0;
+        let hit = self.cons.iter().find(|a| {
+            last_duration = a.get_duration();
+            pos += last_duration;
+            time < pos
+        });
+        let shifted_time = time - pos + last_duration;
+        match hit {
+            Some(e) => e.get_value(shifted_time),
+            None => match self.cons.last() {
+                Some(e) => e.get_value(shifted_time),
+                None => 0.0.into(), // empty list
+            },
+        }
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        let mut pos = 0.0;
+        let mut last_duration = 0.0;
+        let hit = self.cons.iter().find(|a| {
+            last_duration = a.get_duration();
+            pos += last_duration;
+            time < pos
+        });
+        let shifted_time = time - pos + last_duration;
+        match hit.or_else(|| self.cons.last()) {
+            Some(e) => e.get_velocity(shifted_time),
+            None => 0.0.into(),
+        }
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let nodes: Vec<u32> = self.cons.iter().map(|a| a.lower(program)).collect();
+        program.push_concat(&nodes)
+    }
+}
+
+pub type EnvelopeFn = Box<dyn Envelope>;
+
+impl std::fmt::Debug for EnvelopeFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Envelope").finish()
+    }
+}
+
+type ValueFn = fn(&[f64]) -> f64;
+/// Derivative from the operands and their derivatives
+type DerivativeFn = fn(&[f64], &[f64]) -> f64;
+
+/// Component-wise math function and its derivative, for the chain rule
+#[derive(Clone, Copy)]
+pub struct MathFn {
+    pub value: ValueFn,
+    pub derivative: DerivativeFn,
+    /// Builtin operation the function implements. Programs with other functions
+    /// cannot be serialized.
+    pub op: Option<MathOp>,
+}
+
+/// Builtin math operations, in the order of their binary encoding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MathOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+    Sin,
+    Cos,
+    Abs,
+    Sqrt,
+    Floor,
+    Fract,
+    Min,
+    Max,
+    Pow,
+    Clamp,
+    Mix,
+}
+
+pub const MATH_OPS: [MathOp; 16] = [
+    MathOp::Add,
+    MathOp::Sub,
+    MathOp::Mul,
+    MathOp::Div,
+    MathOp::Neg,
+    MathOp::Sin,
+    MathOp::Cos,
+    MathOp::Abs,
+    MathOp::Sqrt,
+    MathOp::Floor,
+    MathOp::Fract,
+    MathOp::Min,
+    MathOp::Max,
+    MathOp::Pow,
+    MathOp::Clamp,
+    MathOp::Mix,
+];
+
+impl MathOp {
+    /// Number of operands
+    pub fn arity(self) -> usize {
+        match self {
+            Self::Neg
+            | Self::Sin
+            | Self::Cos
+            | Self::Abs
+            | Self::Sqrt
+            | Self::Floor
+            | Self::Fract => 1,
+            Self::Clamp | Self::Mix => 3,
+            _ => 2,
+        }
+    }
+
+    pub fn func(self) -> MathFn {
+        let (value, derivative): (ValueFn, DerivativeFn) = match self {
+            Self::Add => (|x| x[0] + x[1], |_, d| d[0] + d[1]),
+            Self::Sub => (|x| x[0] - x[1], |_, d| d[0] - d[1]),
+            Self::Mul => (|x| x[0] * x[1], |x, d| d[0] * x[1] + x[0] * d[1]),
+            Self::Div => (
+                |x| x[0] / x[1],
+                |x, d| (d[0] * x[1] - x[0] * d[1]) / (x[1] * x[1]),
+            ),
+            Self::Neg => (|x| -x[0], |_, d| -d[0]),
+            Self::Sin => (|x| x[0].sin(), |x, d| x[0].cos() * d[0]),
+            Self::Cos => (|x| x[0].cos(), |x, d| -x[0].sin() * d[0]),
+            Self::Abs => (|x| x[0].abs(), |x, d| x[0].signum() * d[0]),
+            Self::Sqrt => (|x| x[0].sqrt(), |x, d| d[0] / (2.0 * x[0].sqrt())),
+            Self::Floor => (|x| x[0].floor(), |_, _| 0.0),
+            Self::Fract => (|x| x[0] - x[0].floor(), |_, d| d[0]),
+            Self::Min => (
+                |x| x[0].min(x[1]),
+                |x, d| if x[0] <= x[1] { d[0] } else { d[1] },
+            ),
+            Self::Max => (
+                |x| x[0].max(x[1]),
+                |x, d| if x[0] >= x[1] { d[0] } else { d[1] },
+            ),
+            Self::Pow => (|x| x[0].powf(x[1]), pow_derivative),
+            Self::Clamp => (|x| x[0].max(x[1]).min(x[2]), clamp_derivative),
+            Self::Mix => (
+                |x| x[0] + (x[1] - x[0]) * x[2],
+                |x, d| d[0] + (d[1] - d[0]) * x[2] + (x[1] - x[0]) * d[2],
+            ),
+        };
+        MathFn {
+            value,
+            derivative,
+            op: Some(self),
+        }
+    }
+}
+
+fn pow_derivative(x: &[f64], d: &[f64]) -> f64 {
+    let base = x[1] * x[0].powf(x[1] - 1.0) * d[0];
+    if d[1] == 0.0 {
+        base
+    } else {
+        base + x[0].powf(x[1]) * x[0].ln() * d[1]
+    }
+}
+
+fn clamp_derivative(x: &[f64], d: &[f64]) -> f64 {
+    if x[0].max(x[1]) > x[2] {
+        d[2]
+    } else if x[0] >= x[1] {
+        d[0]
+    } else {
+        d[1]
+    }
+}
+
+impl MathFn {
+    pub fn new(value: ValueFn, derivative: DerivativeFn) -> Self {
+        Self {
+            value,
+            derivative,
+            op: None,
+        }
+    }
+
+    pub fn apply_derivative(&self, values: &[Vector], velocities: &[Vector]) -> Vector {
+        let mut result = Vector::default();
+        let mut args = Vec::with_capacity(values.len());
+        let mut derivatives = Vec::with_capacity(values.len());
+        for (i, x) in result.0.iter_mut().enumerate() {
+            args.clear();
+            args.extend(values.iter().map(|v| v.0[i]));
+            derivatives.clear();
+            derivatives.extend(velocities.iter().map(|v| v.0[i]));
+            *x = (self.derivative)(&args, &derivatives);
+        }
+        result
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+pub struct Apply {
+    pub duration: Duration,
+    pub cons: Vec<Box<dyn Envelope>>,
+    pub func: MathFn,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Apply {
+    pub fn new(cons: Vec<Box<dyn Envelope>>, func: MathFn) -> Self {
+        let duration = cons.iter().map(|a| a.get_duration()).fold(0.0, f64::max);
+        Self {
+            duration,
+            cons,
+            func,
+        }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Apply {
+    fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        let values: Vec<Vector> = self.cons.iter().map(|a| a.get_value(time)).collect();
+        Vector::apply(&values, self.func.value)
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        let values: Vec<Vector> = self.cons.iter().map(|a| a.get_value(time)).collect();
+        let velocities: Vec<Vector> = self.cons.iter().map(|a| a.get_velocity(time)).collect();
+        self.func.apply_derivative(&values, &velocities)
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let args: Vec<u32> = self.cons.iter().map(|a| a.lower(program)).collect();
+        program.push_apply(&args, self.func, self.duration)
+    }
+}
+
+/// Plays `a`, blending into `b` over the last `fade` seconds of `a`
+#[cfg(any(feature = "compiler", test))]
+pub struct Crossfade {
+    pub fade: Duration,
+    pub a: Box<dyn Envelope>,
+    pub b: Box<dyn Envelope>,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Crossfade {
+    pub fn new(fade: Duration, a: Box<dyn Envelope>, b: Box<dyn Envelope>) -> Self {
+        let fade = fade.max(0.0).min(a.get_duration());
+        Self { fade, a, b }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Crossfade {
+    fn get_duration(&self) -> Duration {
+        self.a.get_duration() - self.fade + self.b.get_duration()
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        let start = self.a.get_duration() - self.fade;
+        fade_between(
+            time - start,
+            self.fade,
+            || self.a.get_value(time),
+            || self.b.get_value(time - start),
+        )
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        let start = self.a.get_duration() - self.fade;
+        fade_velocity(
+            time - start,
+            self.fade,
+            || (self.a.get_value(time), self.a.get_velocity(time)),
+            || {
+                (
+                    self.b.get_value(time - start),
+                    self.b.get_velocity(time - start),
+                )
+            },
+        )
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let a = self.a.lower(program);
+        let b = self.b.lower(program);
+        program.push(
+            Node::Crossfade {
+                start: program.duration_of(a) - self.fade,
+                fade: self.fade,
+                a,
+                b,
+            },
+            self.get_duration(),
+        )
+    }
+}
+
+/// Blends from `a` to `b`, where `time` is measured from the start of the fade
+pub fn fade_between<A, B>(time: Duration, fade: Duration, a: A, b: B) -> Vector
+where
+    A: FnOnce() -> Vector,
+    B: FnOnce() -> Vector,
+{
+    if time < 0.0 {
+        a()
+    } else if time >= fade {
+        b()
+    } else {
+        let a = a();
+        &a + &(&b() - &a).scalar(time / fade)
+    }
+}
+
+/// Derivative of `fade_between`, from the values and velocities of `a` and `b`
+pub fn fade_velocity<A, B>(time: Duration, fade: Duration, a: A, b: B) -> Vector
+where
+    A: FnOnce() -> (Vector, Vector),
+    B: FnOnce() -> (Vector, Vector),
+{
+    if time < 0.0 {
+        a().1
+    } else if time >= fade {
+        b().1
+    } else {
+        let (a, a_velocity) = a();
+        let (b, b_velocity) = b();
+        let t = time / fade;
+        &(&a_velocity + &(&b_velocity - &a_velocity).scalar(t)) + &(&b - &a).scalar(1.0 / fade)
+    }
+}
+
+/// Picks one of the envelopes by the integer part of the index envelope
+#[cfg(any(feature = "compiler", test))]
+pub struct Select {
+    pub index: Box<dyn Envelope>,
+    pub cons: Vec<Box<dyn Envelope>>,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Select {
+    pub fn new(index: Box<dyn Envelope>, cons: Vec<Box<dyn Envelope>>) -> Self {
+        Self { index, cons }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Select {
+    fn get_duration(&self) -> Duration {
+        self.index.get_duration()
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        let index = select_index(self.index.get_value(time).to_f(), self.cons.len());
+        match self.cons.get(index) {
+            Some(e) => e.get_value(time),
+            None => 0.0.into(), // empty list
+        }
+    }
+
+    /// Velocity of the selected envelope. Switching between envelopes is not continuous.
+    fn get_velocity(&self, time: Duration) -> Vector {
+        let index = select_index(self.index.get_value(time).to_f(), self.cons.len());
+        match self.cons.get(index) {
+            Some(e) => e.get_velocity(time),
+            None => 0.0.into(),
+        }
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let index = self.index.lower(program);
+        let nodes: Vec<u32> = self.cons.iter().map(|a| a.lower(program)).collect();
+        program.push_select(index, &nodes, self.get_duration())
+    }
+}
+
+/// Index clamped to `0..count`
+pub fn select_index(index: f64, count: usize) -> usize {
+    (index.floor().max(0.0) as usize).min(count.saturating_sub(1))
+}
+
+/// Starts the envelope after an offset, holding its initial value until then
+#[cfg(any(feature = "compiler", test))]
+pub struct Delay {
+    pub offset: Duration,
+    pub envelope: Box<dyn Envelope>,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Delay {
+    pub fn new(offset: Duration, envelope: Box<dyn Envelope>) -> Self {
+        Self { offset, envelope }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Delay {
+    fn get_duration(&self) -> Duration {
+        self.offset + self.envelope.get_duration()
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        self.envelope.get_value((time - self.offset).max(0.0))
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        if time > self.offset {
+            self.envelope.get_velocity(time - self.offset)
+        } else {
+            0.0.into()
+        }
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let node = self.envelope.lower(program);
+        program.push(
+            Node::Delay {
+                offset: self.offset,
+                node,
+            },
+            self.get_duration(),
+        )
+    }
+}
+
+#[cfg(feature = "compiler")]
+pub struct Repeat {
+    pub duration: Duration,
+    #[allow(dead_code)]
+    pub repeats: u32,
+    pub envelope: Box<dyn Envelope>,
+}
+
+#[cfg(feature = "compiler")]
+impl Repeat {
+    pub fn new(repeats: u32, envelope: Box<dyn Envelope>) -> Self {
+        let duration = envelope.get_duration() * (repeats as Duration);
+        Self {
+            repeats,
+            envelope,
+            duration,
+        }
+    }
+}
+
+#[cfg(feature = "compiler")]
+impl Envelope for Repeat {
+    fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        self.envelope.get_value(if time > self.duration {
+            time
+        } else {
+            time % self.envelope.get_duration()
+        })
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        self.envelope.get_velocity(if time > self.duration {
+            time
+        } else {
+            time % self.envelope.get_duration()
+        })
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let node = self.envelope.lower(program);
+        program.push(
+            Node::Repeat {
+                duration: self.duration,
+                period: program.duration_of(node),
+                node,
+            },
+            self.duration,
+        )
+    }
+}
+
+#[cfg(feature = "compiler")]
+pub struct Loop {
+    pub envelope: Box<dyn Envelope>,
+}
+
+#[cfg(feature = "compiler")]
+impl Loop {
+    pub fn new(envelope: Box<dyn Envelope>) -> Self {
+        Self { envelope }
+    }
+}
+
+#[cfg(feature = "compiler")]
+impl Envelope for Loop {
+    fn get_duration(&self) -> Duration {
+        f64::INFINITY
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        self.envelope.get_value(time % self.envelope.get_duration())
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        self.envelope
+            .get_velocity(time % self.envelope.get_duration())
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let node = self.envelope.lower(program);
+        program.push(
+            Node::Loop {
+                period: program.duration_of(node),
+                node,
+            },
+            self.get_duration(),
+        )
+    }
+}
+
+/// Plays the envelope `factor` times faster
+#[cfg(any(feature = "compiler", test))]
+pub struct Speed {
+    pub factor: f64,
+    pub envelope: Box<dyn Envelope>,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Speed {
+    pub fn new(factor: f64, envelope: Box<dyn Envelope>) -> Self {
+        Self { factor, envelope }
+    }
+
+    /// Scales the envelope to last `duration` seconds
+    pub fn stretch(duration: Duration, envelope: Box<dyn Envelope>) -> Self {
+        Self::new(envelope.get_duration() / duration, envelope)
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Speed {
+    fn get_duration(&self) -> Duration {
+        self.envelope.get_duration() / self.factor
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        self.envelope.get_value(time * self.factor)
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        self.envelope
+            .get_velocity(time * self.factor)
+            .scalar(self.factor)
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let node = self.envelope.lower(program);
+        program.push(
+            Node::Speed {
+                factor: self.factor,
+                node,
+            },
+            self.get_duration(),
+        )
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+pub struct Reverse {
+    pub envelope: Box<dyn Envelope>,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Reverse {
+    pub fn new(envelope: Box<dyn Envelope>) -> Self {
+        Self { envelope }
     }
 }
 
-impl Envelope for Linear {
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Reverse {
     fn get_duration(&self) -> Duration {
-        self.duration
+        self.envelope.get_duration()
     }
 
     fn get_value(&self, time: Duration) -> Vector {
-        let t = (time / self.duration).min(1.0);
-        &self.a + &self.b.scalar(t)
+        let duration = self.envelope.get_duration();
+        self.envelope
+            .get_value(duration - time.max(0.0).min(duration))
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        let duration = self.envelope.get_duration();
+        if (0.0..=duration).contains(&time) {
+            self.envelope.get_velocity(duration - time).scalar(-1.0)
+        } else {
+            0.0.into()
+        }
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let node = self.envelope.lower(program);
+        program.push(
+            Node::Reverse {
+                duration: program.duration_of(node),
+                node,
+            },
+            self.get_duration(),
+        )
     }
 }
 
-pub struct Concat {
-    pub duration: Duration,
-    pub cons: Vec<Box<dyn Envelope>>,
+/// Plays the envelope forwards and backwards, forever
+#[cfg(any(feature = "compiler", test))]
+pub struct PingPong {
+    pub envelope: Box<dyn Envelope>,
 }
 
-impl Concat {
-    pub fn new(cons: Vec<Box<dyn Envelope>>) -> Self {
-        let duration = cons.iter().map(|a| a.get_duration()).sum();
-        Self { duration, cons }
+#[cfg(any(feature = "compiler", test))]
+impl PingPong {
+    pub fn new(envelope: Box<dyn Envelope>) -> Self {
+        Self { envelope }
     }
 }
 
-impl Envelope for Concat {
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for PingPong {
     fn get_duration(&self) -> Duration {
-        self.duration
+        f64::INFINITY
     }
 
     fn get_value(&self, time: Duration) -> Vector {
-        let mut pos = 0.0;
-        let mut last_duration = 0.0;
-        let hit = self.cons.iter().find(|a| {
-            last_duration = a.get_duration();
-            pos += last_duration;
-            time < pos
-        });
-        let shifted_time = time - pos + last_duration;
-        match hit {
-            Some(e) => e.get_value(shifted_time),
-            None => match self.cons.last() {
-                Some(e) => e.get_value(shifted_time),
-                None => 0.0.into(), // empty list
-            },
+        let duration = self.envelope.get_duration();
+        self.envelope.get_value(pingpong(time, duration))
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        let duration = self.envelope.get_duration();
+        let velocity = self.envelope.get_velocity(pingpong(time, duration));
+        if time.rem_euclid(2.0 * duration) > duration {
+            velocity.scalar(-1.0)
+        } else {
+            velocity
         }
     }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let node = self.envelope.lower(program);
+        program.push(
+            Node::PingPong {
+                period: program.duration_of(node),
+                node,
+            },
+            self.get_duration(),
+        )
+    }
 }
 
-pub struct Repeat {
-    pub duration: Duration,
-    pub repeats: u32,
+pub fn pingpong(time: Duration, duration: Duration) -> Duration {
+    let t = time.rem_euclid(2.0 * duration);
+    if t > duration {
+        2.0 * duration - t
+    } else {
+        t
+    }
+}
+
+/// Part of the envelope between `start` and `end`
+#[cfg(any(feature = "compiler", test))]
+pub struct Clip {
+    pub start: Duration,
+    pub end: Duration,
     pub envelope: Box<dyn Envelope>,
 }
 
-impl Repeat {
-    pub fn new(repeats: u32, envelope: Box<dyn Envelope>) -> Self {
-        let duration = envelope.get_duration() * (repeats as Duration);
+#[cfg(any(feature = "compiler", test))]
+impl Clip {
+    pub fn new(start: Duration, end: Duration, envelope: Box<dyn Envelope>) -> Self {
         Self {
-            repeats,
+            start,
+            end,
             envelope,
-            duration,
         }
     }
 }
 
-impl Envelope for Repeat {
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Clip {
     fn get_duration(&self) -> Duration {
-        self.duration
+        (self.end - self.start).max(0.0)
     }
 
     fn get_value(&self, time: Duration) -> Vector {
-        self.envelope.get_value(if time > self.duration {
-            time
+        let time = time.max(0.0).min(self.get_duration());
+        self.envelope.get_value(self.start + time)
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        if (0.0..=self.get_duration()).contains(&time) {
+            self.envelope.get_velocity(self.start + time)
         } else {
-            time % self.envelope.get_duration()
-        })
+            0.0.into()
+        }
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let node = self.envelope.lower(program);
+        program.push(
+            Node::Clip {
+                start: self.start,
+                length: self.get_duration(),
+                node,
+            },
+            self.get_duration(),
+        )
     }
 }
 
-pub struct Loop {
+/// Value of the envelope at a fixed time. Keeps the duration of the envelope.
+#[cfg(any(feature = "compiler", test))]
+pub struct SampleAt {
+    pub time: Duration,
     pub envelope: Box<dyn Envelope>,
 }
 
-impl Loop {
-    pub fn new(envelope: Box<dyn Envelope>) -> Self {
-        Self { envelope }
+#[cfg(any(feature = "compiler", test))]
+impl SampleAt {
+    pub fn new(time: Duration, envelope: Box<dyn Envelope>) -> Self {
+        Self { time, envelope }
     }
 }
 
-impl Envelope for Loop {
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for SampleAt {
+    fn get_duration(&self) -> Duration {
+        self.envelope.get_duration()
+    }
+
+    fn get_value(&self, _time: Duration) -> Vector {
+        self.envelope.get_value(self.time)
+    }
+
+    fn get_velocity(&self, _time: Duration) -> Vector {
+        0.0.into()
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let node = self.envelope.lower(program);
+        program.push(
+            Node::SampleAt {
+                time: self.time,
+                node,
+            },
+            self.get_duration(),
+        )
+    }
+}
+
+/// Restarts the envelope at fixed intervals
+#[cfg(any(feature = "compiler", test))]
+pub struct Every {
+    pub interval: Duration,
+    pub envelope: Box<dyn Envelope>,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Every {
+    pub fn new(interval: Duration, envelope: Box<dyn Envelope>) -> Self {
+        Self { interval, envelope }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Every {
     fn get_duration(&self) -> Duration {
-        std::f64::INFINITY
+        f64::INFINITY
     }
 
     fn get_value(&self, time: Duration) -> Vector {
-        self.envelope.get_value(time % self.envelope.get_duration())
+        self.envelope.get_value(time.rem_euclid(self.interval))
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        self.envelope.get_velocity(time.rem_euclid(self.interval))
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let node = self.envelope.lower(program);
+        program.push(
+            Node::Every {
+                interval: self.interval,
+                node,
+            },
+            self.get_duration(),
+        )
+    }
+}
+
+/// Exponential decay from 1, halving every `half_life` seconds
+#[cfg(any(feature = "compiler", test))]
+pub struct Decay {
+    pub half_life: Duration,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Decay {
+    pub fn new(half_life: Duration) -> Self {
+        Self { half_life }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Decay {
+    fn get_duration(&self) -> Duration {
+        f64::INFINITY
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        decay(self.half_life, time).into()
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        decay_velocity(self.half_life, time).into()
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        program.push(
+            Node::Decay {
+                half_life: self.half_life,
+            },
+            self.get_duration(),
+        )
+    }
+}
+
+pub fn decay(half_life: Duration, time: Duration) -> f64 {
+    (0.5f64).powf(time.max(0.0) / half_life)
+}
+
+pub fn decay_velocity(half_life: Duration, time: Duration) -> f64 {
+    if time > 0.0 {
+        -(2.0f64).ln() / half_life * decay(half_life, time)
+    } else {
+        0.0
+    }
+}
+
+/// Output of an events envelope after each event
+#[derive(Clone)]
+pub enum EventOutput<T> {
+    /// Envelope started at the event and scaled by the weight of the event
+    Shape(T),
+    /// Seconds since the latest event, infinite before the first one
+    Since,
+    /// Number of the latest event counting from zero, -1 before the first one
+    Index,
+}
+
+/// Discrete events, e.g. flashes on kick drum hits
+#[cfg(any(feature = "compiler", test))]
+pub struct Events {
+    /// Times and weights of the events, sorted by time
+    pub events: Vec<(Duration, f64)>,
+    /// Events repeat with this period, unless it is infinite
+    pub period: Duration,
+    pub output: EventOutput<Box<dyn Envelope>>,
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Events {
+    pub fn new(mut times: Vec<Duration>, output: EventOutput<Box<dyn Envelope>>) -> Self {
+        times.sort_by(|a, b| a.total_cmp(b));
+        Self {
+            events: times.into_iter().map(|time| (time, 1.0)).collect(),
+            period: f64::INFINITY,
+            output,
+        }
+    }
+
+    /// Repeating pattern of steps. Every non-zero weight is an event.
+    pub fn pattern(
+        step: Duration,
+        weights: &[f64],
+        output: EventOutput<Box<dyn Envelope>>,
+    ) -> Self {
+        Self {
+            events: weights
+                .iter()
+                .enumerate()
+                .filter(|(_, weight)| **weight != 0.0)
+                .map(|(i, weight)| (i as f64 * step, *weight))
+                .collect(),
+            period: step * weights.len() as f64,
+            output,
+        }
+    }
+}
+
+#[cfg(any(feature = "compiler", test))]
+impl Envelope for Events {
+    fn get_duration(&self) -> Duration {
+        let last = self.events.last().map_or(0.0, |(time, _)| *time);
+        match &self.output {
+            _ if self.period.is_finite() => f64::INFINITY,
+            EventOutput::Shape(shape) => last + shape.get_duration(),
+            _ => last,
+        }
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        let event = last_event(&self.events, self.period, time);
+        event_value(&self.output, event, time, |shape, t| shape.get_value(t))
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        let event = last_event(&self.events, self.period, time);
+        event_velocity(&self.output, event, time, |shape, t| shape.get_velocity(t))
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        let output = match &self.output {
+            EventOutput::Shape(shape) => EventOutput::Shape(shape.lower(program)),
+            EventOutput::Since => EventOutput::Since,
+            EventOutput::Index => EventOutput::Index,
+        };
+        program.push_events(&self.events, self.period, output, self.get_duration())
+    }
+}
+
+/// Index, time and weight of the latest event at or before `time`
+pub fn last_event(
+    events: &[(Duration, f64)],
+    period: Duration,
+    time: Duration,
+) -> Option<(usize, Duration, f64)> {
+    if events.is_empty() || time < 0.0 {
+        return None;
+    }
+    let cycle = if period.is_finite() {
+        (time / period).floor()
+    } else {
+        0.0
+    };
+    // Start of a repeat. Lists of events do not repeat, so their only cycle starts at zero.
+    let cycle_start = |cycle: f64| if cycle > 0.0 { cycle * period } else { 0.0 };
+    let count = events.partition_point(|(start, _)| *start <= time - cycle_start(cycle));
+    let per_cycle = events.len();
+    let (cycle, index) = match count {
+        0 if cycle > 0.0 => (cycle - 1.0, per_cycle - 1),
+        0 => return None,
+        count => (cycle, count - 1),
+    };
+    let (start, weight) = events[index];
+    let start = start + cycle_start(cycle);
+    Some((cycle as usize * per_cycle + index, start, weight))
+}
+
+pub fn event_value<T, F>(
+    output: &EventOutput<T>,
+    event: Option<(usize, Duration, f64)>,
+    time: Duration,
+    shape_value: F,
+) -> Vector
+where
+    F: FnOnce(&T, Duration) -> Vector,
+{
+    match (output, event) {
+        (EventOutput::Shape(shape), Some((_, start, weight))) => {
+            shape_value(shape, time - start).scalar(weight)
+        }
+        (EventOutput::Shape(_), None) => 0.0.into(),
+        (EventOutput::Since, Some((_, start, _))) => (time - start).into(),
+        (EventOutput::Since, None) => f64::INFINITY.into(),
+        (EventOutput::Index, Some((index, _, _))) => (index as f64).into(),
+        (EventOutput::Index, None) => (-1.0).into(),
+    }
+}
+
+pub fn event_velocity<T, F>(
+    output: &EventOutput<T>,
+    event: Option<(usize, Duration, f64)>,
+    time: Duration,
+    shape_velocity: F,
+) -> Vector
+where
+    F: FnOnce(&T, Duration) -> Vector,
+{
+    match (output, event) {
+        (EventOutput::Shape(shape), Some((_, start, weight))) => {
+            shape_velocity(shape, time - start).scalar(weight)
+        }
+        (EventOutput::Since, Some(_)) => 1.0.into(),
+        _ => 0.0.into(),
     }
 }
 
@@ -168,6 +1496,187 @@ fn linear() {
     assert_eq!(x.get_value(1.0).to_f2(), (20.0, 0.0));
 }
 
+#[test]
+fn ease() {
+    use crate::easing::Curve;
+
+    let x = Ease::new(2.0, 10.0.into(), 20.0.into(), Easing::In(Curve::Quad));
+    assert_eq!(x.get_duration(), 2.0);
+    assert_eq!(x.get_value(0.0).to_f(), 10.0);
+    assert_eq!(x.get_value(1.0).to_f(), 12.5);
+    assert_eq!(x.get_value(2.0).to_f(), 20.0);
+    assert_eq!(x.get_value(3.0).to_f(), 20.0);
+
+    let x = Ease::new(
+        1.0,
+        vec![0.0, 4.0].into(),
+        vec![4.0, 0.0].into(),
+        Easing::Smoothstep,
+    );
+    assert_eq!(x.get_value(0.5).to_f2(), (2.0, 2.0));
+}
+
+#[test]
+fn spline() {
+    let x = Spline::catmull_rom(vec![
+        (0.0, 0.0.into()),
+        (1.0, 1.0.into()),
+        (2.0, 2.0.into()),
+        (4.0, vec![0.0, 4.0].into()),
+    ]);
+    assert_eq!(x.get_duration(), 4.0);
+    assert_eq!(x.get_value(-1.0).to_f(), 0.0);
+    assert_eq!(x.get_value(0.5).to_f(), 0.5);
+    assert_eq!(x.get_value(1.0).to_f(), 1.0);
+    assert_eq!(x.get_value(4.0).to_f2(), (0.0, 4.0));
+    assert_eq!(x.get_value(5.0).to_f2(), (0.0, 4.0));
+
+    // Velocity is continuous over keyframes
+    let velocity = |t: f64| (x.get_value(t + 1e-6).to_f() - x.get_value(t - 1e-6).to_f()) / 2e-6;
+    assert!((velocity(1.0) - 1.0).abs() < 1e-4);
+    assert!((velocity(2.0) - x.keys[2].in_tangent.to_f()).abs() < 1e-4);
+
+    let x = Spline::new(vec![
+        SplineKey {
+            time: 0.0,
+            value: 0.0.into(),
+            in_tangent: 0.0.into(),
+            out_tangent: 0.0.into(),
+        },
+        SplineKey {
+            time: 2.0,
+            value: 1.0.into(),
+            in_tangent: 0.0.into(),
+            out_tangent: 0.0.into(),
+        },
+    ]);
+    assert_eq!(x.get_value(1.0).to_f(), 0.5);
+    assert!(x.get_value(0.5).to_f() < 0.25);
+
+    let x = Spline::tcb(vec![
+        (0.0, 0.0.into(), [0.0, 0.0, 0.0]),
+        (1.0, 1.0.into(), [1.0, 0.0, 0.0]),
+        (2.0, 0.0.into(), [0.0, 0.0, 0.0]),
+    ]);
+    assert_eq!(x.keys[1].in_tangent.to_f(), 0.0);
+    assert_eq!(x.keys[1].out_tangent.to_f(), 0.0);
+}
+
+#[test]
+fn random() {
+    let x = Random::new(1, vec![1.0, 0.0].into(), vec![2.0, 0.0].into(), 0.5);
+    let y = Random::new(1, vec![1.0, 0.0].into(), vec![2.0, 0.0].into(), 0.5);
+    assert_eq!(x.get_value(0.1), x.get_value(0.4));
+    assert_ne!(x.get_value(0.4), x.get_value(0.6));
+    assert_eq!(x.get_value(12.3), y.get_value(12.3));
+    for i in 0..100 {
+        let (a, b) = x.get_value(i as f64 * 0.3).to_f2();
+        assert!((1.0..2.0).contains(&a));
+        assert_eq!(b, 0.0);
+    }
+}
+
+#[test]
+fn noise() {
+    let x = Noise::new(5, 2.0, vec![1.0, 0.5].into(), 1);
+    let y = Noise::new(5, 2.0, vec![1.0, 0.5].into(), 1);
+    assert_eq!(x.get_duration(), f64::INFINITY);
+    assert_eq!(x.get_value(1.7), y.get_value(1.7));
+    let (a, b) = x.get_value(1.7).to_f2();
+    assert_ne!(a, b * 2.0);
+    assert!(b.abs() <= 0.5);
+    assert_eq!(x.get_value(1.7).to_f3().2, 0.0);
+}
+
+#[test]
+fn delay() {
+    let x = Delay::new(2.0, Box::new(Linear::new(1.0, 1.0.into(), 2.0.into())));
+    assert_eq!(x.get_duration(), 3.0);
+    assert_eq!(x.get_value(0.0).to_f(), 1.0);
+    assert_eq!(x.get_value(2.5).to_f(), 1.5);
+    assert_eq!(x.get_value(4.0).to_f(), 2.0);
+}
+
+#[test]
+fn every() {
+    let x = Every::new(0.5, Box::new(Linear::new(0.25, 0.0.into(), 1.0.into())));
+    assert_eq!(x.get_duration(), f64::INFINITY);
+    assert_eq!(x.get_value(0.125).to_f(), 0.5);
+    assert_eq!(x.get_value(0.375).to_f(), 1.0);
+    assert_eq!(x.get_value(10.125).to_f(), 0.5);
+}
+
+#[test]
+fn crossfade() {
+    let x = Crossfade::new(
+        1.0,
+        Box::new(Hold::new(2.0, 0.0.into())),
+        Box::new(Linear::new(2.0, 4.0.into(), 6.0.into())),
+    );
+    assert_eq!(x.get_duration(), 3.0);
+    assert_eq!(x.get_value(0.5).to_f(), 0.0);
+    assert_eq!(x.get_value(1.0).to_f(), 0.0);
+    assert_eq!(x.get_value(1.5).to_f(), 2.25);
+    assert_eq!(x.get_value(2.0).to_f(), 5.0);
+    assert_eq!(x.get_value(3.0).to_f(), 6.0);
+}
+
+#[test]
+fn select() {
+    let x = Select::new(
+        Box::new(Linear::new(3.0, 0.0.into(), 3.0.into())),
+        vec![
+            Box::new(Hold::new(1.0, 10.0.into())),
+            Box::new(Linear::new(3.0, 0.0.into(), 3.0.into())),
+        ],
+    );
+    assert_eq!(x.get_duration(), 3.0);
+    assert_eq!(x.get_value(0.5).to_f(), 10.0);
+    assert_eq!(x.get_value(1.5).to_f(), 1.5);
+    assert_eq!(x.get_value(2.5).to_f(), 2.5);
+}
+
+#[test]
+fn speed() {
+    let x = Speed::new(2.0, Box::new(Linear::new(2.0, 0.0.into(), 1.0.into())));
+    assert_eq!(x.get_duration(), 1.0);
+    assert_eq!(x.get_value(0.5).to_f(), 0.5);
+
+    let x = Speed::stretch(4.0, Box::new(Linear::new(2.0, 0.0.into(), 1.0.into())));
+    assert_eq!(x.get_duration(), 4.0);
+    assert_eq!(x.get_value(1.0).to_f(), 0.25);
+}
+
+#[test]
+fn reverse() {
+    let x = Reverse::new(Box::new(Linear::new(2.0, 0.0.into(), 1.0.into())));
+    assert_eq!(x.get_duration(), 2.0);
+    assert_eq!(x.get_value(-1.0).to_f(), 1.0);
+    assert_eq!(x.get_value(0.5).to_f(), 0.75);
+    assert_eq!(x.get_value(3.0).to_f(), 0.0);
+
+    let x = PingPong::new(Box::new(Linear::new(2.0, 0.0.into(), 1.0.into())));
+    assert_eq!(x.get_duration(), f64::INFINITY);
+    assert_eq!(x.get_value(1.0).to_f(), 0.5);
+    assert_eq!(x.get_value(3.0).to_f(), 0.5);
+    assert_eq!(x.get_value(3.5).to_f(), 0.25);
+    assert_eq!(x.get_value(4.5).to_f(), 0.25);
+}
+
+#[test]
+fn clip() {
+    let x = Clip::new(1.0, 3.0, Box::new(Linear::new(4.0, 0.0.into(), 4.0.into())));
+    assert_eq!(x.get_duration(), 2.0);
+    assert_eq!(x.get_value(-1.0).to_f(), 1.0);
+    assert_eq!(x.get_value(0.5).to_f(), 1.5);
+    assert_eq!(x.get_value(5.0).to_f(), 3.0);
+
+    let x = SampleAt::new(1.0, Box::new(Linear::new(4.0, 0.0.into(), 4.0.into())));
+    assert_eq!(x.get_duration(), 4.0);
+    assert_eq!(x.get_value(0.0).to_f(), 1.0);
+    assert_eq!(x.get_value(3.0).to_f(), 1.0);
+}
+
 #[test]
 fn concat() {
     let x = Concat::new(vec![
@@ -197,3 +1706,157 @@ fn concat() {
     assert_eq!(x.get_value(3.0).to_f2(), (0.5, 0.5));
     assert_eq!(x.get_value(4.0).to_f2(), (1.0, 0.0));
 }
+
+#[test]
+fn apply() {
+    let x = Apply::new(
+        vec![
+            Box::new(Linear::new(2.0, 0.0.into(), 2.0.into())),
+            Box::new(Hold::new(1.0, 10.0.into())),
+        ],
+        MathFn::new(|a| a[0] + a[1], |_, d| d[0] + d[1]),
+    );
+
+    assert_eq!(x.get_duration(), 2.0);
+    assert_eq!(x.get_value(0.0).to_f(), 10.0);
+    assert_eq!(x.get_value(1.0).to_f(), 11.0);
+    assert_eq!(x.get_value(3.0).to_f(), 12.0);
+}
+
+#[test]
+fn velocity() {
+    use crate::easing::Curve;
+
+    let x = Linear::new(2.0, 0.0.into(), vec![1.0, -2.0].into());
+    assert_eq!(x.get_velocity(1.0).to_f2(), (0.5, -1.0));
+    assert_eq!(x.get_velocity(3.0).to_f2(), (0.0, 0.0));
+    assert_eq!(Hold::new(1.0, 5.0.into()).get_velocity(0.5).to_f(), 0.0);
+
+    let x = Concat::new(vec![
+        Box::new(Linear::new(1.0, 0.0.into(), 2.0.into())),
+        Box::new(Speed::new(
+            2.0,
+            Box::new(Reverse::new(Box::new(Linear::new(
+                2.0,
+                0.0.into(),
+                1.0.into(),
+            )))),
+        )),
+    ]);
+    assert_eq!(x.get_velocity(0.5).to_f(), 2.0);
+    assert_eq!(x.get_velocity(1.5).to_f(), -1.0);
+
+    // Matches finite differences away from the boundaries of segments
+    let envelopes: Vec<Box<dyn Envelope>> = vec![
+        Box::new(Ease::new(
+            2.0,
+            1.0.into(),
+            3.0.into(),
+            Easing::InOut(Curve::Sine),
+        )),
+        Box::new(Spline::catmull_rom(vec![
+            (0.0, 0.0.into()),
+            (1.0, 2.0.into()),
+            (2.5, 1.0.into()),
+        ])),
+        Box::new(Noise::new(1, 3.0, 2.0.into(), 3)),
+        Box::new(PingPong::new(Box::new(Ease::new(
+            0.75,
+            0.0.into(),
+            1.0.into(),
+            Easing::Smoothstep,
+        )))),
+        Box::new(Crossfade::new(
+            1.0,
+            Box::new(Linear::new(2.0, 0.0.into(), 2.0.into())),
+            Box::new(Noise::new(2, 1.0, 1.0.into(), 1)),
+        )),
+        Box::new(Apply::new(
+            vec![
+                Box::new(Linear::new(4.0, 1.0.into(), 3.0.into())),
+                Box::new(Noise::new(3, 0.5, 1.0.into(), 2)),
+            ],
+            MathFn::new(
+                |x| x[0] * x[1].sin(),
+                |x, d| d[0] * x[1].sin() + x[0] * x[1].cos() * d[1],
+            ),
+        )),
+    ];
+    for (index, x) in envelopes.iter().enumerate() {
+        for i in 0..40 {
+            let time = i as f64 * 0.061 + 0.013;
+            let h = 1e-6;
+            let numeric = (x.get_value(time + h).to_f() - x.get_value(time - h).to_f()) / (2.0 * h);
+            let analytic = x.get_velocity(time).to_f();
+            assert!(
+                (numeric - analytic).abs() < 1e-4,
+                "envelope {} at {}: {} != {}",
+                index,
+                time,
+                analytic,
+                numeric
+            );
+        }
+    }
+}
+
+#[test]
+fn events() {
+    let shape = || Box::new(Linear::new(0.5, 1.0.into(), 0.0.into())) as Box<dyn Envelope>;
+    let x = Events::new(vec![2.0, 1.0], EventOutput::Shape(shape()));
+    assert_eq!(x.get_duration(), 2.5);
+    assert_eq!(x.get_value(0.5).to_f(), 0.0);
+    assert_eq!(x.get_value(1.0).to_f(), 1.0);
+    assert_eq!(x.get_value(1.25).to_f(), 0.5);
+    assert_eq!(x.get_velocity(1.25).to_f(), -2.0);
+    assert_eq!(x.get_value(1.75).to_f(), 0.0);
+    assert_eq!(x.get_value(2.25).to_f(), 0.5);
+
+    let x = Events::new(vec![1.0, 2.0], EventOutput::Since);
+    assert_eq!(x.get_value(0.5).to_f(), f64::INFINITY);
+    assert_eq!(x.get_value(2.5).to_f(), 0.5);
+    assert_eq!(x.get_velocity(2.5).to_f(), 1.0);
+
+    // A bar of four beats at 120 bpm, with accents on the first and third beats
+    let x = Events::pattern(0.5, &[1.0, 0.0, 0.5, 0.0], EventOutput::Index);
+    assert_eq!(x.get_duration(), f64::INFINITY);
+    assert_eq!(x.get_value(-0.1).to_f(), -1.0);
+    assert_eq!(x.get_value(0.9).to_f(), 0.0);
+    assert_eq!(x.get_value(1.9).to_f(), 1.0);
+    assert_eq!(x.get_value(2.1).to_f(), 2.0);
+    assert_eq!(x.get_value(4.0).to_f(), 4.0);
+
+    let x = Events::pattern(0.5, &[0.0, 1.0, 0.0, 0.5], EventOutput::Shape(shape()));
+    assert_eq!(x.get_value(0.25).to_f(), 0.0);
+    assert_eq!(x.get_value(0.75).to_f(), 0.5);
+    assert_eq!(x.get_value(1.75).to_f(), 0.25);
+    assert_eq!(x.get_value(2.75).to_f(), 0.5);
+
+    let x = Decay::new(0.5);
+    assert_eq!(x.get_value(-1.0).to_f(), 1.0);
+    assert_eq!(x.get_value(1.0).to_f(), 0.25);
+    assert_eq!(x.get_velocity(0.5).to_f(), -(2.0f64).ln());
+}
+
+#[test]
+fn color_fades() {
+    let red = Vector([1.0, 0.0, 0.0, 1.0]);
+    let green = Vector([0.0, 1.0, 0.0, 1.0]);
+    let x = ColorFade::new(2.0, red.clone(), green.clone(), ColorSpace::Hsl);
+    assert_eq!(x.get_duration(), 2.0);
+    assert_eq!(x.get_value(-1.0), red);
+    assert_eq!(x.get_value(3.0), green);
+    assert_eq!(x.get_velocity(3.0).to_f(), 0.0);
+    let yellow = x.get_value(1.0);
+    assert!((yellow.0[0] - 1.0).abs() < 1e-9 && (yellow.0[1] - 1.0).abs() < 1e-9);
+    assert!(x.get_velocity(0.5).0[1] > 0.0);
+
+    // The end colours are exact in every space, e.g. `linear_color(#ff0000, #00ff00, 1)`
+    for space in [ColorSpace::LinearRgb, ColorSpace::Hsl, ColorSpace::Oklab].iter() {
+        let orange = Vector([1.0, 0.533, 0.0, 0.5]);
+        let x = ColorFade::new(1.0, orange.clone(), green.clone(), *space);
+        assert_eq!(x.get_value(0.0), orange);
+        assert_eq!(x.get_value(1.0), green);
+        assert_eq!(x.get_value(2.0), green);
+    }
+}
@@ -0,0 +1,303 @@
+// Pretty printer, which prints a parsed script back to source. Number literals keep
+// their original text (e.g. `500ms` or `2b`), and calls and arrays that were split
+// on several lines in the source stay split.
+
+use crate::{ast::*, diagnostics::Diagnostic, parser};
+
+const INDENT: &str = "    ";
+const MAX_WIDTH: usize = 100;
+
+/// Formats a script
+pub fn format(source: &str) -> Result<String, Diagnostic> {
+    let root = parser::parse(source).map_err(|err| Diagnostic::from_parse_error(&err, source))?;
+    let printer = Printer { source };
+    let mut text = match &root.kind {
+        ExprKind::List(_, cons) => printer.statements(cons, 0),
+        _ => printer.expr(&root, 0),
+    };
+    text.push('\n');
+    Ok(text)
+}
+
+struct Printer<'a> {
+    source: &'a str,
+}
+
+impl<'a> Printer<'a> {
+    /// Statements on their own lines. Empty lines between them are kept and
+    /// comments stay on the line they were on.
+    fn statements(&self, cons: &[Expr], indent: usize) -> String {
+        let mut text = String::new();
+        let mut previous: Option<&Expr> = None;
+        for expr in cons.iter() {
+            match previous.map(|p| self.newlines(p.span.end, expr.span.start)) {
+                Some(0) if matches!(expr.kind, ExprKind::Comment(_)) => text.push(' '),
+                newlines => {
+                    match newlines {
+                        Some(1) | Some(0) => text.push('\n'),
+                        Some(_) => text.push_str("\n\n"),
+                        None => (),
+                    }
+                    text.push_str(&INDENT.repeat(indent));
+                }
+            }
+            text.push_str(&self.expr(expr, indent));
+            previous = Some(expr);
+        }
+        text
+    }
+
+    fn expr(&self, expr: &Expr, indent: usize) -> String {
+        self.expr_at(expr, indent, indent * INDENT.len())
+    }
+
+    /// Expression starting from `column` of the current line
+    fn expr_at(&self, expr: &Expr, indent: usize, column: usize) -> String {
+        match &expr.kind {
+            ExprKind::Symbol(name) => name.clone(),
+            ExprKind::NumberList(_) => self.literal(expr),
+            ExprKind::Comment(text) => format!("//{}", text),
+            ExprKind::List(name, cons) => self.list(expr, name, cons, indent, column),
+        }
+    }
+
+    fn list(&self, expr: &Expr, name: &str, cons: &[Expr], indent: usize, column: usize) -> String {
+        let arg = |i: usize| match cons.get(i) {
+            Some(e) => self.expr(e, indent),
+            None => String::new(),
+        };
+        // Value of a definition, after `prefix`
+        let value = |i: usize, prefix: &str| match cons.get(i) {
+            Some(e) => format!(
+                "{}{}",
+                prefix,
+                self.expr_at(e, indent, column + prefix.len())
+            ),
+            None => prefix.to_string(),
+        };
+        match name {
+            KW_ROOT => self.statements(cons, indent),
+            KW_BLOCK if cons.is_empty() => "{}".into(),
+            KW_BLOCK => format!(
+                "{{\n{}\n{}}}",
+                self.statements(cons, indent + 1),
+                INDENT.repeat(indent)
+            ),
+            KW_DEFINE => value(1, &format!("{} = ", arg(0))),
+            KW_EXPORT => value(1, &format!("out {} = ", arg(0))),
+            KW_FUNCTION => {
+                let params = match cons.get(1).map(|e| &e.kind) {
+                    Some(ExprKind::List(_, params)) => params
+                        .iter()
+                        .map(|p| self.expr(p, indent))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    _ => String::new(),
+                };
+                value(2, &format!("{}({}) = ", arg(0), params))
+            }
+            KW_MARKER if cons.len() == 2 => value(1, &format!("marker {} = ", arg(0))),
+            KW_MARKER => {
+                let entries: Vec<String> = cons
+                    .chunks(2)
+                    .map(|pair| {
+                        let value = match pair.get(1) {
+                            Some(e) => self.expr(e, indent + 1),
+                            None => String::new(),
+                        };
+                        format!(
+                            "{}{}: {}",
+                            INDENT.repeat(indent + 1),
+                            self.expr(&pair[0], indent),
+                            value
+                        )
+                    })
+                    .collect();
+                format!(
+                    "markers {{\n{}\n{}}}",
+                    entries.join("\n"),
+                    INDENT.repeat(indent)
+                )
+            }
+            KW_IMPORT => match cons.get(1) {
+                Some(_) => format!("import \"{}\" as {}", arg(0), arg(1)),
+                None => format!("import \"{}\"", arg(0)),
+            },
+            KW_SCENE => format!("scene \"{}\" from {} to {}", arg(0), arg(1), arg(2)),
+            KW_BEATS | KW_BARS => self.literal(expr),
+            KW_ARRAY => self.items(expr, "[", cons, "]", indent, column),
+            OP_ADD | OP_SUB | OP_MUL | OP_DIV => {
+                let precedence = precedence(name);
+                let left = self.operand(cons.first(), indent, |p| p < precedence);
+                let right = self.operand(cons.get(1), indent, |p| p <= precedence);
+                format!("{} {} {}", left, operator(name), right)
+            }
+            OP_NEG => match cons.first() {
+                // `-1` would be parsed as a number
+                Some(e) if matches!(e.kind, ExprKind::NumberList(_)) => {
+                    format!("-({})", self.expr(e, indent))
+                }
+                e => format!("-{}", self.operand(e, indent, |p| p < ATOM)),
+            },
+            _ => self.items(expr, &format!("{}(", name), cons, ")", indent, column),
+        }
+    }
+
+    /// Operand of an operator, in parentheses if `parens` returns true for its precedence
+    fn operand<F>(&self, expr: Option<&Expr>, indent: usize, parens: F) -> String
+    where
+        F: Fn(usize) -> bool,
+    {
+        match expr {
+            Some(e) => {
+                let text = self.expr(e, indent);
+                match &e.kind {
+                    ExprKind::List(name, _) if parens(precedence(name)) => format!("({})", text),
+                    _ => text,
+                }
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Arguments or array items, on one line unless they were split in the source or do not fit
+    fn items(
+        &self,
+        expr: &Expr,
+        open: &str,
+        cons: &[Expr],
+        close: &str,
+        indent: usize,
+        column: usize,
+    ) -> String {
+        let items: Vec<String> = cons.iter().map(|e| self.expr(e, indent)).collect();
+        let flat = format!("{}{}{}", open, items.join(", "), close);
+
+        let mut starts = vec![expr.span.start];
+        starts.extend(cons.iter().map(|e| e.span.end));
+        let split = cons
+            .iter()
+            .zip(starts)
+            .any(|(e, start)| self.newlines(start, e.span.start) > 0);
+        let too_wide = !flat.contains('\n') && column + flat.len() > MAX_WIDTH;
+
+        if cons.is_empty() || !(split || too_wide) {
+            flat
+        } else {
+            let items: Vec<String> = cons
+                .iter()
+                .map(|e| format!("{}{}", INDENT.repeat(indent + 1), self.expr(e, indent + 1)))
+                .collect();
+            format!(
+                "{}\n{}\n{}{}",
+                open,
+                items.join(",\n"),
+                INDENT.repeat(indent),
+                close
+            )
+        }
+    }
+
+    /// Numbers are printed as written, so that units are kept
+    fn literal(&self, expr: &Expr) -> String {
+        if !expr.span.is_empty() {
+            if let Some(text) = self.source.get(expr.span.start..expr.span.end) {
+                return text.into();
+            }
+        }
+        match &expr.kind {
+            ExprKind::NumberList(n) if n.len() == 1 => n[0].to_string(),
+            ExprKind::NumberList(n) => {
+                let items: Vec<String> = n.iter().map(|x| x.to_string()).collect();
+                format!("[{}]", items.join(", "))
+            }
+            ExprKind::List(name, cons) => {
+                let count = match cons.first().map(|e| &e.kind) {
+                    Some(ExprKind::NumberList(n)) => n.first().cloned().unwrap_or(0.0),
+                    _ => 0.0,
+                };
+                let unit = if name == KW_BARS { "bar" } else { "b" };
+                format!("{}{}", count, unit)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn newlines(&self, start: usize, end: usize) -> usize {
+        match self.source.get(start..end) {
+            Some(text) if start < end => text.matches('\n').count(),
+            _ => 0,
+        }
+    }
+}
+
+const ATOM: usize = 3;
+
+fn precedence(name: &str) -> usize {
+    match name {
+        OP_ADD | OP_SUB => 1,
+        OP_MUL | OP_DIV => 2,
+        _ => ATOM,
+    }
+}
+
+fn operator(name: &str) -> &'static str {
+    match name {
+        OP_ADD => "+",
+        OP_SUB => "-",
+        OP_MUL => "*",
+        _ => "/",
+    }
+}
+
+#[cfg(test)]
+fn assert_formats(source: &str, expected: &str) {
+    let formatted = format(source).unwrap();
+    assert_eq!(formatted, expected);
+    assert_eq!(
+        parser::parse(&formatted).unwrap(),
+        parser::parse(source).unwrap()
+    );
+    assert_eq!(format(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn formatting() {
+    assert_formats(
+        "import   \"lib/palette.boe\"  as pal\n\
+         bpm=120\n\n\n\
+         // Timing\n\
+         f( x,y )=hold( x, 500ms+1b )   // note\n\
+         out a=concat(\n\
+         linear(0,1,2,ease_in_quad),hold([ 1,2 ],1bar))\n\
+         markers { intro: 0, drop: 4b }\n\
+         scene  \"tunnel\"  from drop to  drop+8b\n\
+         out b = (1 - (2 - 3)) * -(1 + 2) - -linear(0, 1, 1) / (2 * 3)\n\
+         out c = {\n\
+         x = 1\n\
+         {}\n\
+         }",
+        "import \"lib/palette.boe\" as pal\n\
+         bpm = 120\n\
+         \n\
+         // Timing\n\
+         f(x, y) = hold(x, 500ms + 1b) // note\n\
+         out a = concat(\n    linear(0, 1, 2, ease_in_quad),\n    hold([1, 2], 1bar)\n)\n\
+         markers {\n    intro: 0\n    drop: 4b\n}\n\
+         scene \"tunnel\" from drop to drop + 8b\n\
+         out b = (1 - (2 - 3)) * -(1 + 2) - -linear(0, 1, 1) / (2 * 3)\n\
+         out c = {\n    x = 1\n    {}\n}\n",
+    );
+}
+
+#[test]
+fn long_lines() {
+    let source = "out a = spline([[0, [0, 0, 0]], [1, [1, 2, 3]], [2, [3, 2, 1]], [3, [0, 0, 0]], [4, [1, 1, 1]], [5, [0, 0, 0]]])";
+    let formatted = format(source).unwrap();
+    assert!(formatted.lines().all(|line| line.len() <= MAX_WIDTH));
+    assert!(formatted.starts_with("out a = spline(\n    [[0, [0, 0, 0]], [1, [1, 2, 3]],"));
+    assert_eq!(
+        parser::parse(&formatted).unwrap(),
+        parser::parse(source).unwrap()
+    );
+}
@@ -0,0 +1,708 @@
+// GLSL backend. Every node of a program becomes a function of time, so that shaders can
+// evaluate the exports at any time, e.g. per pixel, instead of receiving one value per
+// frame. The functions follow `Program::eval` in single precision and need GLSL 4.00.
+
+use crate::{
+    color::ColorSpace,
+    easing::Easing,
+    envelope::{EventOutput, MathOp},
+    program::{Node, Program},
+    vector::Vector,
+};
+use std::fmt::Write;
+
+/// Helpers shared by the scripts of a shader. Randomness uses the same 64-bit hash as
+/// `random`, emulated with pairs of 32-bit words (high, low).
+const LIBRARY: &str = "#ifndef BOENTHOESCRIPT_LIBRARY
+#define BOENTHOESCRIPT_LIBRARY
+
+const float BOE_PI = 3.14159265358979;
+
+float boe_fmod(float x, float y) {
+    return x - y * trunc(x / y);
+}
+
+float boe_pingpong(float t, float period) {
+    float x = mod(t, 2.0 * period);
+    return x > period ? 2.0 * period - x : x;
+}
+
+float boe_bounce_out(float t) {
+    if (t < 1.0 / 2.75) {
+        return 7.5625 * t * t;
+    } else if (t < 2.0 / 2.75) {
+        t -= 1.5 / 2.75;
+        return 7.5625 * t * t + 0.75;
+    } else if (t < 2.5 / 2.75) {
+        t -= 2.25 / 2.75;
+        return 7.5625 * t * t + 0.9375;
+    }
+    t -= 2.625 / 2.75;
+    return 7.5625 * t * t + 0.984375;
+}
+
+float boe_ease_in(int curve, float t) {
+    switch (curve) {
+    case 0:
+        return t * t;
+    case 1:
+        return t * t * t;
+    case 2:
+        return t * t * t * t;
+    case 3:
+        return t <= 0.0 ? 0.0 : pow(2.0, 10.0 * t - 10.0);
+    case 4:
+        return 1.0 - cos(t * BOE_PI / 2.0);
+    case 5:
+        return 2.70158 * t * t * t - 1.70158 * t * t;
+    case 6:
+        if (t <= 0.0 || t >= 1.0) {
+            return t;
+        }
+        return -pow(2.0, 10.0 * t - 10.0) * sin((t * 10.0 - 10.75) * (2.0 * BOE_PI / 3.0));
+    default:
+        return 1.0 - boe_bounce_out(1.0 - t);
+    }
+}
+
+// Modes are smoothstep, in, out and in-out
+float boe_ease(int mode, int curve, float t) {
+    t = clamp(t, 0.0, 1.0);
+    switch (mode) {
+    case 0:
+        return t * t * (3.0 - 2.0 * t);
+    case 1:
+        return boe_ease_in(curve, t);
+    case 2:
+        return 1.0 - boe_ease_in(curve, 1.0 - t);
+    default:
+        return t < 0.5 ? boe_ease_in(curve, 2.0 * t) / 2.0
+                       : 1.0 - boe_ease_in(curve, 2.0 - 2.0 * t) / 2.0;
+    }
+}
+
+float boe_to_srgb(float c) {
+    return c <= 0.0031308 ? c * 12.92 : 1.055 * pow(c, 1.0 / 2.4) - 0.055;
+}
+
+vec3 boe_hsl_to_rgb(vec3 hsl) {
+    float q = hsl.z < 0.5 ? hsl.z * (1.0 + hsl.y) : hsl.z + hsl.y - hsl.z * hsl.y;
+    float p = 2.0 * hsl.z - q;
+    vec3 rgb;
+    for (int i = 0; i < 3; i++) {
+        float offset = (1.0 - float(i)) / 3.0;
+        float t = hsl.x + offset - floor(hsl.x + offset);
+        rgb[i] = t < 1.0 / 6.0 ? p + (q - p) * 6.0 * t
+               : t < 0.5 ? q
+               : t < 2.0 / 3.0 ? p + (q - p) * (4.0 - 6.0 * t)
+               : p;
+    }
+    return rgb;
+}
+
+vec3 boe_oklab_to_linear(vec3 lab) {
+    vec3 lms = vec3(
+        dot(vec3(1.0, 0.3963377774, 0.2158037573), lab),
+        dot(vec3(1.0, -0.1055613458, -0.0638541728), lab),
+        dot(vec3(1.0, -0.0894841775, -1.2914855480), lab));
+    lms = lms * lms * lms;
+    return vec3(
+        dot(vec3(4.0767416621, -3.3077115913, 0.2309699292), lms),
+        dot(vec3(-1.2684380046, 2.6097574011, -0.3413193965), lms),
+        dot(vec3(-0.0041960863, -0.7034186147, 1.7076147010), lms));
+}
+
+// Spaces are linear RGB, HSL and Oklab
+// `from` and `to` are the end colours, `a` and `b` the end points in the colour space
+vec4 boe_color_fade(int space, float duration, vec4 from, vec4 to, vec4 a, vec4 b, float t) {
+    if (t <= 0.0) {
+        return from;
+    }
+    if (t >= duration) {
+        return to;
+    }
+    vec4 color = mix(a, b, t / duration);
+    if (space == 1) {
+        return vec4(boe_hsl_to_rgb(color.xyz), color.w);
+    }
+    vec3 rgb = space == 2 ? boe_oklab_to_linear(color.xyz) : color.xyz;
+    return vec4(boe_to_srgb(rgb.x), boe_to_srgb(rgb.y), boe_to_srgb(rgb.z), color.w);
+}
+
+vec4 boe_hermite(float t0, vec4 a, vec4 out_tangent, float t1, vec4 b, vec4 in_tangent, float t) {
+    float h = t1 - t0;
+    float s = (t - t0) / h;
+    float s2 = s * s;
+    float s3 = s2 * s;
+    return a * (2.0 * s3 - 3.0 * s2 + 1.0) + out_tangent * (h * (s3 - 2.0 * s2 + s))
+        + b * (-2.0 * s3 + 3.0 * s2) + in_tangent * (h * (s3 - s2));
+}
+
+uvec2 boe_add64(uvec2 a, uvec2 b) {
+    uint carry;
+    uint low = uaddCarry(a.y, b.y, carry);
+    return uvec2(a.x + b.x + carry, low);
+}
+
+uvec2 boe_mul64(uvec2 a, uvec2 b) {
+    uint high, low;
+    umulExtended(a.y, b.y, high, low);
+    return uvec2(high + a.x * b.y + a.y * b.x, low);
+}
+
+// x ^ (x >> shift) for shifts of 1 to 31
+uvec2 boe_xorshift64(uvec2 x, int shift) {
+    return x ^ uvec2(x.x >> shift, (x.y >> shift) | (x.x << (32 - shift)));
+}
+
+float boe_unit(uvec2 seed, uint stream, int index) {
+    const uvec2 k1 = uvec2(0x9e3779b9u, 0x7f4a7c15u);
+    const uvec2 k2 = uvec2(0xbf58476du, 0x1ce4e5b9u);
+    const uvec2 k3 = uvec2(0x94d049bbu, 0x133111ebu);
+    uvec2 x = boe_add64(
+        boe_add64(boe_mul64(seed, k1), boe_mul64(uvec2(0u, stream), k2)),
+        boe_mul64(uvec2(index < 0 ? 0xffffffffu : 0u, uint(index)), k3));
+    x = boe_mul64(boe_xorshift64(x, 30), k2);
+    x = boe_mul64(boe_xorshift64(x, 27), k3);
+    x = boe_xorshift64(x, 31);
+    return float(x.x >> 8) / 16777216.0;
+}
+
+vec4 boe_random(uvec2 seed, int index) {
+    return vec4(
+        boe_unit(seed, 0u, index),
+        boe_unit(seed, 1u, index),
+        boe_unit(seed, 2u, index),
+        boe_unit(seed, 3u, index));
+}
+
+float boe_noise(uvec2 seed, uint stream, float x) {
+    float i = floor(x);
+    float f = x - i;
+    float a = (boe_unit(seed, stream, int(i)) * 2.0 - 1.0) * f;
+    float b = (boe_unit(seed, stream, int(i) + 1) * 2.0 - 1.0) * (f - 1.0);
+    float fade = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    return (a + (b - a) * fade) * 2.0;
+}
+
+float boe_fbm(uvec2 seed, uint stream, float x, int octaves) {
+    float sum = 0.0;
+    float amplitude = 1.0;
+    float frequency = 1.0;
+    float total = 0.0;
+    for (int octave = 0; octave < octaves; octave++) {
+        sum += boe_noise(seed, stream + uint(octave) * 0x1000u, x * frequency) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return total > 0.0 ? sum / total : 0.0;
+}
+
+vec4 boe_fbm4(uvec2 seed, float x, int octaves) {
+    return vec4(
+        boe_fbm(seed, 0u, x, octaves),
+        boe_fbm(seed, 1u, x, octaves),
+        boe_fbm(seed, 2u, x, octaves),
+        boe_fbm(seed, 3u, x, octaves));
+}
+
+#endif
+";
+
+impl Program {
+    /// GLSL source that defines `vec4 env_<name>(float t)` for each export. Functions of
+    /// the nodes are prefixed with `namespace`, so that a shader can include several scripts.
+    pub fn to_glsl(&self, namespace: &str) -> Result<String, String> {
+        let namespace = identifier(namespace);
+        let mut glsl = String::from(LIBRARY);
+        let guard = format!("BOENTHOESCRIPT_{}", namespace.to_uppercase());
+        writeln!(glsl, "\n#ifndef {0}\n#define {0}", guard).unwrap();
+        for index in 0..self.nodes.len() {
+            let body = self.node_body(&namespace, index)?;
+            writeln!(
+                glsl,
+                "\nvec4 {}(float t) {{\n{}}}",
+                node_name(&namespace, index as u32),
+                body
+            )
+            .unwrap();
+        }
+        for (name, root) in self.outputs.iter() {
+            writeln!(
+                glsl,
+                "\nvec4 env_{}(float t) {{\n    return {}(t);\n}}",
+                identifier(name),
+                node_name(&namespace, *root)
+            )
+            .unwrap();
+        }
+        glsl.push_str("\n#endif\n");
+        Ok(glsl)
+    }
+
+    /// Statements of the function of a node, indented and ending with a newline
+    fn node_body(&self, namespace: &str, index: usize) -> Result<String, String> {
+        let call = |node: &u32, time: &str| format!("{}({})", node_name(namespace, *node), time);
+        let body = match &self.nodes[index] {
+            Node::Hold { value } => format!("return {};", vector(value)),
+            Node::Linear { duration, a, b } if *duration > 0.0 => format!(
+                "return {} + {} * min(t / {}, 1.0);",
+                vector(a),
+                vector(b),
+                float(*duration)
+            ),
+            Node::Linear { a, b, .. } => format!("return {} + {};", vector(a), vector(b)),
+            Node::Ease {
+                duration,
+                a,
+                b,
+                easing,
+            } if *duration > 0.0 => {
+                let (mode, curve) = match easing {
+                    Easing::Smoothstep => (0, 0),
+                    Easing::In(curve) => (1, *curve as i32),
+                    Easing::Out(curve) => (2, *curve as i32),
+                    Easing::InOut(curve) => (3, *curve as i32),
+                };
+                format!(
+                    "return {} + {} * boe_ease({}, {}, t / {});",
+                    vector(a),
+                    vector(b),
+                    mode,
+                    curve,
+                    float(*duration)
+                )
+            }
+            Node::Ease { a, b, .. } => format!("return {} + {};", vector(a), vector(b)),
+            Node::ColorFade {
+                duration,
+                from,
+                to,
+                a,
+                b,
+                space,
+            } => {
+                let space = match space {
+                    ColorSpace::LinearRgb => 0,
+                    ColorSpace::Hsl => 1,
+                    ColorSpace::Oklab => 2,
+                };
+                format!(
+                    "return boe_color_fade({}, {}, {}, {}, {}, {}, t);",
+                    space,
+                    float(*duration),
+                    vector(from),
+                    vector(to),
+                    vector(a),
+                    vector(b)
+                )
+            }
+            Node::Spline { first, count } => {
+                let keys = &self.keys[*first as usize..(first + count) as usize];
+                let mut body = String::new();
+                if let Some(key) = keys.first() {
+                    writeln!(
+                        body,
+                        "if (t < {}) return {};",
+                        float(key.time),
+                        vector(&key.value)
+                    )
+                    .unwrap();
+                }
+                for pair in keys.windows(2) {
+                    let (a, b) = (&pair[0], &pair[1]);
+                    writeln!(
+                        body,
+                        "if (t < {}) return boe_hermite({}, {}, {}, {}, {}, {}, t);",
+                        float(b.time),
+                        float(a.time),
+                        vector(&a.value),
+                        vector(&a.out_tangent),
+                        float(b.time),
+                        vector(&b.value),
+                        vector(&b.in_tangent)
+                    )
+                    .unwrap();
+                }
+                let last = keys
+                    .last()
+                    .map_or(Vector::default(), |key| key.value.clone());
+                body + &format!("return {};", vector(&last))
+            }
+            Node::Random {
+                seed,
+                min,
+                range,
+                interval,
+            } => format!(
+                "return {} + {} * boe_random({}, int(floor(t / {})));",
+                vector(min),
+                vector(range),
+                seed64(*seed),
+                float(*interval)
+            ),
+            Node::Noise {
+                seed,
+                frequency,
+                amplitude,
+                octaves,
+            } => format!(
+                "return {} * boe_fbm4({}, t * {}, {});",
+                vector(amplitude),
+                seed64(*seed),
+                float(*frequency),
+                octaves
+            ),
+            Node::Concat { first, count } => {
+                let segments = &self.segments[*first as usize..(first + count) as usize];
+                let mut body = String::new();
+                let mut start = 0.0;
+                for (i, segment) in segments.iter().enumerate() {
+                    let time = if start == 0.0 {
+                        String::from("t")
+                    } else {
+                        format!("t - {}", float(start))
+                    };
+                    let value = call(&segment.node, &time);
+                    if i + 1 < segments.len() {
+                        writeln!(body, "if (t < {}) return {};", float(segment.end), value)
+                            .unwrap();
+                    } else {
+                        write!(body, "return {};", value).unwrap();
+                    }
+                    start = segment.end;
+                }
+                if segments.is_empty() {
+                    body.push_str("return vec4(0.0);");
+                }
+                body
+            }
+            Node::Apply { first, count, func } => {
+                let op = func
+                    .op
+                    .ok_or("custom math functions can not be converted to GLSL")?;
+                let args = &self.operands[*first as usize..(first + count) as usize];
+                let mut body = String::new();
+                for (i, arg) in args.iter().enumerate() {
+                    writeln!(body, "vec4 x{} = {};", i, call(arg, "t")).unwrap();
+                }
+                body + &format!("return {};", math(op))
+            }
+            Node::Delay { offset, node } => {
+                format!(
+                    "return {};",
+                    call(node, &format!("max(t - {}, 0.0)", float(*offset)))
+                )
+            }
+            Node::Repeat {
+                duration,
+                period,
+                node,
+            } => {
+                let repeated = wrap("boe_fmod", *period);
+                let time = if duration.is_finite() {
+                    format!("t > {} ? t : {}", float(*duration), repeated)
+                } else {
+                    repeated
+                };
+                format!("return {};", call(node, &time))
+            }
+            Node::Loop { period, node } => {
+                format!("return {};", call(node, &wrap("boe_fmod", *period)))
+            }
+            Node::Every { interval, node } => {
+                format!("return {};", call(node, &wrap("mod", *interval)))
+            }
+            Node::Speed { factor, node } => {
+                format!("return {};", call(node, &format!("t * {}", float(*factor))))
+            }
+            Node::Reverse { duration, node } => {
+                let duration = float(*duration);
+                format!(
+                    "return {};",
+                    call(node, &format!("{0} - min(max(t, 0.0), {0})", duration))
+                )
+            }
+            Node::PingPong { period, node } => {
+                let time = if period.is_finite() {
+                    format!("boe_pingpong(t, {})", float(*period))
+                } else {
+                    String::from("t")
+                };
+                format!("return {};", call(node, &time))
+            }
+            Node::Clip {
+                start,
+                length,
+                node,
+            } => format!(
+                "return {};",
+                call(
+                    node,
+                    &format!("{} + min(max(t, 0.0), {})", float(*start), float(*length))
+                )
+            ),
+            Node::SampleAt { time, node } => format!("return {};", call(node, &float(*time))),
+            Node::Crossfade { start, fade, a, b } => format!(
+                "float f = t - {start};\n\
+                 if (f < 0.0) return {a};\n\
+                 if (f >= {fade}) return {b};\n\
+                 vec4 a = {a};\n\
+                 return a + ({b} - a) * (f / {fade});",
+                start = float(*start),
+                fade = float(*fade),
+                a = call(a, "t"),
+                b = call(b, "f"),
+            ),
+            Node::Select {
+                index,
+                first,
+                count,
+            } => {
+                let choices = &self.choices[*first as usize..(first + count) as usize];
+                let mut body = format!("float i = floor({}.x);\n", call(index, "t"));
+                for (i, choice) in choices.iter().enumerate() {
+                    if i + 1 < choices.len() {
+                        writeln!(body, "if (i < {}.0) return {};", i + 1, call(choice, "t"))
+                            .unwrap();
+                    } else {
+                        write!(body, "return {};", call(choice, "t")).unwrap();
+                    }
+                }
+                if choices.is_empty() {
+                    body.push_str("return vec4(0.0);");
+                }
+                body
+            }
+            Node::Decay { half_life } => format!(
+                "return vec4(pow(0.5, max(t, 0.0) / {}), 0.0, 0.0, 0.0);",
+                float(*half_life)
+            ),
+            Node::Events {
+                first,
+                count,
+                period,
+                output,
+            } => {
+                let events = &self.events[*first as usize..(first + count) as usize];
+                self.events_body(namespace, events, *period, output)
+            }
+        };
+        let mut indented = String::new();
+        for line in body.lines() {
+            writeln!(indented, "    {}", line).unwrap();
+        }
+        Ok(indented)
+    }
+
+    /// Finds the latest event like `last_event`, with a binary search over the event times
+    fn events_body(
+        &self,
+        namespace: &str,
+        events: &[(f64, f64)],
+        period: f64,
+        output: &EventOutput<u32>,
+    ) -> String {
+        let none = match output {
+            EventOutput::Shape(_) => String::from("vec4(0.0)"),
+            EventOutput::Since => format!("vec4({}, 0.0, 0.0, 0.0)", float(f64::INFINITY)),
+            EventOutput::Index => String::from("vec4(-1.0, 0.0, 0.0, 0.0)"),
+        };
+        if events.is_empty() {
+            return format!("return {};", none);
+        }
+        let list = |values: Vec<f64>| values.into_iter().map(float).collect::<Vec<_>>().join(", ");
+        let n = events.len();
+        let mut body = String::new();
+        writeln!(
+            body,
+            "const float times[{0}] = float[{0}]({1});\n\
+             const float weights[{0}] = float[{0}]({2});\n\
+             if (t < 0.0) return {3};",
+            n,
+            list(events.iter().map(|(time, _)| *time).collect()),
+            list(events.iter().map(|(_, weight)| *weight).collect()),
+            none
+        )
+        .unwrap();
+        if period.is_finite() {
+            let period = float(period);
+            writeln!(
+                body,
+                "float cycle = floor(t / {0});\n\
+                 float x = t - (cycle > 0.0 ? cycle * {0} : 0.0);",
+                period
+            )
+            .unwrap();
+        } else {
+            body.push_str("float cycle = 0.0;\nfloat x = t;\n");
+        }
+        writeln!(
+            body,
+            "int low = 0;\n\
+             int high = {0};\n\
+             while (low < high) {{\n    \
+                 int middle = (low + high) / 2;\n    \
+                 if (times[middle] <= x) low = middle + 1; else high = middle;\n\
+             }}\n\
+             int index = low - 1;\n\
+             if (index < 0) {{\n    \
+                 if (cycle <= 0.0) return {1};\n    \
+                 cycle -= 1.0;\n    \
+                 index = {2};\n\
+             }}",
+            n,
+            none,
+            n - 1
+        )
+        .unwrap();
+        if period.is_finite() {
+            writeln!(
+                body,
+                "float start = times[index] + (cycle > 0.0 ? cycle * {} : 0.0);",
+                float(period)
+            )
+            .unwrap();
+        } else {
+            body.push_str("float start = times[index];\n");
+        }
+        let value = match output {
+            EventOutput::Shape(node) => format!(
+                "{}(t - start) * weights[index]",
+                node_name(namespace, *node)
+            ),
+            EventOutput::Since => String::from("vec4(t - start, 0.0, 0.0, 0.0)"),
+            EventOutput::Index => format!("vec4(cycle * {}.0 + float(index), 0.0, 0.0, 0.0)", n),
+        };
+        body + &format!("return {};", value)
+    }
+}
+
+fn node_name(namespace: &str, node: u32) -> String {
+    format!("{}_node{}", namespace, node)
+}
+
+/// Name with the characters that are not allowed in GLSL identifiers replaced
+fn identifier(name: &str) -> String {
+    let mut identifier: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !identifier.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        identifier.insert(0, 'x');
+    }
+    identifier
+}
+
+/// Time wrapped to `0..period` with `function`, or `t` if the period is infinite
+fn wrap(function: &str, period: f64) -> String {
+    if period.is_finite() {
+        format!("{}(t, {})", function, float(period))
+    } else {
+        String::from("t")
+    }
+}
+
+fn math(op: MathOp) -> &'static str {
+    match op {
+        MathOp::Add => "x0 + x1",
+        MathOp::Sub => "x0 - x1",
+        MathOp::Mul => "x0 * x1",
+        MathOp::Div => "x0 / x1",
+        MathOp::Neg => "-x0",
+        MathOp::Sin => "sin(x0)",
+        MathOp::Cos => "cos(x0)",
+        MathOp::Abs => "abs(x0)",
+        MathOp::Sqrt => "sqrt(x0)",
+        MathOp::Floor => "floor(x0)",
+        MathOp::Fract => "fract(x0)",
+        MathOp::Min => "min(x0, x1)",
+        MathOp::Max => "max(x0, x1)",
+        MathOp::Pow => "pow(x0, x1)",
+        MathOp::Clamp => "min(max(x0, x1), x2)",
+        MathOp::Mix => "x0 + (x1 - x0) * x2",
+    }
+}
+
+/// Float literal. Infinities are not representable as literals, so they are built from bits.
+fn float(x: f64) -> String {
+    let x = x as f32;
+    if x.is_nan() {
+        String::from("uintBitsToFloat(0x7fc00000u)")
+    } else if x == f32::INFINITY {
+        String::from("uintBitsToFloat(0x7f800000u)")
+    } else if x == f32::NEG_INFINITY {
+        String::from("uintBitsToFloat(0xff800000u)")
+    } else {
+        format!("{:?}", x)
+    }
+}
+
+fn vector(v: &Vector) -> String {
+    if v.0.iter().all(|x| *x == v.0[0]) {
+        format!("vec4({})", float(v.0[0]))
+    } else {
+        let components: Vec<String> = v.0.iter().map(|x| float(*x)).collect();
+        format!("vec4({})", components.join(", "))
+    }
+}
+
+fn seed64(seed: u64) -> String {
+    format!(
+        "uvec2({:#010x}u, {:#010x}u)",
+        seed >> 32,
+        seed & 0xffff_ffff
+    )
+}
+
+#[cfg(feature = "compiler")]
+#[test]
+fn transpiling() {
+    let module = crate::compile(
+        "bpm = 120\n\
+         out camera.eye = concat(hold([1, 1], 1), linear([1, 1], [2, 3], 2), ease_in_cubic([0, 0], [1, 1], 1))\n\
+         out b = spline([[0, 0], [1, 2], [3, 4]]) + random(3, 0, 1, 0.25) * fbm(1, 2, 1)\n\
+         out c = at(1, repeat(3, linear(0, 1, 0.5))) - every(1b, pingpong(clip(0.25, 0.75, linear(0, 1, 1))))\n\
+         out d = select(linear(-1, 3, 4), [hold(1, 1), crossfade(linear(0, 1, 2), hold(2, 2), 0.5)])\n\
+         out e = events([1, 3], since) + on_beat(100, [1, 0, 0.5], decay(0.2)) + concat()\n\
+         out f = linear_color(#ff8800, #2040c0, 1, hsl)",
+    )
+    .unwrap();
+    let glsl = module.program.to_glsl("2d-scene").unwrap();
+    assert!(glsl.contains("#ifndef BOENTHOESCRIPT_X2D_SCENE"));
+    for name in ["camera_eye", "b", "c", "d", "e", "f"].iter() {
+        assert!(
+            glsl.contains(&format!("vec4 env_{}(float t) {{", name)),
+            "{}",
+            name
+        );
+    }
+    assert!(glsl.contains("uintBitsToFloat(0x7f800000u)"));
+    assert_eq!(glsl.matches('{').count(), glsl.matches('}').count());
+
+    // GLSL has no recursion, so every node must be defined before it is called
+    for index in 0..module.program.nodes.len() {
+        let name = format!("x2d_scene_node{}(", index);
+        let definition = glsl.find(&format!("vec4 {}", name)).unwrap();
+        assert_eq!(glsl.find(&name), Some(definition + 5), "{}", name);
+    }
+}
+
+#[test]
+fn unsupported_nodes() {
+    let mut program = Program::default();
+    let x = program.push(Node::Hold { value: 1.0.into() }, 1.0);
+    let func = crate::envelope::MathFn::new(|x| x[0], |_, d| d[0]);
+    let apply = program.push_apply(&[x], func, 1.0);
+    program.outputs.push((String::from("a"), apply));
+    assert!(program.to_glsl("a").is_err());
+
+    assert_eq!(float(0.5), "0.5");
+    assert_eq!(float(1e-7), "1e-7");
+    assert_eq!(float(f64::NEG_INFINITY), "uintBitsToFloat(0xff800000u)");
+    assert_eq!(
+        vector(&Vector([1.0, 2.0, 0.0, 0.0])),
+        "vec4(1.0, 2.0, 0.0, 0.0)"
+    );
+}
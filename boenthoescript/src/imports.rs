@@ -0,0 +1,195 @@
+// Resolves `import "path"` statements before compilation. Imported scripts are
+// loaded with a callback, so that the host decides where the files come from.
+
+use crate::{ast::*, compiler::BuildError, diagnostics::Diagnostic, parser};
+use std::collections::HashMap;
+
+/// Parsed imported script
+#[derive(Clone)]
+pub struct Source {
+    pub source: String,
+    pub ast: Expr,
+}
+
+/// Imported scripts by their resolved path
+pub type Sources = HashMap<String, Source>;
+
+/// Loads imports of `root`, which was loaded from `path`, and their imports.
+/// Import paths in the tree are replaced with paths resolved relative to the importing script.
+pub fn resolve<F>(root: &mut Expr, path: &str, load: &mut F) -> Result<Sources, BuildError>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let mut sources = Sources::new();
+    let mut stack = vec![normalize(path)];
+    resolve_imports(root, &mut stack, &mut sources, load)?;
+    Ok(sources)
+}
+
+fn resolve_imports<F>(
+    root: &mut Expr,
+    stack: &mut Vec<String>,
+    sources: &mut Sources,
+    load: &mut F,
+) -> Result<(), BuildError>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let from = stack.last().cloned().unwrap_or_default();
+    let items = match &mut root.kind {
+        ExprKind::List(_, items) => items,
+        _ => return Ok(()),
+    };
+
+    // Imports are only allowed on the top level
+    for item in items.iter_mut() {
+        let span = item.span;
+        let target = match &mut item.kind {
+            ExprKind::List(keyword, cons) if keyword == KW_IMPORT => match cons.first_mut() {
+                Some(target) => target,
+                None => return Err(BuildError::MissingArgument.at(span)),
+            },
+            _ => continue,
+        };
+        let path = match &target.kind {
+            ExprKind::Symbol(name) => join(&from, name),
+            _ => return Err(BuildError::MissingArgument.at(span)),
+        };
+        target.kind = ExprKind::Symbol(path.clone());
+
+        if let Some(start) = stack.iter().position(|p| *p == path) {
+            let mut cycle = stack[start..].to_vec();
+            cycle.push(path);
+            return Err(BuildError::ImportCycle(cycle).at(span));
+        }
+        if sources.contains_key(&path) {
+            continue;
+        }
+
+        let import_error = |message: String| {
+            BuildError::Import {
+                path: path.clone(),
+                message,
+            }
+            .at(span)
+        };
+        let source = load(&path).map_err(import_error)?;
+        let mut ast = parser::parse(&source).map_err(|err| {
+            import_error(Diagnostic::from_parse_error(&err, &source).render(&source))
+        })?;
+
+        stack.push(path.clone());
+        let result = resolve_imports(&mut ast, stack, sources, load);
+        stack.pop();
+        if let Err(err) = result {
+            return Err(match err.cause() {
+                // Cycles are reported at the import of the main script
+                BuildError::ImportCycle(cycle) => BuildError::ImportCycle(cycle.clone()).at(span),
+                _ => import_error(Diagnostic::from_build_error(&err, &ast).render(&source)),
+            });
+        }
+
+        sources.insert(path, Source { source, ast });
+    }
+    Ok(())
+}
+
+/// Path relative to the directory of `from`, with `.` and `..` removed
+fn join(from: &str, path: &str) -> String {
+    match path.strip_prefix('/') {
+        Some(absolute) => normalize(absolute),
+        None => match from.rfind('/') {
+            Some(i) => normalize(&format!("{}/{}", &from[..i], path)),
+            None => normalize(path),
+        },
+    }
+}
+
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            ".." if !parts.is_empty() && parts.last() != Some(&"..") => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+fn load_from(files: &[(&str, &str)]) -> impl FnMut(&str) -> Result<String, String> {
+    let files: HashMap<String, String> = files
+        .iter()
+        .map(|(path, source)| (path.to_string(), source.to_string()))
+        .collect();
+    move |path| {
+        files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| format!("file not found: {}", path))
+    }
+}
+
+#[test]
+fn paths() {
+    assert_eq!(join("scripts/main.boe", "common.boe"), "scripts/common.boe");
+    assert_eq!(join("scripts/main.boe", "../common.boe"), "common.boe");
+    assert_eq!(join("scripts/main.boe", "/lib/./a.boe"), "lib/a.boe");
+    assert_eq!(join("main.boe", "a/b/../c.boe"), "a/c.boe");
+    assert_eq!(normalize("../a/./b"), "../a/b");
+}
+
+#[test]
+fn resolving() {
+    let mut load = load_from(&[
+        ("fx/common.boe", "import \"../timing.boe\"\nfade = 1"),
+        ("timing.boe", "bpm = 120"),
+    ]);
+    let mut root = parser::parse("import \"common.boe\" as c\nout a = hold(1, c.fade)").unwrap();
+    let sources = resolve(&mut root, "fx/main.boe", &mut load).unwrap();
+
+    let mut paths: Vec<&String> = sources.keys().collect();
+    paths.sort();
+    assert_eq!(paths, vec!["fx/common.boe", "timing.boe"]);
+    match &root.kind {
+        ExprKind::List(_, items) => assert_eq!(
+            items[0],
+            Expr::list(KW_IMPORT, vec!["fx/common.boe".into(), "c".into()])
+        ),
+        _ => panic!("Not a list"),
+    }
+}
+
+#[test]
+fn resolving_errors() {
+    let mut load = load_from(&[
+        ("a.boe", "import \"b.boe\""),
+        ("b.boe", "import \"a.boe\""),
+        ("broken.boe", "x = hold(1"),
+    ]);
+
+    let mut root = parser::parse("import \"a.boe\"").unwrap();
+    let err = resolve(&mut root, "main.boe", &mut load).err().unwrap();
+    match err.cause() {
+        BuildError::ImportCycle(cycle) => assert_eq!(cycle, &vec!["a.boe", "b.boe", "a.boe"]),
+        x => panic!("Unexpected error: {:?}", x),
+    }
+
+    let mut root = parser::parse("import \"missing.boe\"").unwrap();
+    let err = resolve(&mut root, "main.boe", &mut load).err().unwrap();
+    assert_eq!(err.to_string(), "error in imported script `missing.boe`");
+    assert_eq!(err.span(), Some(Span { start: 0, end: 20 }));
+
+    let mut root = parser::parse("import \"broken.boe\"").unwrap();
+    let err = resolve(&mut root, "main.boe", &mut load).err().unwrap();
+    match err.cause() {
+        BuildError::Import { path, message } => {
+            assert_eq!(path, "broken.boe");
+            assert!(message.contains("at 1:11"), "{}", message);
+        }
+        x => panic!("Unexpected error: {:?}", x),
+    }
+}
@@ -1,20 +1,81 @@
-use std::collections::HashMap;
-
+#[cfg(feature = "compiler")]
+pub mod analysis;
+#[cfg(feature = "compiler")]
 mod ast;
+mod binary;
+#[cfg(feature = "compiler")]
+mod checker;
+mod color;
+#[cfg(feature = "compiler")]
 mod compiler;
+#[cfg(feature = "compiler")]
+mod diagnostics;
+mod easing;
 mod envelope;
+#[cfg(feature = "compiler")]
+mod format;
+mod glsl;
+#[cfg(feature = "compiler")]
+mod imports;
+#[cfg(feature = "compiler")]
 mod parser;
+mod program;
+mod random;
+#[cfg(feature = "compiler")]
+mod session;
+mod timeline;
+mod types;
 mod vector;
 
-pub use crate::compiler::EnvelopeFn;
+#[cfg(feature = "compiler")]
+pub use crate::ast::Span;
+pub use crate::binary::Package;
+#[cfg(feature = "compiler")]
+pub use crate::compiler::Module;
+#[cfg(feature = "compiler")]
+pub use crate::diagnostics::{line_col, Diagnostic};
+pub use crate::envelope::{Envelope, EnvelopeFn};
+#[cfg(feature = "compiler")]
+pub use crate::format::format;
+pub use crate::program::Program;
+#[cfg(feature = "compiler")]
+pub use crate::session::{Session, Value};
+pub use crate::timeline::{Marker, Scene, SceneTime, Timeline};
+pub use crate::types::Type;
 pub use crate::vector::Vector;
 
-pub fn build(source: &str) -> Result<HashMap<String, EnvelopeFn>, String> {
-    match parser::parse(source) {
-        Ok(ast) => {
-            // println!("AST: {:?}", ast);
-            compiler::build(ast).or_else(|err| Err(format!("Could not compile: {:?}", err)))
-        }
-        Err(error) => Err(format!("Could not parse: {:?}", error)),
-    }
+/// Compiles a script, returning the error as a readable message with its source location
+#[cfg(feature = "compiler")]
+pub fn build(source: &str) -> Result<Module, String> {
+    compile(source).map_err(|diagnostic| diagnostic.render(source))
+}
+
+#[cfg(feature = "compiler")]
+pub fn compile(source: &str) -> Result<Module, Diagnostic> {
+    compile_file("", source, |path| {
+        Err(format!("imports are not available, `{}`", path))
+    })
+}
+
+/// Compiles a script loaded from `path`. Imports are resolved relative to the path
+/// and loaded with `load`.
+#[cfg(feature = "compiler")]
+pub fn build_file<F>(path: &str, source: &str, load: F) -> Result<Module, String>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    compile_file(path, source, load).map_err(|diagnostic| diagnostic.render(source))
+}
+
+#[cfg(feature = "compiler")]
+pub fn compile_file<F>(path: &str, source: &str, mut load: F) -> Result<Module, Diagnostic>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let mut ast =
+        parser::parse(source).map_err(|err| Diagnostic::from_parse_error(&err, source))?;
+    let root = ast.clone();
+    imports::resolve(&mut ast, path, &mut load)
+        .and_then(|sources| compiler::build_with_imports(ast, sources))
+        .map_err(|err| Diagnostic::from_build_error(&err, &root))
 }
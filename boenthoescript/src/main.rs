@@ -1,34 +1,611 @@
-mod ast;
-mod compiler;
-mod envelope;
-mod parser;
-mod vector;
-
-// TODO:
-// - Stack based vectors
-// - Marker lists
-// - Arithmetics
-// - Randoms
+// Command line tool for checking, evaluating, plotting and formatting scripts
+// without running the engine
+
+use boenthoescript::{Module, Package, Session, Value, Vector};
+use std::{
+    env, fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    process,
+};
+
+const USAGE: &str = "Usage: boenthoescript <command> FILE [options]
+       boenthoescript repl [FILE] [options]
+
+Commands:
+  check     Validates the script and lists its exports
+  eval      Prints values of the exports over time as CSV
+  plot      Writes an SVG chart of the exports over time
+  fmt       Prints the script formatted
+  compile   Writes the script in the binary format, loadable without the parser
+  glsl      Writes the exports as GLSL functions `vec4 env_<name>(float t)`
+  repl      Evaluates definitions and expressions interactively, after loading FILE
+
+Options:
+  --from SECONDS    Start time (default 0)
+  --to SECONDS      End time (default: end of the longest export)
+  --step SECONDS    Time between samples (default 0.1, or 0.01 for plot, 0.5 for repl)
+  --export NAME     Export to include, can be repeated (default: all)
+  --json            Prints eval output as JSON
+  --output FILE     Output file of plot, compile or glsl (default: the script with .svg,
+                    .boeb or .glsl extension)
+  --write           Overwrites the script with the formatted one";
+
+const COMPONENTS: [&str; 4] = ["x", "y", "z", "w"];
+const COLORS: [&str; 8] = [
+    "#e6194b", "#3cb44b", "#4363d8", "#f58231", "#911eb4", "#42d4f4", "#f032e6", "#9a6324",
+];
+
+#[derive(Default)]
+struct Options {
+    command: String,
+    file: String,
+    from: f64,
+    to: Option<f64>,
+    step: Option<f64>,
+    exports: Vec<String>,
+    json: bool,
+    output: Option<String>,
+    write: bool,
+}
 
 fn main() {
-    let script = std::fs::read_to_string("example.bs").expect("Could not read example.bs");
-    println!("SCRIPT:\n\n{}\n\n", script);
+    let result =
+        parse_args(env::args().skip(1)).and_then(|options| match options.command.as_str() {
+            "check" => check(&options),
+            "eval" => eval(&options),
+            "plot" => plot(&options),
+            "fmt" => fmt(&options),
+            "compile" => compile(&options),
+            "glsl" => glsl(&options),
+            "repl" => repl(&options),
+            command => Err(format!("unknown command `{}`\n\n{}", command, USAGE)),
+        });
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn parse_args<I>(mut args: I) -> Result<Options, String>
+where
+    I: Iterator<Item = String>,
+{
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} requires a value", arg))
+        };
+        match arg.as_str() {
+            "--from" => options.from = number(&value()?)?,
+            "--to" => options.to = Some(number(&value()?)?),
+            "--step" => options.step = Some(number(&value()?)?),
+            "--export" => options.exports.push(value()?),
+            "--output" => options.output = Some(value()?),
+            "--json" => options.json = true,
+            "--write" => options.write = true,
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    match positional.as_slice() {
+        [command, file] => {
+            options.command = command.clone();
+            options.file = file.clone();
+        }
+        [command] if command == "repl" => options.command = command.clone(),
+        _ => return Err(USAGE.into()),
+    }
+    if matches!(options.step, Some(step) if step <= 0.0) {
+        return Err("--step must be positive".into());
+    }
+    Ok(options)
+}
+
+fn number(text: &str) -> Result<f64, String> {
+    text.parse()
+        .map_err(|_| format!("expected a number, found `{}`", text))
+}
+
+fn read(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))
+}
+
+/// Builds the script, loading its imports relative to it
+fn build(path: &str) -> Result<(String, Module), String> {
+    let source = read(path)?;
+    let module = boenthoescript::compile_file(path, &source, read)
+        .map_err(|diagnostic| format!("{}: {}", path, diagnostic.render(&source)))?;
+    Ok((source, module))
+}
+
+fn check(options: &Options) -> Result<(), String> {
+    let (source, module) = build(&options.file)?;
+    for warning in module.warnings.iter() {
+        eprintln!("{}: warning: {}", options.file, warning.render(&source));
+    }
+
+    let program = &module.program;
+    for (index, name) in program.outputs().enumerate() {
+        let signature = match module.signatures.get(name) {
+            Some(ty) => ty.to_string(),
+            None => "an unknown value".into(),
+        };
+        println!(
+            "out {}: {}, {} s",
+            name,
+            signature,
+            program.get_duration(index)
+        );
+    }
+    for marker in module.markers.iter() {
+        println!("marker {}: {} s", marker.name, marker.time);
+    }
+    for scene in module.timeline.scenes().iter() {
+        println!("scene {}: {} s to {} s", scene.name, scene.start, scene.end);
+    }
+    Ok(())
+}
+
+/// Selected export with its index in the program and the number of components
+struct Output {
+    name: String,
+    index: usize,
+    components: usize,
+}
+
+fn outputs(options: &Options, module: &Module) -> Result<Vec<Output>, String> {
+    let names: Vec<String> = match options.exports.is_empty() {
+        true => module.program.outputs().map(String::from).collect(),
+        false => options.exports.clone(),
+    };
+    names
+        .into_iter()
+        .map(|name| {
+            let index = module
+                .program
+                .index_of(&name)
+                .ok_or_else(|| format!("unknown export `{}`", name))?;
+            let components = module
+                .signatures
+                .get(&name)
+                .and_then(|ty| ty.dimension())
+                .unwrap_or(COMPONENTS.len())
+                .max(1)
+                .min(COMPONENTS.len());
+            Ok(Output {
+                name,
+                index,
+                components,
+            })
+        })
+        .collect()
+}
+
+/// Sample times between `--from` and `--to`, which defaults to the end of the longest
+/// finite export
+fn times(options: &Options, module: &Module, outputs: &[Output], step: f64) -> Vec<f64> {
+    let to = options.to.unwrap_or_else(|| {
+        let end = outputs
+            .iter()
+            .map(|output| module.program.get_duration(output.index))
+            .filter(|duration| duration.is_finite())
+            .fold(0.0, f64::max);
+        if end > options.from {
+            end
+        } else {
+            options.from + 10.0
+        }
+    });
+    let count = ((to - options.from) / step + 1e-9).floor().max(0.0) as usize;
+    (0..=count)
+        .map(|i| options.from + i as f64 * step)
+        .collect()
+}
+
+/// Values of the selected exports at each time
+fn sample(module: &Module, outputs: &[Output], times: &[f64]) -> Vec<Vec<Vector>> {
+    let mut state = vec![Vector::default(); module.program.len()];
+    times
+        .iter()
+        .map(|time| {
+            module.program.evaluate(*time, &mut state);
+            outputs
+                .iter()
+                .map(|output| state[output.index].clone())
+                .collect()
+        })
+        .collect()
+}
+
+fn column_names(outputs: &[Output]) -> Vec<String> {
+    let mut names = Vec::new();
+    for output in outputs.iter() {
+        match output.components {
+            1 => names.push(output.name.clone()),
+            n => names.extend(
+                COMPONENTS[..n]
+                    .iter()
+                    .map(|c| format!("{}.{}", output.name, c)),
+            ),
+        }
+    }
+    names
+}
+
+fn eval(options: &Options) -> Result<(), String> {
+    let (_, module) = build(&options.file)?;
+    let outputs = outputs(options, &module)?;
+    let times = times(options, &module, &outputs, options.step.unwrap_or(0.1));
+    let samples = sample(&module, &outputs, &times);
+
+    if options.json {
+        println!("[");
+        for (i, (time, values)) in times.iter().zip(samples.iter()).enumerate() {
+            let mut fields = vec![format!("\"time\": {}", json_number(*time))];
+            for (output, value) in outputs.iter().zip(values) {
+                let value = match output.components {
+                    1 => json_number(value.0[0]),
+                    n => {
+                        let items: Vec<String> =
+                            value.0[..n].iter().map(|x| json_number(*x)).collect();
+                        format!("[{}]", items.join(", "))
+                    }
+                };
+                fields.push(format!("\"{}\": {}", output.name, value));
+            }
+            let separator = if i + 1 < times.len() { "," } else { "" };
+            println!("  {{{}}}{}", fields.join(", "), separator);
+        }
+        println!("]");
+    } else {
+        println!("time,{}", column_names(&outputs).join(","));
+        for (time, values) in times.iter().zip(samples.iter()) {
+            let mut row = vec![time.to_string()];
+            for (output, value) in outputs.iter().zip(values) {
+                row.extend(value.0[..output.components].iter().map(|x| x.to_string()));
+            }
+            println!("{}", row.join(","));
+        }
+    }
+    Ok(())
+}
+
+fn json_number(value: f64) -> String {
+    match value.is_finite() {
+        true => value.to_string(),
+        false => "null".into(),
+    }
+}
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 400.0;
+const MARGIN: f64 = 50.0;
+
+fn plot(options: &Options) -> Result<(), String> {
+    let (_, module) = build(&options.file)?;
+    let outputs = outputs(options, &module)?;
+    let times = times(options, &module, &outputs, options.step.unwrap_or(0.01));
+    let samples = sample(&module, &outputs, &times);
+
+    // One series per component
+    let mut series: Vec<(String, Vec<(f64, f64)>)> = Vec::new();
+    let names = column_names(&outputs);
+    for (output_index, output) in outputs.iter().enumerate() {
+        for component in 0..output.components {
+            let points = times
+                .iter()
+                .zip(samples.iter())
+                .map(|(time, values)| (*time, values[output_index].0[component]))
+                .filter(|(_, value)| value.is_finite())
+                .collect();
+            series.push((names[series.len()].clone(), points));
+        }
+    }
 
-    let ast = match parser::parse(&script) {
-        Ok(expr) => expr,
-        Err(error) => panic!(format!("Could not parse: {:?}", error)),
+    let (t0, t1) = match (times.first(), times.last()) {
+        (Some(t0), Some(t1)) if t1 > t0 => (*t0, *t1),
+        (Some(t0), _) => (*t0, t0 + 1.0),
+        _ => (0.0, 1.0),
     };
-    println!("AST:\n\n{:?}\n\n", ast);
+    let values = series
+        .iter()
+        .flat_map(|(_, points)| points.iter().map(|p| p.1));
+    let (mut min, mut max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+        (min.min(v), max.max(v))
+    });
+    if min >= max {
+        let center = if min.is_finite() { min } else { 0.0 };
+        min = center - 1.0;
+        max = center + 1.0;
+    }
+    let x = |t: f64| MARGIN + (t - t0) / (t1 - t0) * (WIDTH - 2.0 * MARGIN);
+    let y = |v: f64| HEIGHT - MARGIN - (v - min) / (max - min) * (HEIGHT - 2.0 * MARGIN);
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\" font-size=\"11\">\n\
+         <rect width=\"{w}\" height=\"{h}\" fill=\"white\"/>\n",
+        w = WIDTH,
+        h = HEIGHT
+    );
+    let axis_step = tick_step(t1 - t0);
+    let mut tick = (t0 / axis_step).ceil() * axis_step;
+    while tick <= t1 + 1e-9 {
+        svg.push_str(&format!(
+            "<line x1=\"{x:.1}\" y1=\"{y0}\" x2=\"{x:.1}\" y2=\"{y1}\" stroke=\"#ddd\"/>\n\
+             <text x=\"{x:.1}\" y=\"{ty}\" text-anchor=\"middle\">{t}</text>\n",
+            x = x(tick),
+            y0 = MARGIN,
+            y1 = HEIGHT - MARGIN,
+            ty = HEIGHT - MARGIN + 15.0,
+            t = round(tick)
+        ));
+        tick += axis_step;
+    }
+    let value_step = tick_step(max - min);
+    let mut tick = (min / value_step).ceil() * value_step;
+    while tick <= max + 1e-9 {
+        svg.push_str(&format!(
+            "<line x1=\"{x0}\" y1=\"{y:.1}\" x2=\"{x1}\" y2=\"{y:.1}\" stroke=\"{color}\"/>\n\
+             <text x=\"{tx}\" y=\"{y:.1}\" text-anchor=\"end\" dominant-baseline=\"middle\">{v}</text>\n",
+            x0 = MARGIN,
+            x1 = WIDTH - MARGIN,
+            y = y(tick),
+            color = if tick.abs() < 1e-9 { "#999" } else { "#ddd" },
+            tx = MARGIN - 5.0,
+            v = round(tick)
+        ));
+        tick += value_step;
+    }
+
+    for (i, (name, points)) in series.iter().enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        let points: Vec<String> = points
+            .iter()
+            .map(|(t, v)| format!("{:.2},{:.2}", x(*t), y(*v)))
+            .collect();
+        svg.push_str(&format!(
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>\n\
+             <text x=\"{}\" y=\"{}\" fill=\"{}\">{}</text>\n",
+            color,
+            points.join(" "),
+            MARGIN + 5.0,
+            MARGIN + 12.0 + i as f64 * 14.0,
+            color,
+            name
+        ));
+    }
+    svg.push_str("</svg>\n");
 
-    let output = match compiler::build(ast) {
-        Ok(exports) => exports,
-        Err(err) => panic!(format!("Could not compile: {:?}", err)),
+    let output = output_path(options, "svg");
+    fs::write(&output, svg).map_err(|err| format!("{}: {}", output, err))?;
+    println!("Wrote {}", output);
+    Ok(())
+}
+
+/// `--output`, or the script with another extension
+fn output_path(options: &Options, extension: &str) -> String {
+    match &options.output {
+        Some(output) => output.clone(),
+        None => Path::new(&options.file)
+            .with_extension(extension)
+            .to_string_lossy()
+            .into(),
+    }
+}
+
+/// Distance between axis labels, so that there are at most ten of them
+fn tick_step(range: f64) -> f64 {
+    let magnitude = 10f64.powf((range / 10.0).log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|step| range / step <= 10.0)
+        .unwrap_or(magnitude * 10.0)
+}
+
+fn round(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+fn fmt(options: &Options) -> Result<(), String> {
+    let source = read(&options.file)?;
+    let formatted = boenthoescript::format(&source)
+        .map_err(|diagnostic| format!("{}: {}", options.file, diagnostic.render(&source)))?;
+    if options.write {
+        fs::write(&options.file, formatted).map_err(|err| format!("{}: {}", options.file, err))
+    } else {
+        print!("{}", formatted);
+        Ok(())
+    }
+}
+
+fn compile(options: &Options) -> Result<(), String> {
+    let (_, module) = build(&options.file)?;
+    let bytes = Package::from(module).to_bytes()?;
+    let output = output_path(options, "boeb");
+    fs::write(&output, &bytes).map_err(|err| format!("{}: {}", output, err))?;
+    println!("Wrote {} ({} bytes)", output, bytes.len());
+    Ok(())
+}
+
+fn glsl(options: &Options) -> Result<(), String> {
+    let (_, module) = build(&options.file)?;
+    let namespace = Path::new(&options.file)
+        .file_stem()
+        .map_or(String::from("script"), |stem| stem.to_string_lossy().into());
+    let glsl = module.program.to_glsl(&namespace)?;
+    let output = output_path(options, "glsl");
+    fs::write(&output, &glsl).map_err(|err| format!("{}: {}", output, err))?;
+    println!("Wrote {} ({} bytes)", output, glsl.len());
+    Ok(())
+}
+
+const REPL_HELP: &str = "Enter definitions, or expressions to evaluate them. Commands:
+  :t TIME EXPR        Evaluates EXPR at TIME, or at times FROM..TO
+  :load FILE          Evaluates the definitions of a script
+  :help               Prints this help
+  :quit               Exits";
+
+/// Default time between rows
+const REPL_STEP: f64 = 0.5;
+/// Rows printed for an envelope without `:t`
+const REPL_ROWS: usize = 20;
+
+fn repl(options: &Options) -> Result<(), String> {
+    let mut session = Session::new();
+    if !options.file.is_empty() {
+        load(&mut session, &options.file)?;
+    }
+    let step = options.step.unwrap_or(REPL_STEP);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut input = String::new();
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { "| " });
+        io::stdout().flush().map_err(|err| err.to_string())?;
+        let line = match lines.next() {
+            Some(line) => line.map_err(|err| err.to_string())?,
+            None => {
+                println!();
+                return Ok(());
+            }
+        };
+        // A command cancels an unfinished expression
+        if line.trim_start().starts_with(':') {
+            input.clear();
+        }
+        input.push_str(&line);
+        input.push('\n');
+        // Continue brackets on the next line
+        if open_brackets(&input) > 0 {
+            continue;
+        }
+
+        let line = std::mem::take(&mut input);
+        let line = line.trim();
+        let (command, rest) = match line.split_once(char::is_whitespace) {
+            Some((command, rest)) => (command, rest.trim()),
+            None => (line, ""),
+        };
+        let result = match command {
+            "" => Ok(()),
+            ":q" | ":quit" => return Ok(()),
+            ":help" => {
+                println!("{}", REPL_HELP);
+                Ok(())
+            }
+            ":load" => load(&mut session, rest),
+            ":t" => match rest.split_once(char::is_whitespace) {
+                Some((times, expr)) => repl_times(times, step)
+                    .and_then(|times| print_value(&mut session, expr, Some(&times), options)),
+                None => Err("usage: :t TIME EXPR".into()),
+            },
+            _ if command.starts_with(':') => Err(format!("unknown command `{}`", command)),
+            _ => print_value(&mut session, line, None, options),
+        };
+        if let Err(err) = result {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+/// Number of brackets left open, not counting the ones in comments and strings
+fn open_brackets(input: &str) -> isize {
+    let mut open = 0;
+    for line in input.lines() {
+        let mut chars = line.chars().peekable();
+        let mut in_string = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => in_string = !in_string,
+                _ if in_string => (),
+                '/' if chars.peek() == Some(&'/') => break,
+                '(' | '[' | '{' => open += 1,
+                ')' | ']' | '}' => open -= 1,
+                _ => (),
+            }
+        }
+    }
+    open
+}
+
+fn load(session: &mut Session, path: &str) -> Result<(), String> {
+    let source = read(path)?;
+    session
+        .eval_file(path, &source, read)
+        .map_err(|diagnostic| format!("{}: {}", path, diagnostic.render(&source)))?;
+    println!("Loaded {}", path);
+    Ok(())
+}
+
+/// Evaluates an input and prints its value at `times`. Envelopes are printed from `--from`
+/// by default.
+fn print_value(
+    session: &mut Session,
+    input: &str,
+    times: Option<&[f64]>,
+    options: &Options,
+) -> Result<(), String> {
+    let value = session
+        .eval(input)
+        .map_err(|diagnostic| diagnostic.render(input))?;
+    let dimension = value.dimension().min(COMPONENTS.len());
+    let default_times: Vec<f64>;
+    let times = match (&value, times) {
+        (Value::Nil, None) => return Ok(()),
+        (Value::Nil, Some(_)) => return Err("expected an expression".into()),
+        (Value::Numbers(n), None) => {
+            let items: Vec<String> = n.iter().map(|x| round(*x).to_string()).collect();
+            println!("[{}]", items.join(", "));
+            return Ok(());
+        }
+        (Value::Envelope { .. }, None) => {
+            let duration = value.get_duration();
+            match dimension {
+                1 => println!("an envelope, {} s", duration),
+                n => println!("an envelope of {} components, {} s", n, duration),
+            }
+            let step = options.step.unwrap_or(REPL_STEP);
+            default_times = (0..REPL_ROWS)
+                .map(|i| options.from + i as f64 * step)
+                .take_while(|time| *time <= duration)
+                .collect();
+            &default_times
+        }
+        (_, Some(times)) => times,
     };
-    for i in 0..50 {
-        let t = i as f64 / 10.0;
-        println!("At {}:", t);
-        for (key, envelope) in output.iter() {
-            println!("  {}: {:?}", key, envelope.get_value(t));
+
+    let header: Vec<String> = COMPONENTS[..dimension]
+        .iter()
+        .map(|c| format!("{:>10}", c))
+        .collect();
+    println!("{:>10}{}", "time", header.join(""));
+    for time in times.iter() {
+        let vector = value
+            .get_value(*time)
+            .ok_or("the value has more than four components")?;
+        let row: Vec<String> = vector.0[..dimension]
+            .iter()
+            .map(|x| format!("{:>10}", round(*x)))
+            .collect();
+        println!("{:>10}{}", round(*time), row.join(""));
+    }
+    Ok(())
+}
+
+/// A time, or times from `FROM..TO` with `step` between them
+fn repl_times(spec: &str, step: f64) -> Result<Vec<f64>, String> {
+    match spec.split_once("..") {
+        Some((from, to)) => {
+            let (from, to) = (number(from)?, number(to)?);
+            let count = ((to - from) / step + 1e-9).floor().max(0.0) as usize;
+            Ok((0..=count).map(|i| from + i as f64 * step).collect())
         }
+        None => Ok(vec![number(spec)?]),
     }
 }
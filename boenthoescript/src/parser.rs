@@ -4,63 +4,154 @@ peg::parser! {
   grammar bs_parser() for str {
 
     pub rule script() -> Expr
-        = __ e:(export() / expr()) ** __ __ { Expr::list(KW_ROOT, e) }
+        = __ p:position!() e:(import() / export() / markers() / scene() / expr()) ** __ q:position!() __ {
+            Expr::list(KW_ROOT, e).with_span(p, q)
+        }
+
+    // Only the leading tokens of a rule are quiet, so that errors inside an
+    // unfinished call or block are reported where they happen
 
     pub rule expr_list() -> Vec<Expr>
-        = quiet! { l:expr() ** __ { l } }
-        / expected!("expression list")
+        = l:expr() ** __ { l }
 
     pub rule expr() -> Expr
-        = quiet! {
-            line_comment()
-            / block()
-            / array()
-            / fn_call()
-            / define()
-            / number()
-            / symbol()
-        }
+        = line_comment()
+        / function()
+        / define()
+        / arithmetic()
         / expected!("expression")
 
+    rule arithmetic() -> Expr
+        = precedence! {
+            a:(@) _ "+" __ b:@ { Expr::binary(OP_ADD, a, b) }
+            a:(@) _ "-" __ b:@ { Expr::binary(OP_SUB, a, b) }
+            --
+            a:(@) _ "*" __ b:@ { Expr::binary(OP_MUL, a, b) }
+            a:(@) _ "/" __ b:@ { Expr::binary(OP_DIV, a, b) }
+            --
+            a:atom() { a }
+        }
+
+    rule atom() -> Expr
+        = block()
+        / array()
+        / fn_call()
+        / number()
+        / color()
+        / symbol()
+        / "(" __ e:arithmetic() __ ")" { e }
+        / p:position!() "-" _ a:atom() {
+            let q = a.span.end;
+            Expr::list(OP_NEG, vec![a]).with_span(p, q)
+        }
+
     pub rule number() -> Expr
-        = quiet!{ n:$(['+'|'-']?['0'..='9']+(['.']['0'..='9']+)?) {
+        = quiet!{ p:position!() n:$(['+'|'-']?['0'..='9']+(['.']['0'..='9']+)?) u:unit()? q:position!() {
             let f: f64 = n.parse().unwrap();
-            f.into()
+            let expr: Expr = match u {
+                Some("ms") => (f / 1000.0).into(),
+                Some("bars") | Some("bar") => Expr::list(KW_BARS, vec![Expr::from(f).with_span(p, q)]),
+                Some("beats") | Some("beat") | Some("b") => Expr::list(KW_BEATS, vec![Expr::from(f).with_span(p, q)]),
+                _ => f.into(),
+            };
+            expr.with_span(p, q)
         } }
         / expected!("number")
 
+    rule unit() -> &'input str
+        = u:$("ms" / "s" / "bars" / "bar" / "beats" / "beat" / "b") !['a'..='z'|'A'..='Z'|'0'..='9'|'.'|'_'] { u }
+
+    /// Hex colour, e.g. `#ff8800` or `#ff880080` with alpha, as sRGB components in range 0..1
+    pub rule color() -> Expr
+        = quiet!{ p:position!() "#" h:$(hex_digit()*<8> / hex_digit()*<6>) !hex_digit() q:position!() {
+            let components = (0..h.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&h[i..i + 2], 16).unwrap() as f64 / 255.0)
+                .collect();
+            Expr::from(ExprKind::NumberList(components)).with_span(p, q)
+        } }
+        / expected!("colour")
+
+    rule hex_digit()
+        = ['0'..='9'|'a'..='f'|'A'..='F']
+
     rule symbol() -> Expr
-        = s:symbol_str() { Expr::Symbol(s) }
+        = p:position!() s:symbol_str() q:position!() { Expr::from(ExprKind::Symbol(s)).with_span(p, q) }
 
     rule symbol_str() -> String
         = quiet!{ s:$(['a'..='z'|'A'..='Z']['a'..='z'|'A'..='Z'|'0'..='9'|'.'|'_']*) { String::from(s) } }
         / expected!("symbol")
 
     rule fn_call() -> Expr
-        = quiet! { n:symbol_str() _ "(" __ c:expr() ** ("," __) __ ")" { Expr::list(&n, c) } }
-        / expected!("function call")
+        = p:position!() n:quiet! { n:symbol_str() _ "(" { n } } __ c:expr() ** ("," __) __ ")" q:position!() {
+            Expr::list(&n, c).with_span(p, q)
+        }
 
     rule define() -> Expr
-        = quiet! { s:symbol() __ "=" __ v:expr() { Expr::list(KW_DEFINE, vec![s, v]) } }
-        / expected!("assignment")
+        = p:position!() s:quiet! { s:symbol() __ "=" { s } } __ v:expr() q:position!() {
+            Expr::list(KW_DEFINE, vec![s, v]).with_span(p, q)
+        }
+
+    rule function() -> Expr
+        = p:position!() h:quiet! { n:symbol() _ "(" __ a:symbol() ** ("," __) __ ")" __ "=" { (n, a) } }
+            __ v:expr() q:position!() {
+            let (n, a) = h;
+            Expr::list(KW_FUNCTION, vec![n, Expr::list(KW_PARAMS, a), v]).with_span(p, q)
+        }
 
     rule block() -> Expr
-        = quiet! { "{" __ l:expr_list() __ "}" { Expr::list(KW_BLOCK, l) } }
-        / expected!("block")
+        = p:position!() quiet! { "{" } __ l:expr_list() __ "}" q:position!() {
+            Expr::list(KW_BLOCK, l).with_span(p, q)
+        }
 
     rule array() -> Expr
-        = quiet! { "[" __ l:expr() ** ("," __) __ "]" { Expr::list(KW_ARRAY, l) } }
-        / expected!("array")
+        = p:position!() quiet! { "[" } __ l:expr() ** ("," __) __ "]" q:position!() {
+            Expr::list(KW_ARRAY, l).with_span(p, q)
+        }
 
     rule export() -> Expr
-        = quiet! { "out" _ s:symbol() __ "=" __ v:expr() { Expr::list(KW_EXPORT, vec![s, v] )} }
-        / expected!("export expression")
+        = p:position!() s:quiet! { "out" _ s:symbol() __ "=" { s } } __ v:expr() q:position!() {
+            Expr::list(KW_EXPORT, vec![s, v]).with_span(p, q)
+        }
+
+    rule markers() -> Expr
+        = p:position!() s:quiet! { "marker" [' '|'\t']+ s:symbol() __ "=" { s } } __ v:expr() q:position!() {
+            Expr::list(KW_MARKER, vec![s, v]).with_span(p, q)
+        }
+        / p:position!() quiet! { "markers" _ "{" } __ m:marker_entry() ** (__ ("," __)?) __ ","? __ "}" q:position!() {
+            Expr::list(KW_MARKER, m.into_iter().flatten().collect()).with_span(p, q)
+        }
+
+    // `import "path"` or `import "path" as name`. The path is stored as a symbol.
+    rule import() -> Expr
+        = p:position!() quiet! { "import" [' '|'\t']+ } path:path() alias:(_ "as" [' '|'\t']+ s:symbol() { s })? q:position!() {
+            let mut cons = vec![path];
+            cons.extend(alias);
+            Expr::list(KW_IMPORT, cons).with_span(p, q)
+        }
+
+    // `scene "name" from start to end`. The name is stored as a symbol.
+    rule scene() -> Expr
+        = p:position!() quiet! { "scene" [' '|'\t']+ } name:path() _ "from" __ start:arithmetic() _ "to" __ end:arithmetic() q:position!() {
+            Expr::list(KW_SCENE, vec![name, start, end]).with_span(p, q)
+        }
+
+    rule path() -> Expr
+        = p:position!() "\"" s:$([x if x != '"' && x != '\n']+) "\"" q:position!() {
+            Expr::from(ExprKind::Symbol(String::from(s))).with_span(p, q)
+        }
+        / expected!("path")
+
+    rule marker_entry() -> Vec<Expr>
+        = s:symbol() _ ":" __ v:expr() { vec![s, v] }
 
     rule _() = quiet!{ [' '|'\t']* }
     rule __() = quiet!{ [' '|'\t'|'\n']* }
 
     rule line_comment() -> Expr
-        = quiet! { "//" c:$([x if x != '\n']*) "\n" { Expr::Comment(String::from(c)) } }
+        = quiet! { p:position!() "//" c:$([x if x != '\n']*) q:position!() "\n" {
+            Expr::from(ExprKind::Comment(String::from(c))).with_span(p, q)
+        } }
         / expected!("comment")
   }
 }
@@ -75,6 +166,25 @@ fn number_parsing() {
     assert_eq!(bs_parser::expr("123.2"), Ok(123.2.into()));
 }
 
+#[test]
+fn unit_parsing() {
+    assert_eq!(bs_parser::expr("500ms"), Ok(0.5.into()));
+    assert_eq!(bs_parser::expr("3s"), Ok(3.0.into()));
+    assert_eq!(
+        bs_parser::expr("2b"),
+        Ok(Expr::list(KW_BEATS, vec![2.0.into()]))
+    );
+    assert_eq!(
+        bs_parser::expr("0.5beats"),
+        Ok(Expr::list(KW_BEATS, vec![0.5.into()]))
+    );
+    assert_eq!(
+        bs_parser::expr("1bar"),
+        Ok(Expr::list(KW_BARS, vec![1.0.into()]))
+    );
+    assert!(bs_parser::expr("2bx").is_err());
+}
+
 #[test]
 fn symbol_parsing() {
     assert_eq!(bs_parser::expr("foobar.zap1"), Ok("foobar.zap1".into()));
@@ -108,6 +218,50 @@ fn fn_call_parsing() {
 }
 
 #[test]
+fn arithmetic_parsing() {
+    assert_eq!(
+        bs_parser::expr("1 + 2 * 3"),
+        Ok(Expr::list(
+            OP_ADD,
+            vec![1.0.into(), Expr::list(OP_MUL, vec![2.0.into(), 3.0.into()])]
+        ))
+    );
+
+    assert_eq!(
+        bs_parser::expr("(1 + 2) * 3"),
+        Ok(Expr::list(
+            OP_MUL,
+            vec![Expr::list(OP_ADD, vec![1.0.into(), 2.0.into()]), 3.0.into()]
+        ))
+    );
+
+    assert_eq!(
+        bs_parser::expr("a - b - c"),
+        Ok(Expr::list(
+            OP_SUB,
+            vec![Expr::list(OP_SUB, vec!["a".into(), "b".into()]), "c".into()]
+        ))
+    );
+
+    assert_eq!(
+        bs_parser::expr("-x / -2"),
+        Ok(Expr::list(
+            OP_DIV,
+            vec![Expr::list(OP_NEG, vec!["x".into()]), (-2.0).into()]
+        ))
+    );
+
+    assert_eq!(
+        bs_parser::expr("base + sin(phase)"),
+        Ok(Expr::list(
+            OP_ADD,
+            vec!["base".into(), Expr::list("sin", vec!["phase".into()])]
+        ))
+    );
+}
+
+#[test]
+#[allow(clippy::approx_constant)]
 fn define_parsing() {
     assert_eq!(
         bs_parser::expr("pi = 3.14"),
@@ -118,7 +272,153 @@ fn define_parsing() {
         bs_parser::expr("off = hold(0)"),
         Ok(Expr::list(
             KW_DEFINE,
-            vec!["off".into(), Expr::List("hold".into(), vec![0.0.into()])]
+            vec!["off".into(), Expr::list("hold", vec![0.0.into()])]
+        ))
+    );
+}
+
+#[test]
+fn color_parsing() {
+    assert_eq!(
+        bs_parser::expr("#ff0033"),
+        Ok(ExprKind::NumberList(vec![1.0, 0.0, 0.2]).into())
+    );
+    assert_eq!(
+        bs_parser::expr("#FF003380"),
+        Ok(ExprKind::NumberList(vec![1.0, 0.0, 0.2, 128.0 / 255.0]).into())
+    );
+    assert!(bs_parser::expr("#ff003").is_err());
+    assert!(bs_parser::expr("#ff00331").is_err());
+}
+
+#[test]
+fn function_parsing() {
+    assert_eq!(
+        bs_parser::expr("fade(a, b, d) = linear(a, b, d)"),
+        Ok(Expr::list(
+            KW_FUNCTION,
+            vec![
+                "fade".into(),
+                Expr::list(KW_PARAMS, vec!["a".into(), "b".into(), "d".into()]),
+                Expr::list("linear", vec!["a".into(), "b".into(), "d".into()])
+            ]
+        ))
+    );
+
+    assert_eq!(
+        bs_parser::expr("zero() = 0"),
+        Ok(Expr::list(
+            KW_FUNCTION,
+            vec!["zero".into(), Expr::list(KW_PARAMS, vec![]), 0.0.into()]
+        ))
+    );
+}
+
+#[test]
+fn marker_parsing() {
+    assert_eq!(
+        bs_parser::script("marker drop = 32.5\nmarkerx = 1"),
+        Ok(Expr::list(
+            KW_ROOT,
+            vec![
+                Expr::list(KW_MARKER, vec!["drop".into(), 32.5.into()]),
+                Expr::list(KW_DEFINE, vec!["markerx".into(), 1.0.into()])
+            ]
+        ))
+    );
+
+    assert_eq!(
+        bs_parser::script("markers {\n  intro: 0,\n  drop: 32.5\n  outro: 120,\n}"),
+        Ok(Expr::list(
+            KW_ROOT,
+            vec![Expr::list(
+                KW_MARKER,
+                vec![
+                    "intro".into(),
+                    0.0.into(),
+                    "drop".into(),
+                    32.5.into(),
+                    "outro".into(),
+                    120.0.into()
+                ]
+            )]
+        ))
+    );
+}
+
+#[test]
+fn span_parsing() {
+    let source = "out a = concat(\n  hold(x, 1),\n  -foo + 2b\n)";
+    let root = parse(source).unwrap();
+    let export = match &root.kind {
+        ExprKind::List(_, cons) => cons[0].clone(),
+        _ => panic!("Not a list"),
+    };
+    assert_eq!(export.span, Span { start: 0, end: 43 });
+
+    let concat = match export.kind {
+        ExprKind::List(_, cons) => cons[1].clone(),
+        _ => panic!("Not a list"),
+    };
+    let text = |expr: &Expr| &source[expr.span.start..expr.span.end];
+    assert_eq!(text(&concat), "concat(\n  hold(x, 1),\n  -foo + 2b\n)");
+
+    let cons = match concat.kind {
+        ExprKind::List(_, cons) => cons,
+        _ => panic!("Not a list"),
+    };
+    assert_eq!(text(&cons[0]), "hold(x, 1)");
+    assert_eq!(text(&cons[1]), "-foo + 2b");
+    match &cons[0].kind {
+        ExprKind::List(_, args) => assert_eq!(text(&args[0]), "x"),
+        _ => panic!("Not a list"),
+    }
+}
+
+#[test]
+fn scene_parsing() {
+    assert_eq!(
+        parse("scene \"tunnel\" from 12 to drop + 4b\nscenes = 1"),
+        Ok(Expr::list(
+            KW_ROOT,
+            vec![
+                Expr::list(
+                    KW_SCENE,
+                    vec![
+                        "tunnel".into(),
+                        12.0.into(),
+                        Expr::binary(
+                            OP_ADD,
+                            "drop".into(),
+                            Expr::list(KW_BEATS, vec![4.0.into()])
+                        )
+                    ]
+                ),
+                Expr::list(KW_DEFINE, vec!["scenes".into(), 1.0.into()]),
+            ]
+        ))
+    );
+    assert!(parse("scene \"tunnel\" from 12").is_err());
+}
+
+#[test]
+fn import_parsing() {
+    assert_eq!(
+        parse("import \"common/easings.boe\"\nimport \"palette.boe\" as pal\nout a = pal.red"),
+        Ok(Expr::list(
+            KW_ROOT,
+            vec![
+                Expr::list(KW_IMPORT, vec!["common/easings.boe".into()]),
+                Expr::list(KW_IMPORT, vec!["palette.boe".into(), "pal".into()]),
+                Expr::list(KW_EXPORT, vec!["a".into(), "pal.red".into()]),
+            ]
+        ))
+    );
+    assert_eq!(
+        parse("imports = 1"),
+        Ok(Expr::list(
+            KW_ROOT,
+            vec![Expr::list(KW_DEFINE, vec!["imports".into(), 1.0.into()])]
         ))
     );
 }
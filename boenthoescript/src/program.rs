@@ -0,0 +1,927 @@
+// Envelope trees lowered into flat tables. Evaluation walks the node table by
+// index, without virtual calls or heap allocations.
+
+use crate::{
+    color::ColorSpace,
+    easing::Easing,
+    envelope::{
+        color_fade, decay, decay_velocity, event_value, event_velocity, fade_between,
+        fade_velocity, last_event, pingpong, select_index, Envelope, EventOutput, MathFn,
+        SplineKey,
+    },
+    random,
+    vector::Vector,
+};
+use std::{collections::HashMap, rc::Rc};
+
+type Duration = f64;
+type NodeIndex = u32;
+
+/// Math nodes with up to this many operands are evaluated without allocations
+pub const MAX_ARGS: usize = 4;
+
+#[derive(Clone)]
+pub enum Node {
+    Hold {
+        value: Vector,
+    },
+    Linear {
+        duration: Duration,
+        a: Vector,
+        b: Vector,
+    },
+    Ease {
+        duration: Duration,
+        a: Vector,
+        b: Vector,
+        easing: Easing,
+    },
+    /// End colours in sRGB and end points in the colour space
+    ColorFade {
+        duration: Duration,
+        from: Vector,
+        to: Vector,
+        a: Vector,
+        b: Vector,
+        space: ColorSpace,
+    },
+    /// Range of `Program::keys`
+    Spline {
+        first: u32,
+        count: u32,
+    },
+    Random {
+        seed: u64,
+        min: Vector,
+        range: Vector,
+        interval: Duration,
+    },
+    Noise {
+        seed: u64,
+        frequency: f64,
+        amplitude: Vector,
+        octaves: u32,
+    },
+    /// Range of `Program::segments`
+    Concat {
+        first: u32,
+        count: u32,
+    },
+    /// Range of `Program::operands`
+    Apply {
+        first: u32,
+        count: u32,
+        func: MathFn,
+    },
+    Delay {
+        offset: Duration,
+        node: NodeIndex,
+    },
+    Repeat {
+        duration: Duration,
+        period: Duration,
+        node: NodeIndex,
+    },
+    Loop {
+        period: Duration,
+        node: NodeIndex,
+    },
+    Every {
+        interval: Duration,
+        node: NodeIndex,
+    },
+    Speed {
+        factor: f64,
+        node: NodeIndex,
+    },
+    Reverse {
+        duration: Duration,
+        node: NodeIndex,
+    },
+    PingPong {
+        period: Duration,
+        node: NodeIndex,
+    },
+    Clip {
+        start: Duration,
+        length: Duration,
+        node: NodeIndex,
+    },
+    SampleAt {
+        time: Duration,
+        node: NodeIndex,
+    },
+    Crossfade {
+        start: Duration,
+        fade: Duration,
+        a: NodeIndex,
+        b: NodeIndex,
+    },
+    /// Range of `Program::choices`
+    Select {
+        index: NodeIndex,
+        first: u32,
+        count: u32,
+    },
+    Decay {
+        half_life: Duration,
+    },
+    /// Range of `Program::events`
+    Events {
+        first: u32,
+        count: u32,
+        period: Duration,
+        output: EventOutput<NodeIndex>,
+    },
+}
+
+/// Part of a concatenation, ending at `end` seconds from the start of the concatenation
+#[derive(Clone)]
+pub struct Segment {
+    pub end: Duration,
+    pub node: NodeIndex,
+}
+
+/// All exports of a module as one flat program
+#[derive(Clone, Default)]
+pub struct Program {
+    pub(crate) nodes: Vec<Node>,
+    pub(crate) durations: Vec<Duration>,
+    pub(crate) keys: Vec<SplineKey>,
+    pub(crate) segments: Vec<Segment>,
+    pub(crate) choices: Vec<NodeIndex>,
+    /// Operands of math nodes
+    pub(crate) operands: Vec<NodeIndex>,
+    /// Times and weights of events
+    pub(crate) events: Vec<(Duration, f64)>,
+    /// Export names and their root nodes, sorted by name
+    pub(crate) outputs: Vec<(String, NodeIndex)>,
+}
+
+impl Program {
+    pub fn new(exports: &HashMap<String, Box<dyn Envelope>>) -> Self {
+        let mut program = Self::default();
+        let mut names: Vec<&String> = exports.keys().collect();
+        names.sort();
+        for name in names {
+            let root = exports[name].lower(&mut program);
+            program.outputs.push((name.clone(), root));
+        }
+        program
+    }
+
+    /// Adds a node, returning its index. Children must be added before their parents.
+    pub fn push(&mut self, node: Node, duration: Duration) -> NodeIndex {
+        self.nodes.push(node);
+        self.durations.push(duration);
+        (self.nodes.len() - 1) as NodeIndex
+    }
+
+    pub fn push_spline(&mut self, keys: &[SplineKey], duration: Duration) -> NodeIndex {
+        let first = self.keys.len() as u32;
+        self.keys.extend_from_slice(keys);
+        self.push(
+            Node::Spline {
+                first,
+                count: keys.len() as u32,
+            },
+            duration,
+        )
+    }
+
+    pub fn push_concat(&mut self, nodes: &[NodeIndex]) -> NodeIndex {
+        let first = self.segments.len() as u32;
+        let mut end = 0.0;
+        for node in nodes.iter() {
+            end += self.durations[*node as usize];
+            self.segments.push(Segment { end, node: *node });
+        }
+        self.push(
+            Node::Concat {
+                first,
+                count: nodes.len() as u32,
+            },
+            end,
+        )
+    }
+
+    pub fn push_select(
+        &mut self,
+        index: NodeIndex,
+        nodes: &[NodeIndex],
+        duration: Duration,
+    ) -> NodeIndex {
+        let first = self.choices.len() as u32;
+        self.choices.extend_from_slice(nodes);
+        self.push(
+            Node::Select {
+                index,
+                first,
+                count: nodes.len() as u32,
+            },
+            duration,
+        )
+    }
+
+    pub fn push_apply(
+        &mut self,
+        args: &[NodeIndex],
+        func: MathFn,
+        duration: Duration,
+    ) -> NodeIndex {
+        let first = self.operands.len() as u32;
+        self.operands.extend_from_slice(args);
+        self.push(
+            Node::Apply {
+                first,
+                count: args.len() as u32,
+                func,
+            },
+            duration,
+        )
+    }
+
+    pub fn push_events(
+        &mut self,
+        events: &[(Duration, f64)],
+        period: Duration,
+        output: EventOutput<NodeIndex>,
+        duration: Duration,
+    ) -> NodeIndex {
+        let first = self.events.len() as u32;
+        self.events.extend_from_slice(events);
+        self.push(
+            Node::Events {
+                first,
+                count: events.len() as u32,
+                period,
+                output,
+            },
+            duration,
+        )
+    }
+
+    pub fn duration_of(&self, node: NodeIndex) -> Duration {
+        self.durations[node as usize]
+    }
+
+    /// Number of exported values
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    /// Names of the exports, sorted. The position of a name is its output index.
+    pub fn outputs(&self) -> impl Iterator<Item = &str> {
+        self.outputs.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.outputs
+            .binary_search_by(|(output, _)| output.as_str().cmp(name))
+            .ok()
+    }
+
+    pub fn get_duration(&self, output: usize) -> Duration {
+        self.duration_of(self.outputs[output].1)
+    }
+
+    pub fn get_value(&self, output: usize, time: Duration) -> Vector {
+        self.eval(self.outputs[output].1, time)
+    }
+
+    /// Derivative of an export with respect to time
+    pub fn get_velocity(&self, output: usize, time: Duration) -> Vector {
+        self.velocity(self.outputs[output].1, time)
+    }
+
+    /// Evaluates all exports, in output index order
+    pub fn evaluate(&self, time: Duration, values: &mut [Vector]) {
+        for (value, (_, root)) in values.iter_mut().zip(self.outputs.iter()) {
+            *value = self.eval(*root, time);
+        }
+    }
+
+    fn eval(&self, node: NodeIndex, time: Duration) -> Vector {
+        match &self.nodes[node as usize] {
+            Node::Hold { value } => value.clone(),
+            Node::Linear { duration, a, b } => {
+                let t = (time / duration).min(1.0);
+                a + &b.scalar(t)
+            }
+            Node::Ease {
+                duration,
+                a,
+                b,
+                easing,
+            } if *duration > 0.0 => a + &b.scalar(easing.apply(time / duration)),
+            Node::Ease { a, b, .. } => a + b,
+            Node::ColorFade {
+                duration,
+                from,
+                to,
+                a,
+                b,
+                space,
+            } => color_fade(*space, *duration, (from, to), (a, b), time).0,
+            Node::Spline { first, count } => {
+                let keys = &self.keys[*first as usize..(first + count) as usize];
+                spline(keys, time)
+            }
+            Node::Random {
+                seed,
+                min,
+                range,
+                interval,
+            } => {
+                let index = (time / interval).floor() as i64;
+                let mut value = min.clone();
+                for (i, v) in value.0.iter_mut().enumerate() {
+                    *v += range.0[i] * random::unit(*seed, i as u64, index);
+                }
+                value
+            }
+            Node::Noise {
+                seed,
+                frequency,
+                amplitude,
+                octaves,
+            } => {
+                let mut value = amplitude.clone();
+                for (i, v) in value.0.iter_mut().enumerate() {
+                    *v *= random::fbm(*seed, i as u64, time * frequency, *octaves);
+                }
+                value
+            }
+            Node::Concat { first, count } => {
+                let segments = &self.segments[*first as usize..(first + count) as usize];
+                let index = segments
+                    .partition_point(|s| s.end <= time)
+                    .min(segments.len().saturating_sub(1));
+                match segments.get(index) {
+                    Some(segment) => {
+                        let duration = self.duration_of(segment.node);
+                        self.eval(segment.node, time - segment.end + duration)
+                    }
+                    None => 0.0.into(), // empty list
+                }
+            }
+            Node::Apply { first, count, func } => {
+                let args = &self.operands[*first as usize..(first + count) as usize];
+                if args.len() > MAX_ARGS {
+                    let values: Vec<Vector> =
+                        args.iter().map(|arg| self.eval(*arg, time)).collect();
+                    return Vector::apply(&values, func.value);
+                }
+                let count = args.len();
+                let mut values: [Vector; MAX_ARGS] = Default::default();
+                for (value, arg) in values.iter_mut().zip(args.iter()) {
+                    *value = self.eval(*arg, time);
+                }
+                let mut result = Vector::default();
+                let mut operands = [0.0; MAX_ARGS];
+                for (i, x) in result.0.iter_mut().enumerate() {
+                    for (operand, value) in operands.iter_mut().zip(values[..count].iter()) {
+                        *operand = value.0[i];
+                    }
+                    *x = (func.value)(&operands[..count]);
+                }
+                result
+            }
+            Node::Delay { offset, node } => self.eval(*node, (time - offset).max(0.0)),
+            Node::Repeat {
+                duration,
+                period,
+                node,
+            } => self.eval(
+                *node,
+                if time > *duration {
+                    time
+                } else {
+                    time % period
+                },
+            ),
+            Node::Loop { period, node } => self.eval(*node, time % period),
+            Node::Every { interval, node } => self.eval(*node, time.rem_euclid(*interval)),
+            Node::Speed { factor, node } => self.eval(*node, time * factor),
+            Node::Reverse { duration, node } => {
+                self.eval(*node, duration - time.max(0.0).min(*duration))
+            }
+            Node::PingPong { period, node } => self.eval(*node, pingpong(time, *period)),
+            Node::Clip {
+                start,
+                length,
+                node,
+            } => self.eval(*node, start + time.max(0.0).min(*length)),
+            Node::SampleAt { time, node } => self.eval(*node, *time),
+            Node::Crossfade { start, fade, a, b } => fade_between(
+                time - start,
+                *fade,
+                || self.eval(*a, time),
+                || self.eval(*b, time - start),
+            ),
+            Node::Select {
+                index,
+                first,
+                count,
+            } => {
+                let choices = &self.choices[*first as usize..(first + count) as usize];
+                let index = select_index(self.eval(*index, time).to_f(), choices.len());
+                match choices.get(index) {
+                    Some(node) => self.eval(*node, time),
+                    None => 0.0.into(), // empty list
+                }
+            }
+            Node::Decay { half_life } => decay(*half_life, time).into(),
+            Node::Events {
+                first,
+                count,
+                period,
+                output,
+            } => {
+                let events = &self.events[*first as usize..(first + count) as usize];
+                let event = last_event(events, *period, time);
+                event_value(output, event, time, |node, t| self.eval(*node, t))
+            }
+        }
+    }
+
+    /// Derivative of `eval`, following the same structure
+    fn velocity(&self, node: NodeIndex, time: Duration) -> Vector {
+        match &self.nodes[node as usize] {
+            Node::Hold { .. } | Node::Random { .. } | Node::SampleAt { .. } => 0.0.into(),
+            Node::Linear { duration, b, .. } => {
+                if time < *duration {
+                    b.scalar(1.0 / duration)
+                } else {
+                    0.0.into()
+                }
+            }
+            Node::Ease {
+                duration,
+                b,
+                easing,
+                ..
+            } if *duration > 0.0 => b.scalar(easing.derivative(time / duration) / duration),
+            Node::Ease { .. } => 0.0.into(),
+            Node::ColorFade {
+                duration,
+                from,
+                to,
+                a,
+                b,
+                space,
+            } => color_fade(*space, *duration, (from, to), (a, b), time).1,
+            Node::Spline { first, count } => {
+                let keys = &self.keys[*first as usize..(first + count) as usize];
+                spline_velocity(keys, time)
+            }
+            Node::Noise {
+                seed,
+                frequency,
+                amplitude,
+                octaves,
+            } => {
+                let mut velocity = amplitude.scalar(*frequency);
+                for (i, v) in velocity.0.iter_mut().enumerate() {
+                    *v *= random::fbm_derivative(*seed, i as u64, time * frequency, *octaves);
+                }
+                velocity
+            }
+            Node::Concat { first, count } => {
+                let segments = &self.segments[*first as usize..(first + count) as usize];
+                let index = segments
+                    .partition_point(|s| s.end <= time)
+                    .min(segments.len().saturating_sub(1));
+                match segments.get(index) {
+                    Some(segment) => {
+                        let duration = self.duration_of(segment.node);
+                        self.velocity(segment.node, time - segment.end + duration)
+                    }
+                    None => 0.0.into(),
+                }
+            }
+            Node::Apply { first, count, func } => {
+                let args = &self.operands[*first as usize..(first + count) as usize];
+                let values: Vec<Vector> = args.iter().map(|arg| self.eval(*arg, time)).collect();
+                let velocities: Vec<Vector> =
+                    args.iter().map(|arg| self.velocity(*arg, time)).collect();
+                func.apply_derivative(&values, &velocities)
+            }
+            Node::Delay { offset, node } => {
+                if time > *offset {
+                    self.velocity(*node, time - offset)
+                } else {
+                    0.0.into()
+                }
+            }
+            Node::Repeat {
+                duration,
+                period,
+                node,
+            } => self.velocity(
+                *node,
+                if time > *duration {
+                    time
+                } else {
+                    time % period
+                },
+            ),
+            Node::Loop { period, node } => self.velocity(*node, time % period),
+            Node::Every { interval, node } => self.velocity(*node, time.rem_euclid(*interval)),
+            Node::Speed { factor, node } => self.velocity(*node, time * factor).scalar(*factor),
+            Node::Reverse { duration, node } => {
+                if (0.0..=*duration).contains(&time) {
+                    self.velocity(*node, duration - time).scalar(-1.0)
+                } else {
+                    0.0.into()
+                }
+            }
+            Node::PingPong { period, node } => {
+                let velocity = self.velocity(*node, pingpong(time, *period));
+                if time.rem_euclid(2.0 * period) > *period {
+                    velocity.scalar(-1.0)
+                } else {
+                    velocity
+                }
+            }
+            Node::Clip {
+                start,
+                length,
+                node,
+            } => {
+                if (0.0..=*length).contains(&time) {
+                    self.velocity(*node, start + time)
+                } else {
+                    0.0.into()
+                }
+            }
+            Node::Crossfade { start, fade, a, b } => fade_velocity(
+                time - start,
+                *fade,
+                || (self.eval(*a, time), self.velocity(*a, time)),
+                || (self.eval(*b, time - start), self.velocity(*b, time - start)),
+            ),
+            Node::Select {
+                index,
+                first,
+                count,
+            } => {
+                let choices = &self.choices[*first as usize..(first + count) as usize];
+                let index = select_index(self.eval(*index, time).to_f(), choices.len());
+                match choices.get(index) {
+                    Some(node) => self.velocity(*node, time),
+                    None => 0.0.into(),
+                }
+            }
+            Node::Decay { half_life } => decay_velocity(*half_life, time).into(),
+            Node::Events {
+                first,
+                count,
+                period,
+                output,
+            } => {
+                let events = &self.events[*first as usize..(first + count) as usize];
+                let event = last_event(events, *period, time);
+                event_velocity(output, event, time, |node, t| self.velocity(*node, t))
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Program")
+            .field("nodes", &self.nodes.len())
+            .field("outputs", &self.outputs().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Node {
+    /// Replaces the child nodes that are stored in the node itself. Children in the
+    /// tables of the program (segments, choices, operands) are not visited.
+    pub fn map_children<F>(mut self, mut func: F) -> Self
+    where
+        F: FnMut(NodeIndex) -> NodeIndex,
+    {
+        match &mut self {
+            Node::Delay { node, .. }
+            | Node::Repeat { node, .. }
+            | Node::Loop { node, .. }
+            | Node::Every { node, .. }
+            | Node::Speed { node, .. }
+            | Node::Reverse { node, .. }
+            | Node::PingPong { node, .. }
+            | Node::Clip { node, .. }
+            | Node::SampleAt { node, .. } => *node = func(*node),
+            Node::Crossfade { a, b, .. } => {
+                *a = func(*a);
+                *b = func(*b);
+            }
+            Node::Select { index, .. } => *index = func(*index),
+            Node::Events {
+                output: EventOutput::Shape(node),
+                ..
+            } => *node = func(*node),
+            _ => {}
+        }
+        self
+    }
+}
+
+impl Program {
+    /// Copies a node and its children into another program, returning its index there
+    pub fn copy_to(&self, node: NodeIndex, target: &mut Program) -> NodeIndex {
+        let duration = self.duration_of(node);
+        match &self.nodes[node as usize] {
+            Node::Spline { first, count } => target.push_spline(
+                &self.keys[*first as usize..(first + count) as usize],
+                duration,
+            ),
+            Node::Concat { first, count } => {
+                let nodes: Vec<NodeIndex> = self.segments
+                    [*first as usize..(first + count) as usize]
+                    .iter()
+                    .map(|segment| self.copy_to(segment.node, target))
+                    .collect();
+                target.push_concat(&nodes)
+            }
+            Node::Select {
+                index,
+                first,
+                count,
+            } => {
+                let index = self.copy_to(*index, target);
+                let nodes: Vec<NodeIndex> = self.choices[*first as usize..(first + count) as usize]
+                    .iter()
+                    .map(|choice| self.copy_to(*choice, target))
+                    .collect();
+                target.push_select(index, &nodes, duration)
+            }
+            Node::Apply { first, count, func } => {
+                let args: Vec<NodeIndex> = self.operands[*first as usize..(first + count) as usize]
+                    .iter()
+                    .map(|arg| self.copy_to(*arg, target))
+                    .collect();
+                target.push_apply(&args, *func, duration)
+            }
+            Node::Events {
+                first,
+                count,
+                period,
+                output,
+            } => {
+                let output = match output {
+                    EventOutput::Shape(node) => EventOutput::Shape(self.copy_to(*node, target)),
+                    EventOutput::Since => EventOutput::Since,
+                    EventOutput::Index => EventOutput::Index,
+                };
+                let events = &self.events[*first as usize..(first + count) as usize];
+                target.push_events(events, *period, output, duration)
+            }
+            node => {
+                let node = node
+                    .clone()
+                    .map_children(|child| self.copy_to(child, target));
+                target.push(node, duration)
+            }
+        }
+    }
+
+    /// Exports as envelopes that share the program, e.g. for a script loaded without the compiler
+    pub fn envelopes(self) -> HashMap<String, Box<dyn Envelope>> {
+        let program = Rc::new(self);
+        program
+            .outputs
+            .iter()
+            .map(|(name, node)| {
+                let envelope = Lowered {
+                    program: program.clone(),
+                    node: *node,
+                };
+                (name.clone(), Box::new(envelope) as Box<dyn Envelope>)
+            })
+            .collect()
+    }
+}
+
+/// Node of a shared program as an envelope
+struct Lowered {
+    program: Rc<Program>,
+    node: NodeIndex,
+}
+
+impl Envelope for Lowered {
+    fn get_duration(&self) -> Duration {
+        self.program.duration_of(self.node)
+    }
+
+    fn get_value(&self, time: Duration) -> Vector {
+        self.program.eval(self.node, time)
+    }
+
+    fn get_velocity(&self, time: Duration) -> Vector {
+        self.program.velocity(self.node, time)
+    }
+
+    fn lower(&self, program: &mut Program) -> u32 {
+        self.program.copy_to(self.node, program)
+    }
+}
+
+/// Cubic Hermite interpolation between sorted keyframes
+pub fn spline(keys: &[SplineKey], time: Duration) -> Vector {
+    let index = keys.partition_point(|k| k.time <= time);
+    if index == 0 {
+        return match keys.first() {
+            Some(k) => k.value.clone(),
+            None => 0.0.into(),
+        };
+    }
+    if index == keys.len() {
+        return keys[index - 1].value.clone();
+    }
+
+    let a = &keys[index - 1];
+    let b = &keys[index];
+    let h = b.time - a.time;
+    let s = (time - a.time) / h;
+    let s2 = s * s;
+    let s3 = s2 * s;
+
+    &(&a.value.scalar(2.0 * s3 - 3.0 * s2 + 1.0) + &a.out_tangent.scalar(h * (s3 - 2.0 * s2 + s)))
+        + &(&b.value.scalar(-2.0 * s3 + 3.0 * s2) + &b.in_tangent.scalar(h * (s3 - s2)))
+}
+
+/// Derivative of `spline` with respect to time. Zero outside the keyframes.
+pub fn spline_velocity(keys: &[SplineKey], time: Duration) -> Vector {
+    let index = keys.partition_point(|k| k.time <= time);
+    if index == 0 || index == keys.len() {
+        return 0.0.into();
+    }
+
+    let a = &keys[index - 1];
+    let b = &keys[index];
+    let h = b.time - a.time;
+    let s = (time - a.time) / h;
+    let s2 = s * s;
+
+    &(&a.value.scalar((6.0 * s2 - 6.0 * s) / h) + &a.out_tangent.scalar(3.0 * s2 - 4.0 * s + 1.0))
+        + &(&b.value.scalar((-6.0 * s2 + 6.0 * s) / h) + &b.in_tangent.scalar(3.0 * s2 - 2.0 * s))
+}
+
+#[cfg(all(test, feature = "compiler"))]
+fn compare(source: &str, times: &[f64]) {
+    let module = crate::compiler::build(crate::parser::parse(source).unwrap()).unwrap();
+    let program = &module.program;
+    assert_eq!(program.len(), module.exports.len());
+    for (index, name) in program.outputs().enumerate() {
+        let envelope = &module.exports[name];
+        assert_eq!(
+            program.get_duration(index),
+            envelope.get_duration(),
+            "{}",
+            name
+        );
+        for time in times.iter() {
+            assert_eq!(
+                program.get_value(index, *time),
+                envelope.get_value(*time),
+                "{} at {}",
+                name,
+                time
+            );
+            assert_eq!(
+                program.get_velocity(index, *time),
+                envelope.get_velocity(*time),
+                "velocity of {} at {}",
+                name,
+                time
+            );
+        }
+    }
+}
+
+#[cfg(feature = "compiler")]
+#[test]
+fn lowering() {
+    let times: Vec<f64> = (-10..200).map(|i| i as f64 * 0.037).collect();
+    compare(
+        "bpm = 120\n\
+         out a = concat(hold([1, 1], 1), linear([1, 1], [2, 3], 2), ease_in_cubic([0, 0], [1, 1], 1), hold([5, 5], 0))\n\
+         out b = spline([[0, [0, 0]], [1, [1, 2]], [2.5, [0, 0]], [3, [4, 4]]])\n\
+         out c = random(3, 0, 1, 0.25) * noise(2, 1, 3) + fbm(1, 2, 1)\n\
+         out d = at(1, repeat(3, linear(0, 1, 0.5)))\n\
+         out e = loop(concat(linear(0, 1, 1), linear(1, 0, 1))) - every(1b, linear(0, 1, 0.25))\n\
+         out f = clamp(sin(linear(0, 10, 4)), -0.5, 0.5)\n\
+         out g = concat()\n\
+         out h = concat(speed(2, linear(0, 1, 1)), stretch(3, reverse(linear(0, 2, 1))))\n\
+         out i = delay(0.5, pingpong(clip(0.25, 0.75, linear(0, 1, 1))))\n\
+         out j = sample_at(0.3, linear(0, 1, 1)) + speed(0.5, fbm(1, 1, 1))\n\
+         out k = crossfade(linear([0, 0], [1, 1], 2), hold([2, 3], 2), 0.5)\n\
+         out l = select(linear(-1, 3, 4), [hold(1, 1), mul(linear(0, 1, 1), 2), add(noise(1, 1, 1), 1)])\n\
+         out m = select(linear(0, 1, 1), [])\n\
+         out n = events([0.5, 1, 2b], linear(1, 0, 0.5)) + events([1, 3], since) + on_beat(100, [1, 0, 0.5], index)\n\
+         out o = on_beat(140, [0, 1], decay(0.2)) + events([], decay(1)) + on_beat(90, [])\n\
+         out p = concat(linear_color(#ff8800, #2040c0, 1, hsl), linear_color(#2040c0, hsl(90, 0.5, 0.5), 2))\n\
+         out q = smoothstep([0, 1], [1, 0], 0) + ease_in_quad([1, 2], [0, 0], 0)",
+        &times,
+    );
+}
+
+#[cfg(feature = "compiler")]
+#[test]
+fn outputs() {
+    let module = crate::compiler::build(
+        crate::parser::parse("out b = hold(2, 1)\nout a = linear(0, 1, 2)").unwrap(),
+    )
+    .unwrap();
+    let program = module.program;
+    assert_eq!(program.outputs().collect::<Vec<_>>(), vec!["a", "b"]);
+    assert_eq!(program.index_of("b"), Some(1));
+    assert_eq!(program.index_of("c"), None);
+
+    let mut values = vec![Vector::default(); program.len()];
+    program.evaluate(1.0, &mut values);
+    assert_eq!(values[0].to_f(), 0.5);
+    assert_eq!(values[1].to_f(), 2.0);
+}
+
+#[cfg(feature = "compiler")]
+#[test]
+fn large_concat() {
+    let keys: Vec<String> = (0..5000)
+        .map(|i| format!("linear({}, {}, 0.1)", i, i + 1))
+        .collect();
+    let source = format!("out a = concat({})", keys.join(", "));
+    let module = crate::compiler::build(crate::parser::parse(&source).unwrap()).unwrap();
+    let program = module.program;
+    assert!((program.get_duration(0) - 500.0).abs() < 1e-6);
+    assert!((program.get_value(0, 250.05).to_f() - 2500.5).abs() < 1e-6);
+    assert_eq!(program.get_value(0, 1000.0).to_f(), 5000.0);
+}
+
+#[test]
+fn many_operands() {
+    use crate::envelope::{Apply, Linear};
+
+    let cons: Vec<Box<dyn Envelope>> = (0..MAX_ARGS + 2)
+        .map(|i| Box::new(Linear::new(2.0, 0.0.into(), (i as f64).into())) as Box<dyn Envelope>)
+        .collect();
+    let sum = MathFn::new(|x| x.iter().sum(), |_, d| d.iter().sum());
+    let mut exports: HashMap<String, Box<dyn Envelope>> = HashMap::new();
+    exports.insert("a".into(), Box::new(Apply::new(cons, sum)));
+
+    let program = Program::new(&exports);
+    assert_eq!(program.get_value(0, 1.0).to_f(), 7.5);
+    assert_eq!(program.get_velocity(0, 1.0).to_f(), 7.5);
+}
+
+/// Hundreds of exports with thousands of keys in total
+#[cfg(test)]
+fn large_program() -> Program {
+    use crate::envelope::Spline;
+
+    let mut exports: HashMap<String, Box<dyn Envelope>> = HashMap::new();
+    for export in 0..300 {
+        let points = (0..20)
+            .map(|key| (key as f64 * 3.0, Vector::from(((export * key) % 7) as f64)))
+            .collect();
+        exports.insert(
+            format!("x{}", export),
+            Box::new(Spline::catmull_rom(points)),
+        );
+    }
+    Program::new(&exports)
+}
+
+#[test]
+fn large_programs() {
+    let program = large_program();
+    assert_eq!(program.len(), 300);
+    assert_eq!(program.keys.len(), 6000);
+
+    let mut values = vec![Vector::default(); program.len()];
+    program.evaluate(9.0, &mut values);
+    for export in 0..300 {
+        let index = program.index_of(&format!("x{}", export)).unwrap();
+        assert_eq!(values[index].to_f(), ((export * 3) % 7) as f64);
+    }
+}
+
+/// Evaluates a minute at 60 fps, run with `cargo test --release -- --ignored`
+#[test]
+#[ignore]
+fn large_program_speed() {
+    let program = large_program();
+    let mut values = vec![Vector::default(); program.len()];
+    let start = std::time::Instant::now();
+    for frame in 0..3600 {
+        program.evaluate(frame as f64 / 60.0, &mut values);
+    }
+    assert!(start.elapsed() / 3600 < std::time::Duration::from_millis(1));
+}
@@ -0,0 +1,125 @@
+// Deterministic hash based randomness. Results depend only on the inputs, so
+// scripts give exactly the same output on every run and platform.
+
+fn hash(seed: u64, stream: u64, index: i64) -> u64 {
+    // SplitMix64 finalizer over the combined inputs
+    let mut x = seed
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        .wrapping_add(stream.wrapping_mul(0xbf58_476d_1ce4_e5b9))
+        .wrapping_add((index as u64).wrapping_mul(0x94d0_49bb_1331_11eb));
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Uniformly distributed value in range 0..1
+pub fn unit(seed: u64, stream: u64, index: i64) -> f64 {
+    (hash(seed, stream, index) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// 1D gradient noise in range -1..1
+pub fn noise(seed: u64, stream: u64, x: f64) -> f64 {
+    let i = x.floor();
+    let f = x - i;
+    let gradient = |index: f64| unit(seed, stream, index as i64) * 2.0 - 1.0;
+    let a = gradient(i) * f;
+    let b = gradient(i + 1.0) * (f - 1.0);
+    let fade = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    (a + (b - a) * fade) * 2.0
+}
+
+/// Derivative of `noise` with respect to `x`
+pub fn noise_derivative(seed: u64, stream: u64, x: f64) -> f64 {
+    let i = x.floor();
+    let f = x - i;
+    let gradient = |index: f64| unit(seed, stream, index as i64) * 2.0 - 1.0;
+    let (ga, gb) = (gradient(i), gradient(i + 1.0));
+    let a = ga * f;
+    let b = gb * (f - 1.0);
+    let fade = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    let fade_derivative = 30.0 * f * f * (f - 1.0) * (f - 1.0);
+    (ga + (gb - ga) * fade + (b - a) * fade_derivative) * 2.0
+}
+
+/// Fractal sum of noise octaves, each with double frequency and half amplitude
+pub fn fbm(seed: u64, stream: u64, x: f64, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    let mut total = 0.0;
+    for octave in 0..octaves {
+        sum += noise(seed, stream + (octave as u64) * 0x1000, x * frequency) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    if total > 0.0 {
+        sum / total
+    } else {
+        0.0
+    }
+}
+
+/// Derivative of `fbm` with respect to `x`
+pub fn fbm_derivative(seed: u64, stream: u64, x: f64, octaves: u32) -> f64 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    let mut total = 0.0;
+    for octave in 0..octaves {
+        let stream = stream + (octave as u64) * 0x1000;
+        sum += noise_derivative(seed, stream, x * frequency) * frequency * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    if total > 0.0 {
+        sum / total
+    } else {
+        0.0
+    }
+}
+
+#[test]
+fn determinism() {
+    assert_eq!(unit(1, 0, 5), unit(1, 0, 5));
+    assert_ne!(unit(1, 0, 5), unit(2, 0, 5));
+    assert_ne!(unit(1, 0, 5), unit(1, 1, 5));
+    assert_ne!(unit(1, 0, 5), unit(1, 0, 6));
+    assert_eq!(noise(3, 0, 1.2345), noise(3, 0, 1.2345));
+}
+
+#[test]
+fn ranges() {
+    for i in -500..500 {
+        let u = unit(7, 0, i);
+        assert!((0.0..1.0).contains(&u));
+        let n = noise(7, 0, i as f64 * 0.013);
+        assert!((-1.0..=1.0).contains(&n));
+        let n = fbm(7, 0, i as f64 * 0.013, 5);
+        assert!((-1.0..=1.0).contains(&n));
+    }
+    assert_eq!(noise(7, 0, 3.0), 0.0);
+}
+
+#[test]
+fn continuity() {
+    for i in 0..1000 {
+        let x = i as f64 * 0.01;
+        assert!((noise(1, 0, x + 1e-4) - noise(1, 0, x)).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn derivatives() {
+    for i in 0..1000 {
+        let x = i as f64 * 0.0137 + 0.0001;
+        let h = 1e-6;
+        let numeric = (fbm(2, 0, x + h, 3) - fbm(2, 0, x - h, 3)) / (2.0 * h);
+        assert!(
+            (fbm_derivative(2, 0, x, 3) - numeric).abs() < 1e-4,
+            "at {}",
+            x
+        );
+    }
+}
@@ -0,0 +1,187 @@
+// Top level scope that keeps its definitions between inputs, for exploring scripts
+// interactively. Each input is parsed as a script: its definitions are added to the scope
+// and the value of its last statement is returned.
+
+use crate::{
+    ast::*,
+    checker,
+    compiler::{self, Build, BuildError, Env},
+    diagnostics::Diagnostic,
+    envelope::EnvelopeFn,
+    imports::{self, Sources},
+    parser,
+    vector::Vector,
+};
+
+pub struct Session {
+    env: Env,
+    sources: Sources,
+    /// Definitions entered so far, for inferring types of later inputs
+    definitions: Vec<Expr>,
+}
+
+/// Value of the last statement of an input
+#[derive(Debug)]
+pub enum Value {
+    /// The input only defined names
+    Nil,
+    Numbers(Vec<f64>),
+    Envelope {
+        envelope: EnvelopeFn,
+        /// Number of components, 4 if it could not be inferred
+        dimension: usize,
+    },
+}
+
+impl Value {
+    /// Value at `time`. Lists of up to four numbers are constant vectors.
+    pub fn get_value(&self, time: f64) -> Option<Vector> {
+        match self {
+            Self::Envelope { envelope, .. } => Some(envelope.get_value(time)),
+            Self::Numbers(n) if n.len() <= 4 => {
+                let mut vector = Vector::default();
+                vector.0[..n.len()].copy_from_slice(n);
+                Some(vector)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_duration(&self) -> f64 {
+        match self {
+            Self::Envelope { envelope, .. } => envelope.get_duration(),
+            _ => f64::INFINITY,
+        }
+    }
+
+    pub fn dimension(&self) -> usize {
+        match self {
+            Self::Nil => 0,
+            Self::Numbers(n) => n.len(),
+            Self::Envelope { dimension, .. } => *dimension,
+        }
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            env: Env::new(Sources::new()),
+            sources: Sources::new(),
+            definitions: Vec::new(),
+        }
+    }
+
+    /// Evaluates an input without imports
+    pub fn eval(&mut self, source: &str) -> Result<Value, Diagnostic> {
+        self.eval_file("", source, |path| {
+            Err(format!("imports are not available, `{}`", path))
+        })
+    }
+
+    /// Evaluates an input loaded from `path`. Imports are resolved relative to the path
+    /// and loaded with `load`.
+    pub fn eval_file<F>(
+        &mut self,
+        path: &str,
+        source: &str,
+        mut load: F,
+    ) -> Result<Value, Diagnostic>
+    where
+        F: FnMut(&str) -> Result<String, String>,
+    {
+        let mut ast =
+            parser::parse(source).map_err(|err| Diagnostic::from_parse_error(&err, source))?;
+        let root = ast.clone();
+        imports::resolve(&mut ast, path, &mut load)
+            .and_then(|sources| {
+                self.sources.extend(sources);
+                self.env = self.env.with_sources(self.sources.clone());
+                self.run(ast)
+            })
+            .map_err(|err| Diagnostic::from_build_error(&err, &root))
+    }
+
+    fn run(&mut self, ast: Expr) -> Result<Value, BuildError> {
+        let statements = match &ast.kind {
+            ExprKind::List(_, cons) => cons.clone(),
+            _ => Vec::new(),
+        };
+        let value = compiler::compile(ast, &self.env)?;
+
+        self.definitions.extend(
+            statements
+                .iter()
+                .filter(|statement| is_definition(statement))
+                .cloned(),
+        );
+        let last = match statements.last() {
+            Some(last) if !is_definition(last) => last,
+            _ => return Ok(Value::Nil),
+        };
+        match value {
+            Build::NumberList(n) => Ok(Value::Numbers(n)),
+            Build::EnvelopeFn(envelope) => {
+                let root = Expr::list(KW_ROOT, self.definitions.clone());
+                let dimension = checker::infer(&root, &self.sources, last)
+                    .dimension()
+                    .unwrap_or(4);
+                Ok(Value::Envelope {
+                    envelope,
+                    dimension,
+                })
+            }
+            Build::Symbol(name) => Err(BuildError::VariableNotFound(name).at(last.span)),
+            _ => Ok(Value::Nil),
+        }
+    }
+}
+
+fn is_definition(statement: &Expr) -> bool {
+    match &statement.kind {
+        ExprKind::List(keyword, _) => matches!(
+            keyword.as_str(),
+            KW_DEFINE | KW_EXPORT | KW_FUNCTION | KW_MARKER | KW_IMPORT | KW_SCENE
+        ),
+        ExprKind::Comment(_) => true,
+        _ => false,
+    }
+}
+
+#[test]
+fn session() {
+    let mut session = Session::new();
+    assert!(matches!(session.eval("length = 2"), Ok(Value::Nil)));
+    assert!(matches!(
+        session.eval("fade(to) = linear([0, 0], to, length)\nmarker drop = 4"),
+        Ok(Value::Nil)
+    ));
+    match session.eval("[length, drop]") {
+        Ok(Value::Numbers(n)) => assert_eq!(n, vec![2.0, 4.0]),
+        x => panic!("{:?}", x),
+    }
+
+    let value = session
+        .eval("repeat(2, concat(fade([4, 2]), hold([1, 2], 1)))")
+        .unwrap();
+    assert_eq!(value.dimension(), 2);
+    assert_eq!(value.get_duration(), 6.0);
+    assert_eq!(value.get_value(1.0).unwrap().0[0], 2.0);
+    assert_eq!(value.get_value(5.5).unwrap().0, [1.0, 2.0, 0.0, 0.0]);
+
+    // Redefinitions replace earlier ones
+    session.eval("length = 4").unwrap();
+    let value = session.eval("fade([1, 1])").unwrap();
+    assert_eq!(value.get_duration(), 4.0);
+
+    let err = session.eval("fade(size)").unwrap_err();
+    assert_eq!(err.message, "unknown variable `size`");
+    assert!(session.eval("out = ").is_err());
+    assert!(matches!(session.eval("length"), Ok(Value::Numbers(_))));
+}
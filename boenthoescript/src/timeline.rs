@@ -0,0 +1,132 @@
+// Scenes declared with `scene "tunnel" from 12 to 48`. Renderers can be bound to a scene,
+// so that they only run while the scene is playing and get time relative to its start.
+
+type Duration = f64;
+
+/// Named point in time, e.g. `marker drop = 32` for jumping to it while developing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub name: String,
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scene {
+    pub name: String,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl Scene {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    /// Returns true if the scene is playing at `time`. The end is exclusive.
+    pub fn contains(&self, time: Duration) -> bool {
+        time >= self.start && time < self.end
+    }
+
+    /// Time and progress of the scene, if it is playing at `time`
+    pub fn at(&self, time: Duration) -> Option<SceneTime> {
+        if self.contains(time) {
+            Some(SceneTime {
+                time: time - self.start,
+                progress: (time - self.start) / self.duration(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Position in a scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SceneTime {
+    /// Seconds since the start of the scene
+    pub time: Duration,
+    /// Normalized progress from 0 at the start to 1 at the end
+    pub progress: f64,
+}
+
+/// Scenes of a script, ordered by start time. Scenes may overlap.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timeline {
+    scenes: Vec<Scene>,
+}
+
+impl Timeline {
+    pub fn new(mut scenes: Vec<Scene>) -> Self {
+        scenes.sort_by(|a, b| {
+            a.start
+                .total_cmp(&b.start)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        Self { scenes }
+    }
+
+    pub fn scenes(&self) -> &[Scene] {
+        &self.scenes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scenes.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Scene> {
+        self.scenes.iter().find(|scene| scene.name == name)
+    }
+
+    /// Scenes playing at `time`
+    pub fn active(&self, time: Duration) -> impl Iterator<Item = &Scene> {
+        self.scenes.iter().filter(move |scene| scene.contains(time))
+    }
+
+    /// Time and progress of a named scene, if it is playing at `time`
+    pub fn at(&self, name: &str, time: Duration) -> Option<SceneTime> {
+        self.get(name).and_then(|scene| scene.at(time))
+    }
+
+    /// Combines scenes of two timelines, e.g. from different scripts
+    pub fn merge(&self, other: &Timeline) -> Self {
+        Self::new(
+            self.scenes
+                .iter()
+                .chain(other.scenes.iter())
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+#[test]
+fn timeline() {
+    let scene = |name: &str, start: f64, end: f64| Scene {
+        name: name.into(),
+        start,
+        end,
+    };
+    let timeline = Timeline::new(vec![
+        scene("outro", 48.0, 60.0),
+        scene("tunnel", 12.0, 48.0),
+    ]);
+    assert_eq!(timeline.scenes()[0].name, "tunnel");
+    assert_eq!(timeline.get("outro").map(Scene::duration), Some(12.0));
+    assert_eq!(
+        timeline.at("tunnel", 21.0),
+        Some(SceneTime {
+            time: 9.0,
+            progress: 0.25
+        })
+    );
+    assert_eq!(timeline.at("tunnel", 48.0), None);
+    assert_eq!(timeline.at("tunnel", 11.0), None);
+    assert_eq!(timeline.at("credits", 20.0), None);
+
+    let names: Vec<&str> = timeline.active(48.0).map(|s| s.name.as_str()).collect();
+    assert_eq!(names, vec!["outro"]);
+
+    let merged = timeline.merge(&Timeline::new(vec![scene("intro", 0.0, 12.0)]));
+    assert_eq!(merged.scenes().len(), 3);
+    assert_eq!(merged.scenes()[0].name, "intro");
+}
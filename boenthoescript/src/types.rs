@@ -0,0 +1,39 @@
+// Types of values, inferred by the static checks and stored with compiled scripts
+
+use std::fmt;
+
+/// Inferred type of an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    /// List of numbers with the given length
+    Numbers(usize),
+    /// Envelope with the given number of components
+    Envelope(usize),
+    /// Name that is not bound to a value, e.g. an easing
+    Symbol(String),
+    /// Definitions, and values that depend on something that could not be inferred
+    Unknown,
+}
+
+impl Type {
+    /// Number of components of a number list or an envelope
+    pub fn dimension(&self) -> Option<usize> {
+        match self {
+            Self::Numbers(n) | Self::Envelope(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Numbers(1) => write!(f, "a number"),
+            Self::Numbers(n) => write!(f, "a list of {} numbers", n),
+            Self::Envelope(1) => write!(f, "an envelope"),
+            Self::Envelope(n) => write!(f, "an envelope of {} components", n),
+            Self::Symbol(s) => write!(f, "symbol `{}`", s),
+            Self::Unknown => write!(f, "an unknown value"),
+        }
+    }
+}
@@ -1,24 +1,28 @@
 type Value = f64;
 const VECTOR_LENGTH: usize = 4;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct Vector(pub [Value; VECTOR_LENGTH]);
 
 impl Vector {
+    pub fn splat(value: Value) -> Self {
+        Self([value; VECTOR_LENGTH])
+    }
+
     pub fn to_f(&self) -> Value {
-        *self.0.get(0).unwrap_or(&0.0)
+        *self.0.first().unwrap_or(&0.0)
     }
 
     pub fn to_f2(&self) -> (Value, Value) {
         (
-            *self.0.get(0).unwrap_or(&0.0),
+            *self.0.first().unwrap_or(&0.0),
             *self.0.get(1).unwrap_or(&0.0),
         )
     }
 
     pub fn to_f3(&self) -> (Value, Value, Value) {
         (
-            *self.0.get(0).unwrap_or(&0.0),
+            *self.0.first().unwrap_or(&0.0),
             *self.0.get(1).unwrap_or(&0.0),
             *self.0.get(2).unwrap_or(&0.0),
         )
@@ -26,20 +30,31 @@ impl Vector {
 
     pub fn to_f4(&self) -> (Value, Value, Value, Value) {
         (
-            *self.0.get(0).unwrap_or(&0.0),
+            *self.0.first().unwrap_or(&0.0),
             *self.0.get(1).unwrap_or(&0.0),
             *self.0.get(2).unwrap_or(&0.0),
             *self.0.get(3).unwrap_or(&0.0),
         )
     }
 
-    pub fn combine_with(&self, rhs: &Self, func: Box<dyn Fn(Value, Value) -> Value>) -> Self {
+    pub fn combine_with<F>(&self, rhs: &Self, func: F) -> Self
+    where
+        F: Fn(Value, Value) -> Value,
+    {
         let mut result = Self([0.0; VECTOR_LENGTH]);
-        for i in 0..(self.0.len().min(rhs.0.len())) {
-            result.0[i] = func(
-                *self.0.get(i).unwrap_or(&0.0),
-                *rhs.0.get(i).unwrap_or(&0.0),
-            )
+        for i in 0..VECTOR_LENGTH {
+            result.0[i] = func(self.0[i], rhs.0[i]);
+        }
+        result
+    }
+
+    pub fn apply(vectors: &[Vector], func: fn(&[Value]) -> Value) -> Self {
+        let mut result = Self([0.0; VECTOR_LENGTH]);
+        let mut args = Vec::with_capacity(vectors.len());
+        for i in 0..VECTOR_LENGTH {
+            args.clear();
+            args.extend(vectors.iter().map(|v| v.0[i]));
+            result.0[i] = func(&args);
         }
         result
     }
@@ -56,28 +71,28 @@ impl Vector {
 impl std::ops::Add for &Vector {
     type Output = Vector;
     fn add(self, rhs: &Vector) -> Self::Output {
-        self.combine_with(rhs, Box::new(|a, b| a + b))
+        self.combine_with(rhs, |a, b| a + b)
     }
 }
 
 impl std::ops::Sub for &Vector {
     type Output = Vector;
     fn sub(self, rhs: &Vector) -> Self::Output {
-        self.combine_with(rhs, Box::new(|a, b| a - b))
+        self.combine_with(rhs, |a, b| a - b)
     }
 }
 
 impl std::ops::Mul for &Vector {
     type Output = Vector;
     fn mul(self, rhs: &Vector) -> Self::Output {
-        self.combine_with(rhs, Box::new(|a, b| a * b))
+        self.combine_with(rhs, |a, b| a * b)
     }
 }
 
 impl std::ops::Div for &Vector {
     type Output = Vector;
     fn div(self, rhs: &Vector) -> Self::Output {
-        self.combine_with(rhs, Box::new(|a, b| a / b))
+        self.combine_with(rhs, |a, b| a / b)
     }
 }
 
@@ -92,9 +107,8 @@ impl From<Value> for Vector {
 impl From<Vec<Value>> for Vector {
     fn from(a: Vec<Value>) -> Self {
         let mut arr = [0.0; VECTOR_LENGTH];
-        for i in 0..(VECTOR_LENGTH.min(a.len())) {
-            arr[i] = a[i];
-        }
+        let len = VECTOR_LENGTH.min(a.len());
+        arr[..len].copy_from_slice(&a[..len]);
         Vector(arr)
     }
 }
@@ -106,3 +120,13 @@ fn add() {
     assert_eq!(&a + &b, Vector::from(vec![3.0, 5.0, 7.0]));
     assert_eq!((&a * &b).to_f2(), (0.0, 4.0));
 }
+
+#[test]
+fn apply() {
+    let a = Vector::from(vec![1.0, 2.0, 3.0]);
+    let b = Vector::splat(2.0);
+    assert_eq!(
+        Vector::apply(&[a, b], |x| x[0].powf(x[1])),
+        Vector::from(vec![1.0, 4.0, 9.0])
+    );
+}
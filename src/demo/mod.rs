@@ -10,11 +10,14 @@ pub fn init(window: &mut winit::window::Window) -> Result<Engine, EngineError> {
 
     // engine.set_music(include_bytes!("assets/musa.mp3"));
 
+    let timeline = engine.load_script(&Path::new("assets/timeline.boe"), None)?;
+    engine.add_timeline(timeline);
+
     // let buffer = Rc::new(textures::color_buffer(&engine, 1.0));
     let depth_buffer = Rc::new(textures::depth_buffer(&engine));
 
     let test_model = testeffect::TestEffect::new(&engine, depth_buffer.clone(), None)?;
-    engine.add_renderer(Box::new(test_model));
+    engine.add_scene_renderer("bottle", Box::new(test_model));
 
     // let simple = simple::Simple::new(&engine)?;
     // engine.add_renderer(Box::new(simple));
@@ -1,6 +1,15 @@
 use crate::engine::{model, prelude::*, scripts};
 use std::path::Path;
 
+const SCRIPT_PATH: &str = "assets/camerajump.boe";
+
+fn script_contract() -> scripts::Contract {
+    scripts::Contract::new()
+        .require("eye_x", 1)
+        .require("eye_y", 1)
+        .require("eye_z", 1)
+}
+
 pub struct TestEffect {
     model: Box<dyn model::Model>,
     script: scripts::Script,
@@ -23,7 +32,7 @@ impl TestEffect {
                 ..Default::default()
             },
         )?;
-        let script = scripts::build(&engine.load_asset(&Path::new("assets/camerajump.boe")))?;
+        let script = engine.load_script(&Path::new(SCRIPT_PATH), Some(&script_contract()))?;
         let camera = Camera::default();
 
         Ok(Self {
@@ -37,25 +46,27 @@ impl TestEffect {
 }
 
 impl Renderer for TestEffect {
-    fn reload_assets(&mut self, assets: &AssetLibrary) -> Result<(), EngineError> {
-        if let Some(script) = assets.changed("camerajump.boe") {
+    fn reload_assets(&mut self, assets: &mut AssetLibrary) -> Result<(), EngineError> {
+        if self.script.is_changed(assets) {
             println!("TestEffect: reload script");
-            self.script = scripts::build(&script)?;
+            let script = assets.load(&Path::new(SCRIPT_PATH));
+            self.script = scripts::build(assets, &script, Some(&script_contract()))?;
         }
 
         Ok(())
     }
 
+    fn markers(&self) -> Option<(&Path, &[boenthoescript::Marker])> {
+        Some((self.script.path(), self.script.markers()))
+    }
+
     fn update(&mut self, ctx: &mut RenderingContext) {
         let time = ctx.time as f32;
         self.script.set_time(ctx.time);
         self.camera.eye = (
-            time.sin() * 0.2,
-            (time * 0.7).sin() * 0.1 - 0.1,
-            time.cos() * 0.2
-            // self.script.get("eye_x").to_f() as f32,
-            // self.script.get("eye_y").to_f() as f32,
-            // self.script.get("eye_z").to_f() as f32,
+            self.script.get("eye_x").to_f() as f32,
+            self.script.get("eye_y").to_f() as f32,
+            self.script.get("eye_z").to_f() as f32,
         )
             .into();
         // self.camera.target.y = 1.0;
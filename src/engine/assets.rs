@@ -11,9 +11,12 @@ pub enum AssetType {
     GlslVertexShader,
     GlslFragmentShader,
     BoenthoeScript,
+    /// Script compiled into the binary format, see `boenthoescript::Package`
+    CompiledBoenthoeScript,
     PngImage,
     JpegImage,
     GltfModel,
+    RocketTrack,
     Unknown,
 }
 
@@ -53,9 +56,11 @@ impl Asset {
                 "vert" => AssetType::GlslVertexShader,
                 "frag" => AssetType::GlslFragmentShader,
                 "boe" => AssetType::BoenthoeScript,
+                "boeb" => AssetType::CompiledBoenthoeScript,
                 "png" => AssetType::PngImage,
                 "jpg" => AssetType::JpegImage,
                 "gltf" | "glb" => AssetType::GltfModel,
+                "track" => AssetType::RocketTrack,
                 _ => AssetType::Unknown,
             },
             None => AssetType::Unknown,
@@ -126,11 +131,11 @@ impl AssetLibrary {
         }
     }
 
-    /// Load asset from asset path
+    /// Load asset from asset path. Pending assets are read again, e.g. after changes.
     pub fn load(&mut self, path: &Path) -> Rc<Asset> {
         match self.assets.get(&path.to_path_buf()) {
-            Some(asset) => asset.clone(),
-            None => {
+            Some(asset) if !matches!(asset.as_ref(), Asset::Pending { .. }) => asset.clone(),
+            _ => {
                 let path = Path::new(&self.asset_path).join(path);
                 let exact_path = std::fs::canonicalize(&path).unwrap();
                 let relative_path = self.relative_path(&path);
@@ -151,12 +156,46 @@ impl AssetLibrary {
         asset.clone()
     }
 
+    /// Returns true if the asset has been added or exists in asset path
+    pub fn exists(&self, path: &Path) -> bool {
+        self.assets.contains_key(path) || self.asset_path.join(path).is_file()
+    }
+
+    /// Write asset to asset path, e.g. data edited within the demo
+    pub fn save(&mut self, path: &Path, data: &[u8]) -> Result<Rc<Asset>, EngineError> {
+        let full_path = self.asset_path.join(path);
+        let write = || -> std::io::Result<PathBuf> {
+            if let Some(dir) = full_path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(&full_path, data)?;
+            fs::canonicalize(&full_path)
+        };
+        let exact_path = write().map_err(|err| EngineError::AssetSaveError {
+            path: full_path.clone(),
+            message: err.to_string(),
+        })?;
+
+        println!("Save asset {:?}...", path);
+        let asset = Rc::<Asset>::new(Asset::preload(exact_path, data));
+        self.assets.insert(path.to_path_buf(), asset.clone());
+        Ok(asset)
+    }
+
     pub fn asset_dir(&self, asset: &Asset) -> PathBuf {
         let mut path = self.relative_path(asset.path());
         path.pop();
         path
     }
 
+    /// Returns true if any of the files has been changed, e.g. the sources of a script
+    pub fn any_changed(&self, paths: &[PathBuf]) -> bool {
+        paths.iter().any(|path| match path.file_name() {
+            Some(name) => self.changed(&name.to_string_lossy()).is_some(),
+            None => false,
+        })
+    }
+
     pub fn changed(&self, filename: &str) -> Option<Rc<Asset>> {
         for (path, asset) in self.assets.iter() {
             match asset.as_ref() {
@@ -1,5 +1,5 @@
 use crate::engine::*;
-use std::{path::Path, rc::Rc, sync::Mutex};
+use std::{collections::HashMap, path::Path, rc::Rc, sync::Mutex};
 use winit::{event::*, window::Window};
 
 pub struct Engine {
@@ -13,8 +13,15 @@ pub struct Engine {
     pub size: winit::dpi::PhysicalSize<u32>,
     pub timer: timer::Timer,
     pub music: Option<music::Music>,
+    pub rocket: Option<rocket::Rocket>,
 
     renderers: Mutex<Vec<Box<dyn renderer::Renderer>>>,
+    /// Marker times by the script they come from
+    markers: Mutex<HashMap<PathBuf, Vec<f64>>>,
+    /// Scenes of all timeline scripts
+    timeline: Mutex<boenthoescript::Timeline>,
+    timeline_scripts: Mutex<Vec<scripts::Script>>,
+    baked_scripts: Mutex<Vec<scripts::BakedScript>>,
     asset_library: Mutex<assets::AssetLibrary>,
     ext_command_buffers: Mutex<Vec<wgpu::CommandBuffer>>,
 }
@@ -71,8 +78,13 @@ impl Engine {
             size,
             timer: timer::Timer::new(),
             music: None,
+            rocket: None,
 
             renderers: Mutex::new(vec![]),
+            markers: Mutex::new(HashMap::new()),
+            timeline: Mutex::new(Default::default()),
+            timeline_scripts: Mutex::new(vec![]),
+            baked_scripts: Mutex::new(vec![]),
             asset_library: Mutex::new(asset_library),
             ext_command_buffers: Mutex::new(vec![]),
         }
@@ -82,10 +94,49 @@ impl Engine {
         self.music = Some(music::Music::from_bytes(bytes));
     }
 
+    /// Enables Rocket sync tracks, see `RenderingContext::sync`
+    pub fn enable_rocket(&mut self, options: rocket::RocketOptions) {
+        self.rocket = Some(rocket::Rocket::new(options));
+    }
+
     pub fn add_renderer(&self, renderer: Box<dyn renderer::Renderer>) {
+        if let Some((source, markers)) = renderer.markers() {
+            self.set_markers(source, markers);
+        }
         self.renderers.lock().unwrap().push(renderer);
     }
 
+    /// Adds a renderer, which is only rendered while the named scene of the timeline is playing
+    pub fn add_scene_renderer(&self, scene: &str, renderer: Box<dyn renderer::Renderer>) {
+        self.add_renderer(Box::new(renderer::InScene::new(scene, renderer)));
+    }
+
+    /// Adds scenes and markers of a script for the renderers bound to them. They are replaced
+    /// when the script is changed. Starts of the scenes are used as markers.
+    pub fn add_timeline(&self, script: scripts::Script) {
+        self.set_markers(script.path(), script.markers());
+        let mut timelines = self.timeline_scripts.lock().unwrap();
+        timelines.push(script);
+        self.merge_timelines(&timelines);
+    }
+
+    fn merge_timelines(&self, timelines: &[scripts::Script]) {
+        *self.timeline.lock().unwrap() = timelines.iter().fold(
+            Default::default(),
+            |timeline: boenthoescript::Timeline, script| timeline.merge(script.timeline()),
+        );
+    }
+
+    /// Sets time markers (e.g. from `scripts::Script::markers`) to jump between with page
+    /// up/down keys. Replaces the markers previously set for the same source script.
+    pub fn set_markers(&self, source: &Path, markers: &[boenthoescript::Marker]) {
+        let times = markers.iter().map(|marker| marker.time).collect();
+        self.markers
+            .lock()
+            .unwrap()
+            .insert(source.to_path_buf(), times);
+    }
+
     pub fn add_command_buffer(&self, command_buffer: wgpu::CommandBuffer) {
         self.ext_command_buffers
             .lock()
@@ -105,6 +156,36 @@ impl Engine {
         self.asset_library.lock().unwrap().asset_dir(asset)
     }
 
+    /// Loads and builds a script, including its imports. See `scripts::build` for the contract.
+    pub fn load_script(
+        &self,
+        path: &Path,
+        contract: Option<&scripts::Contract>,
+    ) -> Result<scripts::Script, EngineError> {
+        let mut assets = self.asset_library.lock().unwrap();
+        if !assets.exists(path) {
+            return Err(EngineError::AssetLoadError {
+                path: path.to_path_buf(),
+                message: String::from("file not found"),
+            });
+        }
+        let asset = assets.load(path);
+        scripts::build(&mut assets, &asset, contract)
+    }
+
+    /// Bakes exports of a script into a data texture, see `scripts::BakedScript`. The texture
+    /// is baked again when the script changes.
+    pub fn bake_script(
+        &self,
+        path: &Path,
+        bake: scripts::Bake,
+    ) -> Result<Rc<textures::Texture>, EngineError> {
+        let baked = scripts::BakedScript::new(self, path, bake)?;
+        let texture = baked.texture.clone();
+        self.baked_scripts.lock().unwrap().push(baked);
+        Ok(texture)
+    }
+
     pub fn input(&mut self, event: &WindowEvent) -> bool {
         const REWIND_AMOUNT: f64 = 10.0;
 
@@ -128,6 +209,24 @@ impl Engine {
                     return true;
                 }
 
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::PageUp),
+                    ..
+                } => {
+                    self.jump_to_marker(-1);
+                    return true;
+                }
+
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::PageDown),
+                    ..
+                } => {
+                    self.jump_to_marker(1);
+                    return true;
+                }
+
                 _ => {}
             },
             _ => {}
@@ -150,6 +249,7 @@ impl Engine {
         #[cfg(watcher)]
         self.check_changed_files();
         self.process_ext_command_buffers();
+        self.update_rocket();
 
         let frame = self
             .swap_chain
@@ -157,15 +257,23 @@ impl Engine {
             .expect("Timeout getting a frame texture");
 
         let mut renderers = self.renderers.lock().unwrap();
+        let timeline = self.timeline.lock().unwrap();
         let mut context = renderer::RenderingContext {
             device: &self.device,
             queue: &mut self.queue,
             output: &frame.output.view,
             time: self.timer.elapsed(),
             screen_size: &self.size,
+            rocket: self.rocket.as_ref(),
+            timeline: &timeline,
+            scene: None,
         };
 
+        for baked in self.baked_scripts.lock().unwrap().iter_mut() {
+            baked.update(&context);
+        }
         for renderer in renderers.iter_mut() {
+            context.scene = renderer.scene().and_then(|name| context.scene_time(name));
             if renderer.should_render(&context) {
                 renderer.update(&mut context);
                 renderer.render(&mut context);
@@ -177,6 +285,48 @@ impl Engine {
         self.timer.elapsed()
     }
 
+    /// Moves the playback position of the timer and music
+    pub fn seek(&mut self, seconds: f64) {
+        if let Some(music) = self.music.as_mut() {
+            music.set_position(seconds);
+        }
+        self.timer.set(seconds);
+    }
+
+    /// Jumps to the next (1) or previous (-1) marker
+    fn jump_to_marker(&mut self, direction: i32) {
+        // Small tolerance, so that jumping back works right after jumping to a marker
+        const TOLERANCE: f64 = 0.5;
+
+        let now = self.elapsed();
+        let target = {
+            let mut markers: Vec<f64> = self
+                .markers
+                .lock()
+                .unwrap()
+                .values()
+                .flatten()
+                .cloned()
+                .collect();
+            let timeline = self.timeline.lock().unwrap();
+            markers.extend(timeline.scenes().iter().map(|scene| scene.start));
+            markers.sort_by(|a, b| a.total_cmp(b));
+            markers.dedup();
+            if direction > 0 {
+                markers.iter().find(|time| **time > now).cloned()
+            } else {
+                markers
+                    .iter()
+                    .rev()
+                    .find(|time| **time < now - TOLERANCE)
+                    .cloned()
+            }
+        };
+        if let Some(time) = target {
+            self.seek(time);
+        }
+    }
+
     fn forward(&mut self, seconds: f64) {
         if let Some(music) = self.music.as_mut() {
             music.forward(seconds);
@@ -184,15 +334,33 @@ impl Engine {
         self.timer.forward(seconds);
     }
 
+    fn update_rocket(&mut self) {
+        if let Some(rocket) = self.rocket.as_mut() {
+            let mut assets = self.asset_library.lock().unwrap();
+            if let Err(error) = rocket.update(&mut assets, &mut self.timer, self.music.as_mut()) {
+                eprintln!("Error: {}", error);
+            }
+        }
+    }
+
     #[cfg(watcher)]
     fn check_changed_files(&mut self) {
         let mut assets_lock = self.asset_library.try_lock();
         if let Ok(ref mut assets) = assets_lock {
             if assets.detect_changes() {
+                self.reload_timelines(assets);
+                for baked in self.baked_scripts.lock().unwrap().iter_mut() {
+                    if let Err(error) = baked.reload_assets(assets) {
+                        eprintln!("Error: {}", error);
+                    }
+                }
                 let mut renderers = self.renderers.lock().unwrap();
                 for renderer in renderers.iter_mut() {
-                    if let Err(error) = renderer.reload_assets(&assets) {
-                        eprintln!("Error: {:?}", error);
+                    if let Err(error) = renderer.reload_assets(assets) {
+                        eprintln!("Error: {}", error);
+                    }
+                    if let Some((source, markers)) = renderer.markers() {
+                        self.set_markers(source, markers);
                     }
                 }
                 assets.clear_assets();
@@ -200,6 +368,28 @@ impl Engine {
         }
     }
 
+    #[cfg(watcher)]
+    fn reload_timelines(&self, assets: &mut assets::AssetLibrary) {
+        let mut timelines = self.timeline_scripts.lock().unwrap();
+        let mut changed = false;
+        for script in timelines.iter_mut() {
+            if script.is_changed(assets) {
+                let asset = assets.load(script.path());
+                match scripts::build(assets, &asset, None) {
+                    Ok(reloaded) => {
+                        self.set_markers(reloaded.path(), reloaded.markers());
+                        *script = reloaded;
+                        changed = true;
+                    }
+                    Err(error) => eprintln!("Error: {}", error),
+                }
+            }
+        }
+        if changed {
+            self.merge_timelines(&timelines);
+        }
+    }
+
     fn process_ext_command_buffers(&mut self) {
         let buffers = self.ext_command_buffers.get_mut().unwrap();
         if !buffers.is_empty() {
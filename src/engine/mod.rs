@@ -12,6 +12,7 @@ pub mod music;
 pub mod object;
 pub mod pipeline;
 pub mod renderer;
+pub mod rocket;
 pub mod scripts;
 pub mod shaders;
 pub mod textures;
@@ -26,6 +27,8 @@ pub enum EngineError {
     AssetParseError { path: PathBuf, message: String },
     AssetLoadError { path: PathBuf, message: String },
     AssetNotLoaded { path: PathBuf },
+    AssetSaveError { path: PathBuf, message: String },
+    ScriptContractError { path: PathBuf, errors: Vec<String> },
 }
 
 impl EngineError {
@@ -47,6 +50,34 @@ impl EngineError {
     }
 }
 
+impl std::fmt::Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedAssetFormat { path, expected } => write!(
+                f,
+                "{}: unsupported format, expected {}",
+                path.display(),
+                expected
+            ),
+            Self::AssetParseError { path, message } => write!(f, "{}: {}", path.display(), message),
+            Self::AssetLoadError { path, message } => {
+                write!(f, "{}: could not load: {}", path.display(), message)
+            }
+            Self::AssetNotLoaded { path } => write!(f, "{}: not loaded", path.display()),
+            Self::AssetSaveError { path, message } => {
+                write!(f, "{}: could not save: {}", path.display(), message)
+            }
+            Self::ScriptContractError { path, errors } => {
+                write!(f, "{}: exports do not match the contract", path.display())?;
+                for error in errors.iter() {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 pub mod prelude {
     pub type Point3 = cgmath::Point3<f32>;
     pub type Vector2 = cgmath::Vector2<f32>;
@@ -65,6 +96,7 @@ pub mod prelude {
     pub use super::object::Object;
     pub use super::pipeline;
     pub use super::renderer::{Renderer, RenderingContext};
+    pub use super::rocket::Rocket;
     pub use super::shaders;
     pub use super::textures;
     pub use super::textures::Texture;
@@ -1,6 +1,9 @@
 use cpal::traits::{DeviceTrait, HostTrait};
 use minimp3::{Decoder, Error, Frame};
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
 
 pub struct Music {
     buffer: Arc<Vec<i16>>,
@@ -8,6 +11,7 @@ pub struct Music {
     channels: cpal::ChannelCount,
     stream: Option<cpal::Stream>,
     position: Arc<Mutex<usize>>,
+    paused: Arc<AtomicBool>,
 }
 
 #[allow(dead_code)]
@@ -41,6 +45,7 @@ impl Music {
             channels,
             stream: None,
             position: Arc::new(Mutex::new(0)),
+            paused: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -67,12 +72,19 @@ impl Music {
 
         let buffer = self.buffer.clone();
         let position = self.position.clone();
+        let paused = self.paused.clone();
 
         self.stream = Some(
             device
                 .build_output_stream(
                     &supported_config.into(),
                     move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                        if paused.load(Ordering::Relaxed) {
+                            for sample in data.iter_mut() {
+                                *sample = 0.0;
+                            }
+                            return;
+                        }
                         let mut pos = position.lock().unwrap();
                         for sample in data.iter_mut() {
                             let value = if *pos < buffer.len() { buffer[*pos] } else { 0 };
@@ -97,6 +109,11 @@ impl Music {
         *position = (*position as i32 + number_of_samples).max(0) as usize;
     }
 
+    /// Outputs silence without advancing the position while paused
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
     fn seconds_to_samples(&self, seconds: f64) -> i32 {
         (self.sample_rate.0 as f64 * seconds) as i32 * self.channels as i32
     }
@@ -1,22 +1,78 @@
 use crate::engine::prelude::*;
+use boenthoescript::{Marker, SceneTime, Timeline};
 
 pub trait Renderer {
-    fn reload_assets(&mut self, _assets: &AssetLibrary) -> Result<(), EngineError> {
+    fn reload_assets(&mut self, _assets: &mut AssetLibrary) -> Result<(), EngineError> {
         Ok(())
     }
-    fn should_render(&self, _context: &RenderingContext) -> bool {
-        true
+    /// Time markers of the renderer and the script they come from. The engine reads them when
+    /// the renderer is added and after `reload_assets`, see `Engine::set_markers`.
+    fn markers(&self) -> Option<(&Path, &[Marker])> {
+        None
+    }
+    /// Name of the scene the renderer is bound to. Bound renderers are only rendered while
+    /// their scene is playing and `RenderingContext::scene` holds the time in the scene.
+    fn scene(&self) -> Option<&str> {
+        None
+    }
+    fn should_render(&self, context: &RenderingContext) -> bool {
+        self.scene().is_none() || context.scene.is_some()
     }
     fn update(&mut self, context: &mut RenderingContext);
     fn render(&mut self, context: &mut RenderingContext);
 }
 
+/// Binds a renderer to a scene of the timeline, see `Engine::add_scene_renderer`
+pub struct InScene {
+    scene: String,
+    renderer: Box<dyn Renderer>,
+}
+
+impl InScene {
+    pub fn new(scene: &str, renderer: Box<dyn Renderer>) -> Self {
+        Self {
+            scene: String::from(scene),
+            renderer,
+        }
+    }
+}
+
+impl Renderer for InScene {
+    fn reload_assets(&mut self, assets: &mut AssetLibrary) -> Result<(), EngineError> {
+        self.renderer.reload_assets(assets)
+    }
+
+    fn markers(&self) -> Option<(&Path, &[Marker])> {
+        self.renderer.markers()
+    }
+
+    fn scene(&self) -> Option<&str> {
+        Some(&self.scene)
+    }
+
+    fn should_render(&self, context: &RenderingContext) -> bool {
+        context.scene.is_some() && self.renderer.should_render(context)
+    }
+
+    fn update(&mut self, context: &mut RenderingContext) {
+        self.renderer.update(context)
+    }
+
+    fn render(&mut self, context: &mut RenderingContext) {
+        self.renderer.render(context)
+    }
+}
+
 pub struct RenderingContext<'a> {
     pub device: &'a wgpu::Device,
     pub queue: &'a mut wgpu::Queue,
     pub output: &'a wgpu::TextureView,
     pub time: f64,
     pub screen_size: &'a winit::dpi::PhysicalSize<u32>,
+    pub rocket: Option<&'a Rocket>,
+    pub timeline: &'a Timeline,
+    /// Time in the scene of the current renderer, if it is bound to a scene
+    pub scene: Option<SceneTime>,
 }
 
 impl<'a> RenderingContext<'a> {
@@ -28,6 +84,17 @@ impl<'a> RenderingContext<'a> {
     pub fn submit(&self, encoder: wgpu::CommandEncoder) {
         self.queue.submit(std::iter::once(encoder.finish()));
     }
+
+    /// Current value of a Rocket track, or 0 if Rocket is not enabled
+    pub fn sync(&self, track: &str) -> f64 {
+        self.rocket
+            .map_or(0.0, |rocket| rocket.get(track, self.time))
+    }
+
+    /// Time and progress of a scene, if it is playing
+    pub fn scene_time(&self, name: &str) -> Option<SceneTime> {
+        self.timeline.at(name, self.time)
+    }
 }
 
 impl<'a> RenderingContext<'a> {
@@ -0,0 +1,584 @@
+// Client for the GNU Rocket sync tracker (https://github.com/rocket/rocket). In debug
+// builds track keys are edited live in the editor, which also seeks and pauses the demo.
+// Release builds, or debug builds without a running editor, read the tracks saved from
+// the editor as `.track` assets.
+
+use crate::engine::{assets::AssetLibrary, music::Music, timer::Timer, EngineError};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryInto,
+    io::{self, Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+    time::Duration,
+};
+
+const CLIENT_GREETING: &[u8] = b"hello, synctracker!";
+const SERVER_GREETING: &[u8] = b"hello, demo!";
+
+const SET_KEY: u8 = 0;
+const DELETE_KEY: u8 = 1;
+const GET_TRACK: u8 = 2;
+const SET_ROW: u8 = 3;
+const PAUSE: u8 = 4;
+const SAVE_TRACKS: u8 = 5;
+
+pub struct RocketOptions {
+    /// Address of the editor
+    pub address: String,
+    pub bpm: f64,
+    pub rows_per_beat: f64,
+    /// Directory of `.track` files within assets
+    pub tracks_dir: PathBuf,
+}
+
+impl Default for RocketOptions {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:1338".into(),
+            bpm: 120.0,
+            rows_per_beat: 8.0,
+            tracks_dir: PathBuf::from("tracks"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    Smooth,
+    Ramp,
+}
+
+impl Interpolation {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Step),
+            1 => Some(Self::Linear),
+            2 => Some(Self::Smooth),
+            3 => Some(Self::Ramp),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Step => 0,
+            Self::Linear => 1,
+            Self::Smooth => 2,
+            Self::Ramp => 3,
+        }
+    }
+
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            Self::Step => 0.0,
+            Self::Linear => t,
+            Self::Smooth => t * t * (3.0 - 2.0 * t),
+            Self::Ramp => t * t,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Key {
+    pub row: u32,
+    pub value: f32,
+    /// Interpolation from this key to the next one
+    pub interpolation: Interpolation,
+}
+
+/// Keys of a track, sorted by row
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    keys: Vec<Key>,
+}
+
+impl Track {
+    pub fn set_key(&mut self, key: Key) {
+        match self.keys.binary_search_by_key(&key.row, |k| k.row) {
+            Ok(index) => self.keys[index] = key,
+            Err(index) => self.keys.insert(index, key),
+        }
+    }
+
+    pub fn delete_key(&mut self, row: u32) {
+        if let Ok(index) = self.keys.binary_search_by_key(&row, |k| k.row) {
+            self.keys.remove(index);
+        }
+    }
+
+    /// Value at a (fractional) row. Before the first and after the last key the value
+    /// of that key is used.
+    pub fn value(&self, row: f64) -> f64 {
+        let next = self.keys.partition_point(|k| k.row as f64 <= row);
+        if next == 0 {
+            self.keys.first().map_or(0.0, |k| k.value as f64)
+        } else if next == self.keys.len() {
+            self.keys[next - 1].value as f64
+        } else {
+            let a = &self.keys[next - 1];
+            let b = &self.keys[next];
+            let t = (row - a.row as f64) / (b.row - a.row) as f64;
+            let t = a.interpolation.apply(t);
+            a.value as f64 + (b.value - a.value) as f64 * t
+        }
+    }
+
+    /// Reads a track in the `.track` format of the Rocket library
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let count = read_u32_le(data, 0).ok_or("missing key count")? as usize;
+        let mut track = Self::default();
+        for index in 0..count {
+            let offset = 4 + index * 9;
+            let row = read_u32_le(data, offset);
+            let value = read_u32_le(data, offset + 4).map(f32::from_bits);
+            let interpolation = data.get(offset + 8).cloned();
+            match (row, value, interpolation) {
+                (Some(row), Some(value), Some(interpolation)) => track.set_key(Key {
+                    row,
+                    value,
+                    interpolation: Interpolation::from_u8(interpolation)
+                        .ok_or_else(|| format!("unknown key type {}", interpolation))?,
+                }),
+                _ => return Err(format!("expected {} keys, found {}", count, index)),
+            }
+        }
+        Ok(track)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(4 + self.keys.len() * 9);
+        data.extend_from_slice(&(self.keys.len() as u32).to_le_bytes());
+        for key in self.keys.iter() {
+            data.extend_from_slice(&key.row.to_le_bytes());
+            data.extend_from_slice(&key.value.to_bits().to_le_bytes());
+            data.push(key.interpolation.to_u8());
+        }
+        data
+    }
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    SetKey { track: usize, key: Key },
+    DeleteKey { track: usize, row: u32 },
+    SetRow(u32),
+    Pause(bool),
+    SaveTracks,
+}
+
+impl Command {
+    /// Parses a command from the start of `data`. Returns the command and its length
+    /// in bytes, or `None` if the command has not been received completely.
+    fn parse(data: &[u8]) -> io::Result<Option<(Self, usize)>> {
+        let u32_at = |offset: usize| read_u32_be(data, 1 + offset);
+        let command = match data.first() {
+            None => None,
+            Some(&SET_KEY) => match (u32_at(0), u32_at(4), u32_at(8), data.get(13)) {
+                (Some(track), Some(row), Some(value), Some(&interpolation)) => {
+                    let interpolation = Interpolation::from_u8(interpolation).ok_or_else(|| {
+                        invalid_data(format!("unknown key type {}", interpolation))
+                    })?;
+                    let key = Key {
+                        row,
+                        value: f32::from_bits(value),
+                        interpolation,
+                    };
+                    let track = track as usize;
+                    Some((Self::SetKey { track, key }, 14))
+                }
+                _ => None,
+            },
+            Some(&DELETE_KEY) => match (u32_at(0), u32_at(4)) {
+                (Some(track), Some(row)) => {
+                    let track = track as usize;
+                    Some((Self::DeleteKey { track, row }, 9))
+                }
+                _ => None,
+            },
+            Some(&SET_ROW) => u32_at(0).map(|row| (Self::SetRow(row), 5)),
+            Some(&PAUSE) => data.get(1).map(|&flag| (Self::Pause(flag != 0), 2)),
+            Some(&SAVE_TRACKS) => Some((Self::SaveTracks, 1)),
+            Some(command) => return Err(invalid_data(format!("unknown command {}", command))),
+        };
+        Ok(command)
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Connection<S> {
+    stream: S,
+    received: Vec<u8>,
+}
+
+impl Connection<TcpStream> {
+    fn connect(address: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let connection = Self::handshake(stream)?;
+        connection.stream.set_nodelay(true)?;
+        connection.stream.set_nonblocking(true)?;
+        Ok(connection)
+    }
+}
+
+impl<S: Read + Write> Connection<S> {
+    fn handshake(mut stream: S) -> io::Result<Self> {
+        stream.write_all(CLIENT_GREETING)?;
+        let mut greeting = [0; SERVER_GREETING.len()];
+        stream.read_exact(&mut greeting)?;
+        if greeting != SERVER_GREETING {
+            return Err(invalid_data("unexpected greeting".into()));
+        }
+        Ok(Self {
+            stream,
+            received: Vec::new(),
+        })
+    }
+
+    fn get_track(&mut self, name: &str) -> io::Result<()> {
+        let mut message = vec![GET_TRACK];
+        message.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        message.extend_from_slice(name.as_bytes());
+        self.stream.write_all(&message)
+    }
+
+    fn set_row(&mut self, row: u32) -> io::Result<()> {
+        let mut message = vec![SET_ROW];
+        message.extend_from_slice(&row.to_be_bytes());
+        self.stream.write_all(&message)
+    }
+
+    /// Returns commands received so far, without blocking
+    fn poll(&mut self) -> io::Result<Vec<Command>> {
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(length) => self.received.extend_from_slice(&buffer[..length]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut commands = Vec::new();
+        let mut offset = 0;
+        while let Some((command, length)) = Command::parse(&self.received[offset..])? {
+            commands.push(command);
+            offset += length;
+        }
+        self.received.drain(..offset);
+        Ok(commands)
+    }
+}
+
+#[derive(Default)]
+struct Tracks {
+    /// Tracks in the order they were requested, which is also their index in the protocol
+    list: Vec<(String, Track)>,
+    indices: HashMap<String, usize>,
+    /// Tracks from this index on have not been requested or read from files yet
+    pending: usize,
+}
+
+pub struct Rocket {
+    tracks: RefCell<Tracks>,
+    connection: Option<Connection<TcpStream>>,
+    rows_per_second: f64,
+    tracks_dir: PathBuf,
+    row: Option<u32>,
+}
+
+impl Rocket {
+    /// Connects to the editor in debug builds, falling back to saved tracks if it is not running
+    pub fn new(options: RocketOptions) -> Self {
+        let connection = if cfg!(debug_assertions) {
+            match Connection::connect(&options.address) {
+                Ok(connection) => {
+                    println!("Connected to Rocket editor at {}", options.address);
+                    Some(connection)
+                }
+                Err(err) => {
+                    println!(
+                        "Could not connect to Rocket editor at {} ({}), using saved tracks",
+                        options.address, err
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        Self {
+            tracks: RefCell::new(Tracks::default()),
+            connection,
+            rows_per_second: options.bpm / 60.0 * options.rows_per_beat,
+            tracks_dir: options.tracks_dir,
+            row: None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// Value of a track at given time in seconds. Unknown tracks are requested from the
+    /// editor or loaded from assets on the next update, until then their value is 0.
+    pub fn get(&self, name: &str, time: f64) -> f64 {
+        let mut tracks = self.tracks.borrow_mut();
+        match tracks.indices.get(name) {
+            Some(&index) => tracks.list[index].1.value(time * self.rows_per_second),
+            None => {
+                let index = tracks.list.len();
+                tracks.list.push((name.to_string(), Track::default()));
+                tracks.indices.insert(name.to_string(), index);
+                0.0
+            }
+        }
+    }
+
+    /// Requests or loads new tracks and handles the commands from the editor. Seeking and
+    /// pausing in the editor are applied to `timer` and `music`.
+    pub fn update(
+        &mut self,
+        assets: &mut AssetLibrary,
+        timer: &mut Timer,
+        mut music: Option<&mut Music>,
+    ) -> Result<(), EngineError> {
+        self.load_tracks(assets, timer, music.as_deref_mut())?;
+
+        let commands = match self.connection.as_mut().map(|c| c.poll()) {
+            Some(Ok(commands)) => commands,
+            Some(Err(err)) => {
+                self.disconnect(err, timer, music);
+                return Ok(());
+            }
+            None => return Ok(()),
+        };
+
+        for command in commands {
+            match command {
+                Command::SetKey { track, key } => match self.tracks.get_mut().list.get_mut(track) {
+                    Some((_, track)) => track.set_key(key),
+                    None => println!("Rocket editor set a key of unknown track {}", track),
+                },
+                Command::DeleteKey { track, row } => {
+                    match self.tracks.get_mut().list.get_mut(track) {
+                        Some((_, track)) => track.delete_key(row),
+                        None => println!("Rocket editor deleted a key of unknown track {}", track),
+                    }
+                }
+                Command::SetRow(row) => {
+                    let seconds = row as f64 / self.rows_per_second;
+                    timer.set(seconds);
+                    if let Some(music) = music.as_mut() {
+                        music.set_position(seconds);
+                    }
+                    self.row = Some(row);
+                }
+                Command::Pause(paused) => {
+                    if paused {
+                        timer.pause();
+                    } else {
+                        timer.resume();
+                    }
+                    if let Some(music) = music.as_mut() {
+                        music.set_paused(paused);
+                    }
+                }
+                Command::SaveTracks => self.save_tracks(assets)?,
+            }
+        }
+
+        if !timer.is_paused() {
+            let row = (timer.elapsed() * self.rows_per_second) as u32;
+            if self.row != Some(row) {
+                self.row = Some(row);
+                if let Some(Err(err)) = self.connection.as_mut().map(|c| c.set_row(row)) {
+                    self.disconnect(err, timer, music);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn load_tracks(
+        &mut self,
+        assets: &mut AssetLibrary,
+        timer: &mut Timer,
+        music: Option<&mut Music>,
+    ) -> Result<(), EngineError> {
+        if let Err(err) = self.request_tracks() {
+            self.disconnect(err, timer, music);
+        }
+
+        // Without the editor, including tracks that could not be requested before it
+        // disconnected, keys are read from the saved tracks
+        if self.connection.is_none() {
+            let tracks = self.tracks.get_mut();
+            while let Some((name, track)) = tracks.list.get_mut(tracks.pending) {
+                tracks.pending += 1;
+                let path = self.tracks_dir.join(track_file_name(name));
+                if assets.exists(&path) {
+                    let asset = assets.load(&path);
+                    *track = Track::from_bytes(asset.data()?)
+                        .map_err(|err| EngineError::parse_error(&asset, err))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Requests the pending tracks from the editor, which sends their keys as set key commands
+    fn request_tracks(&mut self) -> io::Result<()> {
+        let tracks = self.tracks.get_mut();
+        if let Some(connection) = self.connection.as_mut() {
+            while let Some((name, _)) = tracks.list.get(tracks.pending) {
+                connection.get_track(name)?;
+                tracks.pending += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn save_tracks(&mut self, assets: &mut AssetLibrary) -> Result<(), EngineError> {
+        for (name, track) in self.tracks.get_mut().list.iter() {
+            let path = self.tracks_dir.join(track_file_name(name));
+            assets.save(&path, &track.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Continues playback without the editor, using the keys received so far
+    fn disconnect(&mut self, error: io::Error, timer: &mut Timer, music: Option<&mut Music>) {
+        println!("Rocket editor disconnected ({})", error);
+        self.connection = None;
+        timer.resume();
+        if let Some(music) = music {
+            music.set_paused(false);
+        }
+    }
+}
+
+/// Track names often contain separators like `camera:x`, which are not allowed in file names
+fn track_file_name(name: &str) -> String {
+    let mut file_name = String::new();
+    for byte in name.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'_' | b'-' => {
+                file_name.push(byte as char)
+            }
+            _ => file_name.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    file_name + ".track"
+}
+
+#[test]
+fn track_values() {
+    let mut track = Track::default();
+    assert_eq!(track.value(10.0), 0.0);
+    for (row, value, interpolation) in [
+        (8, 2.0, Interpolation::Linear),
+        (0, 0.0, Interpolation::Step),
+        (4, 1.0, Interpolation::Smooth),
+        (12, 4.0, Interpolation::Ramp),
+        (16, 0.0, Interpolation::Step),
+    ]
+    .iter()
+    {
+        track.set_key(Key {
+            row: *row,
+            value: *value,
+            interpolation: *interpolation,
+        });
+    }
+    assert_eq!(track.value(-1.0), 0.0);
+    assert_eq!(track.value(3.5), 0.0);
+    assert_eq!(track.value(5.0), 1.0 + 0.15625);
+    assert_eq!(track.value(10.0), 3.0);
+    assert_eq!(track.value(14.0), 3.0);
+    assert_eq!(track.value(20.0), 0.0);
+
+    track.delete_key(4);
+    assert_eq!(track.value(6.0), 0.0);
+    assert_eq!(Track::from_bytes(&track.to_bytes()), Ok(track));
+    assert!(Track::from_bytes(&[2, 0, 0, 0, 1, 0, 0, 0]).is_err());
+    assert_eq!(track_file_name("camera:pos.x"), "camera%3Apos.x.track");
+}
+
+#[test]
+fn mock_editor() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    let editor = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut greeting = [0; CLIENT_GREETING.len()];
+        stream.read_exact(&mut greeting).unwrap();
+        assert_eq!(&greeting, CLIENT_GREETING);
+        stream.write_all(SERVER_GREETING).unwrap();
+
+        let mut request = [0; 10];
+        stream.read_exact(&mut request).unwrap();
+        assert_eq!(&request, b"\x02\x00\x00\x00\x05fader");
+
+        let mut commands = vec![SET_KEY, 0, 0, 0, 0, 0, 0, 0, 8];
+        commands.extend_from_slice(&1.5f32.to_bits().to_be_bytes());
+        commands.extend_from_slice(&[1, DELETE_KEY, 0, 0, 0, 0, 0, 0, 0, 4]);
+        commands.extend_from_slice(&[SET_ROW, 0, 0, 1, 0, PAUSE, 1, SAVE_TRACKS]);
+        // Split, so that the client has to wait for the rest of the command
+        stream.write_all(&commands[..6]).unwrap();
+        stream.flush().unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        stream.write_all(&commands[6..]).unwrap();
+
+        let mut row = [0; 5];
+        stream.read_exact(&mut row).unwrap();
+        assert_eq!(row, [SET_ROW, 0, 0, 0, 32]);
+    });
+
+    let mut connection = Connection::connect(&address).unwrap();
+    connection.get_track("fader").unwrap();
+
+    let mut commands = Vec::new();
+    while commands.len() < 5 {
+        commands.extend(connection.poll().unwrap());
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    let key = Key {
+        row: 8,
+        value: 1.5,
+        interpolation: Interpolation::Linear,
+    };
+    assert_eq!(
+        commands,
+        vec![
+            Command::SetKey { track: 0, key },
+            Command::DeleteKey { track: 0, row: 4 },
+            Command::SetRow(256),
+            Command::Pause(true),
+            Command::SaveTracks,
+        ]
+    );
+
+    connection.set_row(32).unwrap();
+    editor.join().unwrap();
+}
@@ -1,44 +1,436 @@
-use crate::engine::{assets::*, EngineError};
-use boenthoescript::{EnvelopeFn, Vector};
-use std::collections::HashMap;
+use crate::engine::{
+    assets::*,
+    engine::Engine,
+    renderer::RenderingContext,
+    textures::{self, Texture},
+    EngineError,
+};
+use boenthoescript::{Marker, Package, Program, Timeline, Type, Vector};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-pub fn build(asset: &Asset) -> Result<Script, EngineError> {
-    if let AssetType::BoenthoeScript = asset.get_type() {
-        boenthoescript::build(asset.to_utf8()?)
-            .or_else(|err| {
-                Err(EngineError::AssetParseError {
-                    path: asset.path().clone(),
-                    message: err,
-                })
-            })
-            .and_then(|functions| Ok(Script::new(functions)))
-    } else {
-        Err(EngineError::unsupported_asset_format(asset, ".boe"))
+/// Builds a script from its source (`.boe`) or from the binary format (`.boeb`).
+/// Imports of a source are loaded from the asset library, relative to the script.
+/// With a contract, the script must define the exports that the contract requires.
+pub fn build(
+    assets: &mut AssetLibrary,
+    asset: &Asset,
+    contract: Option<&Contract>,
+) -> Result<Script, EngineError> {
+    let mut script = load(assets, asset)?;
+    if let Some(contract) = contract {
+        contract
+            .apply(&mut script)
+            .map_err(|errors| EngineError::ScriptContractError {
+                path: asset.path().clone(),
+                errors,
+            })?;
     }
+    Ok(script)
+}
+
+fn load(assets: &mut AssetLibrary, asset: &Asset) -> Result<Script, EngineError> {
+    match asset.get_type() {
+        #[cfg(feature = "script-compiler")]
+        AssetType::BoenthoeScript => compile(assets, asset),
+        #[cfg(not(feature = "script-compiler"))]
+        AssetType::BoenthoeScript => Err(EngineError::parse_error(
+            asset,
+            "the script compiler is disabled, use a compiled .boeb script",
+        )),
+        AssetType::CompiledBoenthoeScript => {
+            let package = Package::from_bytes(asset.data()?)
+                .map_err(|err| EngineError::parse_error(asset, err))?;
+            Ok(Script::new(package, vec![asset.path().clone()]))
+        }
+        _ => Err(EngineError::unsupported_asset_format(
+            asset,
+            ".boe or .boeb",
+        )),
+    }
+}
+
+#[cfg(feature = "script-compiler")]
+fn compile(assets: &mut AssetLibrary, asset: &Asset) -> Result<Script, EngineError> {
+    let mut path = assets.asset_dir(asset);
+    path.push(asset.path().file_name().unwrap_or_default());
+    let path = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let source = asset.to_utf8()?;
+    boenthoescript::build_file(&path, source, |import| {
+        let import = Path::new(import);
+        // `load` expects the file to exist
+        if !assets.exists(import) {
+            return Err(String::from("file not found"));
+        }
+        let import = assets.load(import);
+        let source = import.to_utf8().map_err(|err| err.to_string())?;
+        Ok(String::from(source))
+    })
+    .or_else(|err| {
+        Err(EngineError::AssetParseError {
+            path: asset.path().clone(),
+            message: err,
+        })
+    })
+    .and_then(|module| {
+        for warning in module.warnings.iter() {
+            println!("{}: warning: {}", path, warning.render(source));
+        }
+        let mut sources = vec![PathBuf::from(&path)];
+        sources.extend(module.imports.iter().map(PathBuf::from));
+        Ok(Script::new(Package::from(module), sources))
+    })
 }
 
 pub struct Script {
-    envelopes: HashMap<String, EnvelopeFn>,
-    state: HashMap<String, Vector>,
+    program: Program,
+    markers: Vec<Marker>,
+    timeline: Timeline,
+    signatures: HashMap<String, Type>,
+    /// The script and its imports
+    sources: Vec<PathBuf>,
+    /// Values of the exports, indexed like `program.outputs()`
+    state: Vec<Vector>,
+    time: f64,
+    /// Values of the optional exports of the contract that the script does not define
+    defaults: HashMap<String, Vector>,
     default: Vector,
 }
 
 impl Script {
-    fn new(envelopes: HashMap<String, EnvelopeFn>) -> Self {
+    /// `sources` are the script and its imports
+    fn new(package: Package, sources: Vec<PathBuf>) -> Self {
         Self {
-            envelopes,
-            state: HashMap::new(),
+            state: vec![0.0.into(); package.program.len()],
+            program: package.program,
+            markers: package.markers,
+            timeline: package.timeline,
+            signatures: package.signatures,
+            sources,
+            time: 0.0,
+            defaults: HashMap::new(),
             default: 0.0.into(),
         }
     }
 
+    /// Returns true if the script or any of its imports has been changed
+    pub fn is_changed(&self, assets: &AssetLibrary) -> bool {
+        assets.any_changed(&self.sources)
+    }
+
     pub fn set_time(&mut self, time: f64) {
-        for (name, envelope) in self.envelopes.iter() {
-            self.state.insert(name.clone(), envelope.get_value(time));
-        }
+        self.time = time;
+        self.program.evaluate(time, &mut self.state);
     }
 
+    /// Current value of an export. Exports that the script does not define are zero, or
+    /// their default in the contract of the script.
     pub fn get(&self, key: &str) -> &Vector {
-        self.state.get(key).unwrap_or(&self.default)
+        match self.program.index_of(key) {
+            Some(index) => &self.state[index],
+            None => self.defaults.get(key).unwrap_or(&self.default),
+        }
+    }
+
+    /// Derivative of an export at the current time, e.g. the direction of motion of a position
+    pub fn get_velocity(&self, key: &str) -> Vector {
+        match self.program.index_of(key) {
+            Some(index) => self.program.get_velocity(index, self.time),
+            None => self.default.clone(),
+        }
+    }
+
+    /// Index of an export for `get_index`, which avoids looking the name up every frame
+    pub fn index_of(&self, key: &str) -> Option<usize> {
+        self.program.index_of(key)
+    }
+
+    pub fn get_index(&self, index: usize) -> &Vector {
+        self.state.get(index).unwrap_or(&self.default)
+    }
+
+    /// Exports as GLSL functions `vec4 env_<name>(float t)`, for including in shaders
+    pub fn to_glsl(&self, namespace: &str) -> Result<String, String> {
+        self.program.to_glsl(namespace)
+    }
+
+    /// Samples exports over a time range, one row of RGBA values per export
+    pub fn bake(&self, bake: &Bake) -> Result<Vec<f32>, EngineError> {
+        let mut values = Vec::with_capacity(bake.exports.len() * bake.samples as usize * 4);
+        for name in bake.exports.iter() {
+            let index =
+                self.program
+                    .index_of(name)
+                    .ok_or_else(|| EngineError::AssetParseError {
+                        path: self.sources[0].clone(),
+                        message: format!("unknown export `{}`", name),
+                    })?;
+            for sample in 0..bake.samples {
+                let value = self.program.get_value(index, bake.time(sample));
+                values.extend(value.0.iter().map(|x| *x as f32));
+            }
+        }
+        Ok(values)
+    }
+
+    /// Inferred type of an export, e.g. `Type::Envelope(3)` for a position
+    pub fn signature(&self, key: &str) -> Option<&Type> {
+        self.signatures.get(key)
+    }
+
+    /// Path of the script, without its imports
+    pub fn path(&self) -> &Path {
+        &self.sources[0]
+    }
+
+    /// The script and its imports
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    /// Named time markers of the script, ordered by time
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+
+    /// Returns time of a marker in seconds
+    pub fn marker(&self, name: &str) -> Option<f64> {
+        self.markers
+            .iter()
+            .find(|marker| marker.name == name)
+            .map(|marker| marker.time)
+    }
+
+    /// Scenes of the script, see `Engine::add_timeline`
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+}
+
+/// Exports that a renderer requires from its script, checked by `build` when the script is
+/// loaded and reloaded, e.g. `Contract::new().require("eye", 3).optional("fov", 1, 45.0)`
+#[derive(Debug, Clone, Default)]
+pub struct Contract {
+    exports: Vec<Requirement>,
+}
+
+#[derive(Debug, Clone)]
+struct Requirement {
+    name: String,
+    dimension: usize,
+    /// Value of an optional export that the script does not define
+    default: Option<Vector>,
+}
+
+impl Contract {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Export with `dimension` components that the script must define
+    pub fn require(mut self, name: &str, dimension: usize) -> Self {
+        self.exports.push(Requirement {
+            name: String::from(name),
+            dimension,
+            default: None,
+        });
+        self
+    }
+
+    /// Export with `dimension` components that is `default` if the script does not define it
+    pub fn optional<V>(mut self, name: &str, dimension: usize, default: V) -> Self
+    where
+        V: Into<Vector>,
+    {
+        self.exports.push(Requirement {
+            name: String::from(name),
+            dimension,
+            default: Some(default.into()),
+        });
+        self
+    }
+
+    /// Checks the exports of the script and sets the defaults of the optional exports that
+    /// it does not define. Returns all missing and mismatched exports. Exports whose type
+    /// could not be inferred, e.g. values from imports the checker cannot see into, are
+    /// accepted with any dimension.
+    fn apply(&self, script: &mut Script) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        let mut defaults = HashMap::new();
+        for export in self.exports.iter() {
+            if script.index_of(&export.name).is_none() {
+                match &export.default {
+                    Some(default) => {
+                        defaults.insert(export.name.clone(), default.clone());
+                    }
+                    None => errors.push(format!(
+                        "missing export `{}` of {}",
+                        export.name,
+                        components(export.dimension)
+                    )),
+                }
+                continue;
+            }
+            match script.signature(&export.name).and_then(Type::dimension) {
+                Some(dimension) if dimension != export.dimension => errors.push(format!(
+                    "export `{}` has {}, expected {}",
+                    export.name,
+                    components(dimension),
+                    components(export.dimension)
+                )),
+                _ => {}
+            }
+        }
+        if errors.is_empty() {
+            script.defaults = defaults;
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn components(dimension: usize) -> String {
+    match dimension {
+        1 => String::from("1 component"),
+        n => format!("{} components", n),
+    }
+}
+
+/// Exports and time range to bake, see `Script::bake`
+#[derive(Debug, Clone)]
+pub struct Bake {
+    pub exports: Vec<String>,
+    pub from: f64,
+    pub to: f64,
+    /// Samples per export. The first one is at `from` and the last one at `to`.
+    pub samples: u32,
+}
+
+impl Bake {
+    /// Time of a sample
+    pub fn time(&self, sample: u32) -> f64 {
+        if self.samples > 1 {
+            self.from + (self.to - self.from) * sample as f64 / (self.samples - 1) as f64
+        } else {
+            self.from
+        }
+    }
+}
+
+/// Exports of a script baked into an RGBA32F texture, one row per export, for effects that
+/// evaluate the same curves thousands of times per frame. Row `i` holds `bake.exports[i]` and
+/// the texture is baked again when the script changes, see `Engine::bake_script`.
+pub struct BakedScript {
+    path: PathBuf,
+    script: Script,
+    bake: Bake,
+    data: wgpu::Texture,
+    /// Values baked from a reloaded script, written on the next update
+    pending: Option<Vec<f32>>,
+    pub texture: Rc<Texture>,
+}
+
+impl BakedScript {
+    pub fn new(engine: &Engine, path: &Path, bake: Bake) -> Result<Self, EngineError> {
+        let script = engine.load_script(path, None)?;
+        if bake.exports.is_empty() || bake.samples == 0 {
+            return Err(EngineError::AssetParseError {
+                path: path.to_path_buf(),
+                message: String::from("nothing to bake"),
+            });
+        }
+        let too_large = |n: usize| n > textures::MAX_DIMENSION_2D as usize;
+        if too_large(bake.samples as usize) || too_large(bake.exports.len()) {
+            return Err(EngineError::AssetParseError {
+                path: path.to_path_buf(),
+                message: format!(
+                    "{} samples of {} exports exceed the maximum texture size of {}",
+                    bake.samples,
+                    bake.exports.len(),
+                    textures::MAX_DIMENSION_2D
+                ),
+            });
+        }
+        let values = script.bake(&bake)?;
+        let (width, height) = (bake.samples, bake.exports.len() as u32);
+        let (data, texture) = textures::data(engine, width, height);
+        textures::write_data(&engine.queue, &data, width, height, &values);
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            script,
+            bake,
+            data,
+            pending: None,
+            texture: Rc::new(texture),
+        })
     }
+
+    /// Rebuilds and bakes the script if it or its imports have changed. On errors the
+    /// previous values are kept.
+    pub fn reload_assets(&mut self, assets: &mut AssetLibrary) -> Result<(), EngineError> {
+        if self.script.is_changed(assets) {
+            let asset = assets.load(&self.path);
+            let script = build(assets, &asset, None)?;
+            self.pending = Some(script.bake(&self.bake)?);
+            self.script = script;
+        }
+        Ok(())
+    }
+
+    /// Writes the values baked on reload into the texture
+    pub fn update(&mut self, context: &RenderingContext) {
+        if let Some(values) = self.pending.take() {
+            let (width, height) = (self.bake.samples, self.bake.exports.len() as u32);
+            textures::write_data(context.queue, &self.data, width, height, &values);
+        }
+    }
+
+    pub fn script(&self) -> &Script {
+        &self.script
+    }
+
+    pub fn bake(&self) -> &Bake {
+        &self.bake
+    }
+}
+
+#[cfg(feature = "script-compiler")]
+#[test]
+fn contracts() {
+    let build = |source: &str| {
+        let module = boenthoescript::build_file("test.boe", source, |_| Err("no imports".into()));
+        Script::new(
+            Package::from(module.unwrap()),
+            vec![PathBuf::from("test.boe")],
+        )
+    };
+    let contract = Contract::new().require("eye", 3).optional("fov", 1, 45.0);
+
+    let mut script = build("out eye = hold([0, 1, 2], 1)");
+    assert_eq!(contract.apply(&mut script), Ok(()));
+    assert_eq!(script.get("fov").to_f(), 45.0);
+
+    let mut script = build("out fov = hold([60, 0], 1)");
+    assert_eq!(
+        contract.apply(&mut script),
+        Err(vec![
+            String::from("missing export `eye` of 3 components"),
+            String::from("export `fov` has 2 components, expected 1 component"),
+        ])
+    );
+
+    // Without a signature the dimension cannot be checked
+    let mut script = build("out eye = hold([0, 1], 1)");
+    script.signatures.clear();
+    assert_eq!(contract.apply(&mut script), Ok(()));
 }
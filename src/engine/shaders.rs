@@ -1,15 +1,50 @@
 use crate::engine::prelude::*;
+use std::cell::RefCell;
 
 #[derive(Debug, Default)]
 pub struct ShaderBuildOptions<'a> {
     pub macro_flags: &'a [&'a str],
 }
 
+/// Prefix of virtual includes that define the exports of a script as GLSL functions,
+/// e.g. `#include "script:camerajump"` defines `vec4 env_eye_x(float t)`
+const SCRIPT_INCLUDE: &str = "script:";
+
+/// Extension of scripts included in shaders, relative to the shader
+#[cfg(feature = "script-compiler")]
+const SCRIPT_EXTENSION: &str = "boe";
+#[cfg(not(feature = "script-compiler"))]
+const SCRIPT_EXTENSION: &str = "boeb";
+
+/// Shader module and the files it was built from
+pub struct Shader {
+    pub module: wgpu::ShaderModule,
+    /// The shader, its includes and the scripts it includes with their imports
+    sources: Vec<PathBuf>,
+}
+
+impl Shader {
+    /// Returns true if the shader or any file it includes has been changed, so that it
+    /// should be built again
+    pub fn is_changed(&self, assets: &AssetLibrary) -> bool {
+        assets.any_changed(&self.sources)
+    }
+}
+
 pub fn build(
     engine: &Engine,
     asset: &Asset,
     options: Option<&ShaderBuildOptions>,
 ) -> Result<wgpu::ShaderModule, EngineError> {
+    build_shader(engine, asset, options).map(|shader| shader.module)
+}
+
+/// Builds a shader and keeps track of its includes, see `Shader::is_changed`
+pub fn build_shader(
+    engine: &Engine,
+    asset: &Asset,
+    options: Option<&ShaderBuildOptions>,
+) -> Result<Shader, EngineError> {
     let kind = match asset.get_type() {
         AssetType::GlslVertexShader => shaderc::ShaderKind::Vertex,
         AssetType::GlslFragmentShader => shaderc::ShaderKind::Fragment,
@@ -24,18 +59,24 @@ pub fn build(
     let path = asset.path();
     let glsl = asset.to_utf8()?;
 
-    compile_into_spirv(
+    let sources = RefCell::new(vec![path.clone()]);
+    let module = compile_into_spirv(
         engine,
         glsl,
         path,
         kind,
         options.unwrap_or(&ShaderBuildOptions::default()),
+        &sources,
     )
     .or_else(|error| {
         Err(EngineError::AssetParseError {
             path: path.clone(),
             message: error,
         })
+    })?;
+    Ok(Shader {
+        module,
+        sources: sources.into_inner(),
     })
 }
 
@@ -45,6 +86,7 @@ fn compile_into_spirv(
     path: &PathBuf,
     kind: shaderc::ShaderKind,
     build_options: &ShaderBuildOptions,
+    sources: &RefCell<Vec<PathBuf>>,
 ) -> Result<wgpu::ShaderModule, String> {
     // Acquire compiler
     let mut compiler = match shaderc::Compiler::new() {
@@ -62,7 +104,11 @@ fn compile_into_spirv(
         // TODO: Get rid of this ugly path mangling and implement load_child_asset()
         let mut path = path.clone();
         path.pop();
+        if let Some(name) = filename.strip_prefix(SCRIPT_INCLUDE) {
+            return include_script(engine, &path, name, sources);
+        }
         let asset = engine.load_asset(&path.join(filename));
+        sources.borrow_mut().push(asset.path().clone());
         Ok(shaderc::ResolvedInclude {
             content: asset
                 .to_utf8()
@@ -92,3 +138,20 @@ fn compile_into_spirv(
     let shader_data = wgpu::util::make_spirv(spirv.as_binary_u8());
     Ok(engine.device.create_shader_module(shader_data))
 }
+
+fn include_script(
+    engine: &Engine,
+    dir: &PathBuf,
+    name: &str,
+    sources: &RefCell<Vec<PathBuf>>,
+) -> Result<shaderc::ResolvedInclude, String> {
+    let path = dir.join(name).with_extension(SCRIPT_EXTENSION);
+    let script = engine
+        .load_script(&path, None)
+        .or_else(|error| Err(format!("{}", error)))?;
+    sources.borrow_mut().extend_from_slice(script.sources());
+    Ok(shaderc::ResolvedInclude {
+        content: script.to_glsl(name)?,
+        resolved_name: format!("{}{}", SCRIPT_INCLUDE, name),
+    })
+}
@@ -4,6 +4,11 @@ use image::GenericImageView;
 use wgpu::util::DeviceExt;
 
 pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+/// Format of data textures, see `data`
+pub const DATA_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+/// Largest width and height of 2D textures that every device supports. wgpu does not report
+/// the limit of the device.
+pub const MAX_DIMENSION_2D: u32 = 8192;
 
 #[derive(Debug)]
 pub struct Texture {
@@ -72,6 +77,82 @@ pub fn buffer(engine: &engine::Engine, format: wgpu::TextureFormat, scale: f32)
     }
 }
 
+/// Texture for RGBA32F data, e.g. curves baked from a script. It is visible in vertex and
+/// fragment shaders. Float textures are not filterable, so the sampler takes the nearest texel.
+/// Returns also the texture itself for `write_data`.
+pub fn data(engine: &engine::Engine, width: u32, height: u32) -> (wgpu::Texture, Texture) {
+    let device = &engine.device;
+    let texture = device.create_texture(&default_texture_descriptor(width, height, DATA_FORMAT));
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Nearest,
+        ..default_sampler_descriptor()
+    });
+    let visibility = wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT;
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility,
+                ty: wgpu::BindingType::SampledTexture {
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D2,
+                    component_type: wgpu::TextureComponentType::Float,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility,
+                ty: wgpu::BindingType::Sampler { comparison: false },
+                count: None,
+            },
+        ],
+        label: None,
+    });
+    let bind_group = create_bind_group(device, &bind_group_layout, &view, &sampler);
+
+    (
+        texture,
+        Texture {
+            bind_group_layout,
+            bind_group,
+            sampler,
+            view,
+        },
+    )
+}
+
+/// Writes rows of RGBA values into a texture created with `data`
+pub fn write_data(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    width: u32,
+    height: u32,
+    values: &[f32],
+) {
+    queue.write_texture(
+        wgpu::TextureCopyView {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        bytemuck::cast_slice(values),
+        wgpu::TextureDataLayout {
+            offset: 0,
+            bytes_per_row: 4 * std::mem::size_of::<f32>() as u32 * width,
+            rows_per_image: height,
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        },
+    );
+}
+
 fn load_image(asset: &assets::Asset) -> Result<image::DynamicImage, EngineError> {
     let mut image = image::load_from_memory_with_format(asset.data()?, get_image_format(asset)?)
         .or_else(|err| {
@@ -3,6 +3,7 @@ use std::time::Instant;
 pub struct Timer {
     start_time: Instant,
     adjust: f64,
+    paused_at: Option<f64>,
 }
 
 #[allow(dead_code)]
@@ -11,6 +12,7 @@ impl Timer {
         Self {
             start_time: Instant::now(),
             adjust: 0.0,
+            paused_at: None,
         }
     }
 
@@ -20,14 +22,41 @@ impl Timer {
 
     /// Returns elapsed time in seconds
     pub fn elapsed(&self) -> f64 {
-        self.true_elapsed() + self.adjust
+        match self.paused_at {
+            Some(seconds) => seconds,
+            None => self.true_elapsed() + self.adjust,
+        }
     }
 
     pub fn true_elapsed(&self) -> f64 {
         self.start_time.elapsed().as_millis() as f64 * 0.001
     }
 
+    pub fn set(&mut self, seconds: f64) {
+        self.adjust = seconds.max(0.0) - self.true_elapsed();
+        if self.paused_at.is_some() {
+            self.paused_at = Some(seconds.max(0.0));
+        }
+    }
+
     pub fn forward(&mut self, seconds: f64) {
-        self.adjust = (self.adjust + seconds).max(-self.true_elapsed())
+        self.set(self.elapsed() + seconds);
+    }
+
+    /// Stops the time at current position until `resume` is called
+    pub fn pause(&mut self) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(self.elapsed());
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(seconds) = self.paused_at.take() {
+            self.set(seconds);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
     }
 }